@@ -5,6 +5,7 @@ pub enum ErrorType {
     Bluez,
     CoreBluetooth,
     Usb,
+    Sim,
 }
 
 impl From<ErrorType> for &'static str {
@@ -13,6 +14,7 @@ impl From<ErrorType> for &'static str {
             ErrorType::Bluez => "Bluez",
             ErrorType::CoreBluetooth => "CoreBluetooth",
             ErrorType::Usb => "USB",
+            ErrorType::Sim => "Sim",
         }
     }
 }
@@ -35,7 +37,11 @@ pub struct Error {
 }
 
 impl Error {
-    pub fn new<T: Into<String>>(name: T, description: T, error_type: ErrorType) -> Self {
+    pub fn new<N: Into<String>, D: Into<String>>(
+        name: N,
+        description: D,
+        error_type: ErrorType,
+    ) -> Self {
         let name: String = name.into();
         let description: String = description.into();
         let combined_description = format!("{}: {}", name, description);
@@ -0,0 +1,501 @@
+use std::{error, fmt};
+use uuid::Uuid;
+
+use super::{
+    characteristic::{self, Characteristic},
+    descriptor::{self, Descriptor},
+    event::{EventSender, LongValue},
+    handle::CharacteristicHandle,
+    handler::{self, CharacteristicHandler, DescriptorHandler, Server},
+    security::{Security, SecurityLevel},
+    service::Service,
+};
+
+/// Why a builder refused to build a service, characteristic or descriptor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// Reads, writes or subscriptions were enabled without an event sender to deliver them to.
+    MissingEventSender(Uuid),
+    /// A characteristic was made writable both with and without response.
+    ConflictingWrite(Uuid),
+    /// A fixed value was given to an attribute that can also be written.
+    WritableFixedValue(Uuid),
+    /// The attribute can neither be read, written nor subscribed to, and has no fixed value.
+    Inaccessible(Uuid),
+    /// Authorization was required for a fixed value, which the platform serves without asking.
+    AuthorizedFixedValue(Uuid),
+    /// Writes without response or notifications were to be acquired, but are not enabled.
+    NothingToAcquire(Uuid),
+    /// Authorization was required for a value kept by a `CharacteristicHandle`, which has no
+    /// one to ask.
+    AuthorizedManagedValue(Uuid),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::MissingEventSender(uuid) => {
+                write!(f, "{} needs an event sender for its requests", uuid)
+            }
+            BuildError::ConflictingWrite(uuid) => write!(
+                f,
+                "{} cannot be written both with and without response",
+                uuid
+            ),
+            BuildError::WritableFixedValue(uuid) => {
+                write!(f, "{} has a fixed value but can be written", uuid)
+            }
+            BuildError::Inaccessible(uuid) => write!(f, "{} has no value and no access", uuid),
+            BuildError::AuthorizedFixedValue(uuid) => {
+                write!(f, "{} has a fixed value but requires authorization", uuid)
+            }
+            BuildError::NothingToAcquire(uuid) => write!(
+                f,
+                "{} can only acquire writes without response and notifications it has",
+                uuid
+            ),
+            BuildError::AuthorizedManagedValue(uuid) => {
+                write!(f, "{} has a managed value but requires authorization", uuid)
+            }
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+/// Builds a `Service`, see `Service::builder`.
+#[derive(Debug)]
+pub struct ServiceBuilder {
+    uuid: Uuid,
+    primary: bool,
+    characteristics: Vec<CharacteristicBuilder>,
+}
+
+impl ServiceBuilder {
+    pub(crate) fn new(uuid: Uuid) -> Self {
+        ServiceBuilder {
+            uuid,
+            primary: false,
+            characteristics: vec![],
+        }
+    }
+
+    pub fn primary(mut self) -> Self {
+        self.primary = true;
+        self
+    }
+
+    /// Appends a characteristic, configured by `build`.
+    pub fn characteristic<F>(mut self, uuid: Uuid, build: F) -> Self
+    where
+        F: FnOnce(CharacteristicBuilder) -> CharacteristicBuilder,
+    {
+        self.characteristics
+            .push(build(CharacteristicBuilder::new(uuid)));
+        self
+    }
+
+    pub fn build(self) -> Result<Service, BuildError> {
+        let characteristics = self
+            .characteristics
+            .into_iter()
+            .map(CharacteristicBuilder::build)
+            .collect::<Result<_, _>>()?;
+        Ok(Service::new(self.uuid, self.primary, characteristics))
+    }
+}
+
+/// Builds a `Characteristic`, see `Characteristic::builder`.
+#[derive(Debug)]
+pub struct CharacteristicBuilder {
+    uuid: Uuid,
+    sender: Option<EventSender>,
+    server: Option<Server>,
+    managed: bool,
+    read: Option<SecurityLevel>,
+    write: Option<SecurityLevel>,
+    write_without_response: bool,
+    notify: Option<SecurityLevel>,
+    indicate: Option<SecurityLevel>,
+    authorize: bool,
+    acquire_write: bool,
+    acquire_notify: bool,
+    long_values: bool,
+    value: Option<Vec<u8>>,
+    descriptors: Vec<DescriptorBuilder>,
+}
+
+impl CharacteristicBuilder {
+    pub(crate) fn new(uuid: Uuid) -> Self {
+        CharacteristicBuilder {
+            uuid,
+            sender: None,
+            server: None,
+            managed: false,
+            read: None,
+            write: None,
+            write_without_response: false,
+            notify: None,
+            indicate: None,
+            authorize: false,
+            acquire_write: false,
+            acquire_notify: false,
+            long_values: false,
+            value: None,
+            descriptors: vec![],
+        }
+    }
+
+    /// Where read, write and subscription requests are delivered; descriptors without a sender
+    /// of their own use it too.
+    pub fn sender(mut self, sender: EventSender) -> Self {
+        self.sender = Some(sender);
+        self.server = None;
+        self.managed = false;
+        self
+    }
+
+    /// Lets the library answer requests from the value kept by `handle`, once the service is
+    /// added to a peripheral. Descriptors need a sender of their own, as the handle only keeps
+    /// the characteristic's value.
+    pub fn managed(self, handle: &CharacteristicHandle) -> Self {
+        let (sender, server) = handle.server();
+        let mut builder = self.served_by(sender, server);
+        builder.managed = true;
+        builder
+    }
+
+    /// Serves requests by calling `handler` instead of sending events, once the service is
+    /// added to a peripheral.
+    pub fn handler<H: CharacteristicHandler>(self, handler: H) -> Self {
+        let (sender, server) = handler::characteristic_server(handler);
+        self.served_by(sender, server)
+    }
+
+    fn served_by(mut self, sender: EventSender, server: Server) -> Self {
+        self.sender = Some(sender);
+        self.server = Some(server);
+        self.managed = false;
+        self
+    }
+
+    pub fn read(self) -> Self {
+        self.read_with(SecurityLevel::None)
+    }
+
+    /// Allows reads over a link secured with LE Secure Connections only, like `Secure::Secure`.
+    pub fn secure_read(self) -> Self {
+        self.read_with(SecurityLevel::SecureConnections)
+    }
+
+    /// Allows reads over a link secured at `level` or above.
+    pub fn read_with(mut self, level: SecurityLevel) -> Self {
+        self.read = Some(level);
+        self
+    }
+
+    pub fn write(self) -> Self {
+        self.write_with(SecurityLevel::None)
+    }
+
+    /// Allows writes over a link secured with LE Secure Connections only, like `Secure::Secure`.
+    pub fn secure_write(self) -> Self {
+        self.write_with(SecurityLevel::SecureConnections)
+    }
+
+    /// Allows writes over a link secured at `level` or above.
+    pub fn write_with(mut self, level: SecurityLevel) -> Self {
+        self.write = Some(level);
+        self
+    }
+
+    pub fn write_without_response(mut self) -> Self {
+        self.write_without_response = true;
+        self
+    }
+
+    pub fn notify(self) -> Self {
+        self.notify_with(SecurityLevel::None)
+    }
+
+    /// Allows subscriptions to notifications over a link secured at `level` or above.
+    pub fn notify_with(mut self, level: SecurityLevel) -> Self {
+        self.notify = Some(level);
+        self
+    }
+
+    pub fn indicate(self) -> Self {
+        self.indicate_with(SecurityLevel::None)
+    }
+
+    /// Allows subscriptions to indications over a link secured at `level` or above.
+    pub fn indicate_with(mut self, level: SecurityLevel) -> Self {
+        self.indicate = Some(level);
+        self
+    }
+
+    /// Sends an `AuthorizeRequest` before every read or write, which only go ahead if it is
+    /// allowed.
+    pub fn authorize(mut self) -> Self {
+        self.authorize = true;
+        self
+    }
+
+    /// Has the library take care of offsets: read requests always ask for the whole value,
+    /// which is sliced for the central, and write requests always carry it, with anything
+    /// written at an offset spliced into the value read from the handler. Writes at an offset
+    /// drop whatever followed the written part, and writes to an attribute that cannot be read
+    /// are spliced into the value last written.
+    pub fn long_values(mut self) -> Self {
+        self.long_values = true;
+        self
+    }
+
+    /// Lets BlueZ pass write commands through a socket instead of calling `WriteValue` for
+    /// each; they arrive as an `AcquireWrite` event. Needs `write_without_response`.
+    pub fn acquire_write(mut self) -> Self {
+        self.acquire_write = true;
+        self
+    }
+
+    /// Lets BlueZ take notifications from a socket instead of a signal each; subscriptions
+    /// arrive as `AcquireNotify` events. Needs `notify`.
+    pub fn acquire_notify(mut self) -> Self {
+        self.acquire_notify = true;
+        self
+    }
+
+    /// A fixed value, served without asking the event sender.
+    pub fn value<T: Into<Vec<u8>>>(mut self, value: T) -> Self {
+        self.value = Some(value.into());
+        self
+    }
+
+    /// Appends a descriptor, configured by `build`.
+    pub fn descriptor<F>(mut self, uuid: Uuid, build: F) -> Self
+    where
+        F: FnOnce(DescriptorBuilder) -> DescriptorBuilder,
+    {
+        self.descriptors.push(build(DescriptorBuilder::new(uuid)));
+        self
+    }
+
+    pub fn build(self) -> Result<Characteristic, BuildError> {
+        let uuid = self.uuid;
+        let writable = self.write.is_some() || self.write_without_response;
+        if self.write.is_some() && self.write_without_response {
+            return Err(BuildError::ConflictingWrite(uuid));
+        }
+        if self.value.is_some() && writable {
+            return Err(BuildError::WritableFixedValue(uuid));
+        }
+        if self.value.is_some() && self.authorize {
+            return Err(BuildError::AuthorizedFixedValue(uuid));
+        }
+        if self.managed && self.authorize {
+            return Err(BuildError::AuthorizedManagedValue(uuid));
+        }
+        if (self.acquire_write && !self.write_without_response)
+            || (self.acquire_notify && self.notify.is_none())
+        {
+            return Err(BuildError::NothingToAcquire(uuid));
+        }
+        let accessible =
+            self.read.is_some() || writable || self.notify.is_some() || self.indicate.is_some();
+        if !accessible && self.value.is_none() {
+            return Err(BuildError::Inaccessible(uuid));
+        }
+        let sender = match self.sender {
+            Some(sender) => Some(sender),
+            None if accessible => return Err(BuildError::MissingEventSender(uuid)),
+            None => None,
+        };
+
+        let secure = |level: SecurityLevel, sender: &EventSender| match level {
+            SecurityLevel::None => characteristic::Secure::Insecure(sender.clone()),
+            _ => characteristic::Secure::Secure(sender.clone()),
+        };
+        let properties = match sender {
+            Some(ref sender) => characteristic::Properties::new(
+                self.read
+                    .map(|level| characteristic::Read(secure(level, sender))),
+                match self.write {
+                    Some(level) => Some(characteristic::Write::WithResponse(secure(level, sender))),
+                    None if self.write_without_response => {
+                        Some(characteristic::Write::WithoutResponse(sender.clone()))
+                    }
+                    None => None,
+                },
+                self.notify.map(|_| sender.clone()),
+                self.indicate.map(|_| sender.clone()),
+            ),
+            None => characteristic::Properties::new(None, None, None, None),
+        };
+
+        let managed = self.managed;
+        let default_sender = sender.as_ref().filter(|_| !managed);
+        let descriptors = self
+            .descriptors
+            .into_iter()
+            .map(|descriptor| descriptor.build_with(default_sender))
+            .collect::<Result<_, _>>()?;
+        let mut characteristic = Characteristic::new(uuid, properties, self.value, descriptors);
+        characteristic.authorize = self.authorize;
+        characteristic.acquire_write = self.acquire_write;
+        characteristic.acquire_notify = self.acquire_notify;
+        if self.long_values {
+            characteristic.long_value = Some(LongValue::default());
+        }
+        characteristic.server = self.server;
+        characteristic.security = Security {
+            read: self.read.unwrap_or(SecurityLevel::None),
+            write: self.write.unwrap_or(SecurityLevel::None),
+            notify: self.notify.unwrap_or(SecurityLevel::None),
+            indicate: self.indicate.unwrap_or(SecurityLevel::None),
+        };
+        Ok(characteristic)
+    }
+}
+
+/// Builds a `Descriptor`, see `Descriptor::builder`.
+#[derive(Debug)]
+pub struct DescriptorBuilder {
+    uuid: Uuid,
+    sender: Option<EventSender>,
+    server: Option<Server>,
+    read: Option<SecurityLevel>,
+    write: Option<SecurityLevel>,
+    authorize: bool,
+    long_values: bool,
+    value: Option<Vec<u8>>,
+}
+
+impl DescriptorBuilder {
+    pub(crate) fn new(uuid: Uuid) -> Self {
+        DescriptorBuilder {
+            uuid,
+            sender: None,
+            server: None,
+            read: None,
+            write: None,
+            authorize: false,
+            long_values: false,
+            value: None,
+        }
+    }
+
+    /// Where read and write requests are delivered, by default the characteristic's sender.
+    pub fn sender(mut self, sender: EventSender) -> Self {
+        self.sender = Some(sender);
+        self.server = None;
+        self
+    }
+
+    /// Serves requests by calling `handler` instead of sending events, once the service is
+    /// added to a peripheral.
+    pub fn handler<H: DescriptorHandler>(mut self, handler: H) -> Self {
+        let (sender, server) = handler::descriptor_server(handler);
+        self.sender = Some(sender);
+        self.server = Some(server);
+        self
+    }
+
+    pub fn read(self) -> Self {
+        self.read_with(SecurityLevel::None)
+    }
+
+    /// Allows reads over a link secured with LE Secure Connections only, like `Secure::Secure`.
+    pub fn secure_read(self) -> Self {
+        self.read_with(SecurityLevel::SecureConnections)
+    }
+
+    /// Allows reads over a link secured at `level` or above.
+    pub fn read_with(mut self, level: SecurityLevel) -> Self {
+        self.read = Some(level);
+        self
+    }
+
+    pub fn write(self) -> Self {
+        self.write_with(SecurityLevel::None)
+    }
+
+    /// Allows writes over a link secured with LE Secure Connections only, like `Secure::Secure`.
+    pub fn secure_write(self) -> Self {
+        self.write_with(SecurityLevel::SecureConnections)
+    }
+
+    /// Allows writes over a link secured at `level` or above.
+    pub fn write_with(mut self, level: SecurityLevel) -> Self {
+        self.write = Some(level);
+        self
+    }
+
+    /// Sends an `AuthorizeRequest` before every read or write, see
+    /// `CharacteristicBuilder::authorize`.
+    pub fn authorize(mut self) -> Self {
+        self.authorize = true;
+        self
+    }
+
+    /// Has the library take care of offsets, see `CharacteristicBuilder::long_values`.
+    pub fn long_values(mut self) -> Self {
+        self.long_values = true;
+        self
+    }
+
+    /// A fixed value, served without asking the event sender.
+    pub fn value<T: Into<Vec<u8>>>(mut self, value: T) -> Self {
+        self.value = Some(value.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Descriptor, BuildError> {
+        self.build_with(None)
+    }
+
+    fn build_with(self, default_sender: Option<&EventSender>) -> Result<Descriptor, BuildError> {
+        let uuid = self.uuid;
+        if self.value.is_some() && self.write.is_some() {
+            return Err(BuildError::WritableFixedValue(uuid));
+        }
+        if self.value.is_some() && self.authorize {
+            return Err(BuildError::AuthorizedFixedValue(uuid));
+        }
+        let accessible = self.read.is_some() || self.write.is_some();
+        if !accessible && self.value.is_none() {
+            return Err(BuildError::Inaccessible(uuid));
+        }
+        let sender = match self.sender.as_ref().or(default_sender) {
+            Some(sender) => sender.clone(),
+            None if accessible => return Err(BuildError::MissingEventSender(uuid)),
+            None => {
+                return Ok(Descriptor::new(
+                    uuid,
+                    descriptor::Properties::new(None, None),
+                    self.value,
+                ))
+            }
+        };
+
+        let secure = |level: SecurityLevel| match level {
+            SecurityLevel::None => descriptor::Secure::Insecure(sender.clone()),
+            _ => descriptor::Secure::Secure(sender.clone()),
+        };
+        let properties = descriptor::Properties::new(
+            self.read.map(|level| descriptor::Read(secure(level))),
+            self.write.map(|level| descriptor::Write(secure(level))),
+        );
+        let mut descriptor = Descriptor::new(uuid, properties, self.value);
+        descriptor.authorize = self.authorize;
+        if self.long_values {
+            descriptor.long_value = Some(LongValue::default());
+        }
+        descriptor.server = self.server;
+        descriptor.security = Security {
+            read: self.read.unwrap_or(SecurityLevel::None),
+            write: self.write.unwrap_or(SecurityLevel::None),
+            ..Security::default()
+        };
+        Ok(descriptor)
+    }
+}
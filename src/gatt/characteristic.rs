@@ -1,16 +1,35 @@
-use super::{descriptor::Descriptor, event::EventSender};
-use std::{
-    collections::HashSet,
-    hash::{Hash, Hasher},
+use super::{
+    builder::CharacteristicBuilder,
+    descriptor::Descriptor,
+    event::{EventSender, LongValue},
+    handler::Server,
+    security::{Security, SecurityLevel},
 };
 use uuid::Uuid;
 
+/// A characteristic and its descriptors, which are laid out in the order given.
+///
+/// Several descriptors may share a UUID, e.g. two Characteristic Presentation Formats.
+///
+/// A characteristic with a fixed `value` answers reads itself on every backend, without
+/// involving the read handler.
 #[derive(Debug, Clone)]
 pub struct Characteristic {
     pub(crate) uuid: Uuid,
     pub(crate) properties: Properties,
     pub(crate) value: Option<Vec<u8>>,
-    pub(crate) descriptors: HashSet<Descriptor>,
+    pub(crate) descriptors: Vec<Descriptor>,
+    /// Whether reads and writes wait for an `AuthorizeRequest` to be allowed.
+    pub(crate) authorize: bool,
+    pub(crate) security: Security,
+    /// Whether BlueZ may hand write commands over a socket, see `AcquireWrite`.
+    pub(crate) acquire_write: bool,
+    /// Whether BlueZ may take notifications from a socket, see `AcquireNotify`.
+    pub(crate) acquire_notify: bool,
+    /// Set if reads and writes are of the whole value, see `event::read_long`.
+    pub(crate) long_value: Option<LongValue>,
+    /// Answers the events of a `managed` or `handler` characteristic, see `Service::start`.
+    pub(crate) server: Option<Server>,
 }
 
 impl Characteristic {
@@ -18,17 +37,52 @@ impl Characteristic {
         uuid: Uuid,
         properties: Properties,
         value: Option<Vec<u8>>,
-        descriptors: HashSet<Descriptor>,
+        descriptors: Vec<Descriptor>,
     ) -> Self {
+        let security = Security {
+            read: properties
+                .read
+                .as_ref()
+                .map_or(SecurityLevel::None, |read| read.level()),
+            write: match properties.write {
+                Some(Write::WithResponse(ref secure)) => secure.level(),
+                _ => SecurityLevel::None,
+            },
+            ..Security::default()
+        };
         Characteristic {
             uuid,
             properties,
             value,
             descriptors,
+            authorize: false,
+            security,
+            acquire_write: false,
+            acquire_notify: false,
+            long_value: None,
+            server: None,
         }
     }
-}
 
-impl_uuid_hash_eq!(Characteristic);
+    pub fn builder(uuid: Uuid) -> CharacteristicBuilder {
+        CharacteristicBuilder::new(uuid)
+    }
+
+    /// Whether centrals can read it, either through the read handler or its fixed value.
+    pub(crate) fn is_readable(&self) -> bool {
+        self.properties.read.is_some() || self.value.is_some()
+    }
+}
 
 properties!(WriteWithAndWithoutResponse, EventSender, { notify: EventSender, indicate: EventSender });
+
+impl Secure {
+    /// `Secure` asks for LE Secure Connections, which BlueZ exports as the `secure-*` and
+    /// `encrypt-authenticated-*` flags it always had and CoreBluetooth as encryption required.
+    pub(crate) fn level(&self) -> SecurityLevel {
+        match self {
+            Secure::Secure(_) => SecurityLevel::SecureConnections,
+            Secure::Insecure(_) => SecurityLevel::None,
+        }
+    }
+}
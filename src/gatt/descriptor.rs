@@ -1,24 +1,70 @@
-use super::event::EventSender;
-use std::hash::{Hash, Hasher};
+use super::{
+    builder::DescriptorBuilder,
+    event::{EventSender, LongValue},
+    handler::Server,
+    security::{Security, SecurityLevel},
+};
 use uuid::Uuid;
 
+/// A descriptor; one with a fixed `value` answers reads itself on every backend, without
+/// involving the read handler.
 #[derive(Debug, Clone)]
 pub struct Descriptor {
     pub(crate) uuid: Uuid,
     pub(crate) properties: Properties,
     pub(crate) value: Option<Vec<u8>>,
+    /// Whether reads and writes wait for an `AuthorizeRequest` to be allowed.
+    pub(crate) authorize: bool,
+    /// Set if reads and writes are of the whole value, see `event::read_long`.
+    pub(crate) long_value: Option<LongValue>,
+    /// Only `read` and `write` apply.
+    pub(crate) security: Security,
+    /// Answers the events of a `handler` descriptor, see `Service::start`.
+    pub(crate) server: Option<Server>,
 }
 
 impl Descriptor {
     pub fn new(uuid: Uuid, properties: Properties, value: Option<Vec<u8>>) -> Self {
+        let security = Security {
+            read: properties
+                .read
+                .as_ref()
+                .map_or(SecurityLevel::None, |read| read.level()),
+            write: properties
+                .write
+                .as_ref()
+                .map_or(SecurityLevel::None, |write| write.level()),
+            ..Security::default()
+        };
         Descriptor {
             uuid,
             properties,
             value,
+            authorize: false,
+            long_value: None,
+            security,
+            server: None,
         }
     }
-}
 
-impl_uuid_hash_eq!(Descriptor);
+    pub fn builder(uuid: Uuid) -> DescriptorBuilder {
+        DescriptorBuilder::new(uuid)
+    }
+
+    /// Whether centrals can read it, either through the read handler or its fixed value.
+    pub(crate) fn is_readable(&self) -> bool {
+        self.properties.read.is_some() || self.value.is_some()
+    }
+}
 
 properties!(WriteWithResponse, EventSender);
+
+impl Secure {
+    /// See `characteristic::Secure::level`.
+    pub(crate) fn level(&self) -> SecurityLevel {
+        match self {
+            Secure::Secure(_) => SecurityLevel::SecureConnections,
+            Secure::Insecure(_) => SecurityLevel::None,
+        }
+    }
+}
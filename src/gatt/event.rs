@@ -1,4 +1,26 @@
-use futures::channel::{mpsc, oneshot};
+use futures::{
+    channel::{mpsc, oneshot},
+    lock,
+    prelude::*,
+};
+use std::{
+    error, fmt, io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// How long a central has to confirm an indication, the ATT transaction timeout.
+pub(crate) const INDICATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many notifications `AcquireNotify::into_subscription` buffers on their way to the socket.
+const ACQUIRED_NOTIFICATION_CAPACITY: usize = 16;
+/// The longest an attribute value can be.
+const MAX_VALUE_LEN: usize = 512;
 
 pub type EventSender = mpsc::Sender<Event>;
 pub type ResponseSender = oneshot::Sender<Response>;
@@ -8,32 +30,481 @@ pub enum Event {
     ReadRequest(ReadRequest),
     WriteRequest(WriteRequest),
     NotifySubscribe(NotifySubscribe),
-    NotifyUnsubscribe,
+    NotifyUnsubscribe(NotifyUnsubscribe),
+    /// Sent before every read or write of an attribute built with `authorize`.
+    AuthorizeRequest(AuthorizeRequest),
+    /// Only sent by BlueZ, for characteristics built with `acquire_write`.
+    AcquireWrite(AcquireWrite),
+    /// Only sent by BlueZ, for characteristics built with `acquire_notify`.
+    AcquireNotify(AcquireNotify),
 }
 
 #[derive(Debug)]
 pub struct ReadRequest {
+    pub context: RequestContext,
+    /// Always 0 for attributes built with `long_values`, which are sliced by the library.
     pub offset: u16,
     pub response: ResponseSender,
 }
 
 #[derive(Debug)]
 pub struct WriteRequest {
+    pub context: RequestContext,
     pub data: Vec<u8>,
+    /// Always 0 for attributes built with `long_values`, whose `data` is the whole value.
     pub offset: u16,
-    pub without_response: bool,
+    pub kind: WriteKind,
+    /// Nobody waits for the response to a `WriteKind::Command`, so it may be dropped.
     pub response: ResponseSender,
 }
 
+/// Values sent through `notification` reach the central without waiting for it, in the
+/// subscribed `mode`; `indicate` waits for each value to be confirmed. BlueZ sends indications one
+/// at a time, those through `notification` included, each after the previous one was confirmed.
 #[derive(Debug, Clone)]
 pub struct NotifySubscribe {
+    /// Identifies the subscription until the matching `NotifyUnsubscribe`.
+    pub id: SubscriptionId,
+    /// The central that subscribed. BlueZ subscribes once for all centrals and does not say
+    /// which, so only CoreBluetooth and the simulator fill in the device.
+    pub context: RequestContext,
     pub notification: mpsc::Sender<Vec<u8>>,
+    pub mode: NotifyMode,
+    pub(crate) indications: Option<mpsc::Sender<Indication>>,
+}
+
+impl NotifySubscribe {
+    /// Indicates `value` and resolves once the central confirms it.
+    ///
+    /// Fails if the subscription is for notifications, if the central does not confirm in
+    /// time, or if it unsubscribes or disconnects first.
+    pub async fn indicate(&self, value: Vec<u8>) -> Result<(), IndicationError> {
+        let mut indications = self
+            .indications
+            .clone()
+            .ok_or(IndicationError::NotIndicating)?;
+        let (confirmed, confirmation) = oneshot::channel();
+        indications
+            .send(Indication { value, confirmed })
+            .await
+            .map_err(|_| IndicationError::Disconnected)?;
+        match tokio::time::timeout(INDICATION_TIMEOUT, confirmation).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(IndicationError::Disconnected),
+            Err(_) => Err(IndicationError::Timeout),
+        }
+    }
 }
 
+/// The central behind a subscription unsubscribed or disconnected.
 #[derive(Debug, Clone)]
+pub struct NotifyUnsubscribe {
+    pub id: SubscriptionId,
+    pub context: RequestContext,
+}
+
+/// Tells subscriptions apart, unique for the lifetime of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+impl SubscriptionId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        SubscriptionId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Whether a central subscribed to notifications or to indications.
+///
+/// Neither BlueZ nor CoreBluetooth says which one a central enabled on a characteristic that
+/// supports both, so they only report `Indicate` for characteristics that cannot notify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyMode {
+    Notify,
+    /// Every value is confirmed by the central.
+    Indicate,
+}
+
+/// A value to indicate; the backend sends on `confirmed` once the central confirms it and drops
+/// it if the central goes away.
+#[derive(Debug)]
+pub(crate) struct Indication {
+    pub(crate) value: Vec<u8>,
+    pub(crate) confirmed: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndicationError {
+    /// The central subscribed to notifications, which are never confirmed.
+    NotIndicating,
+    Timeout,
+    /// The central unsubscribed or disconnected before confirming.
+    Disconnected,
+}
+
+impl fmt::Display for IndicationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            IndicationError::NotIndicating => "the central subscribed to notifications",
+            IndicationError::Timeout => "the central did not confirm the indication in time",
+            IndicationError::Disconnected => "the central went away before confirming",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl error::Error for IndicationError {}
+
+/// Write commands from a central, handed over in bulk: each packet `stream` yields is the data
+/// of one write, which is not sent as a `WriteRequest` of its own.
+///
+/// The stream ends when bluetoothd closes the socket, e.g. once the central disconnects.
+#[derive(Debug)]
+pub struct AcquireWrite {
+    pub context: RequestContext,
+    /// The ATT MTU, which no packet exceeds.
+    pub mtu: u16,
+    pub stream: PacketStream,
+}
+
+/// A subscription whose values go through `sink` instead of the notification channel; each
+/// packet is one notification of at most `mtu - 3` bytes.
+///
+/// Sending fails once bluetoothd closes the socket, after the last central unsubscribed.
+#[derive(Debug)]
+pub struct AcquireNotify {
+    pub context: RequestContext,
+    /// The ATT MTU.
+    pub mtu: u16,
+    pub sink: PacketSink,
+}
+
+impl AcquireNotify {
+    /// Turns it into a plain subscription, whose notifications are fed into the sink; this
+    /// spawns a task on the Tokio runtime.
+    pub fn into_subscription(self) -> NotifySubscribe {
+        let (sender, receiver) = mpsc::channel(ACQUIRED_NOTIFICATION_CAPACITY);
+        tokio::spawn(receiver.map(Ok).forward(self.sink).map(|_| ()));
+        NotifySubscribe {
+            id: SubscriptionId::next(),
+            context: self.context,
+            notification: sender,
+            mode: NotifyMode::Notify,
+            indications: None,
+        }
+    }
+}
+
+/// The packets of an acquired socket, see `AcquireWrite`.
+pub struct PacketStream {
+    stream: Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>,
+}
+
+impl PacketStream {
+    pub(crate) fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Vec<u8>> + Send + 'static,
+    {
+        PacketStream {
+            stream: Box::pin(stream),
+        }
+    }
+}
+
+impl fmt::Debug for PacketStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PacketStream").finish()
+    }
+}
+
+impl Stream for PacketStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+/// Writes packets to an acquired socket, see `AcquireNotify`.
+pub struct PacketSink {
+    sink: Pin<Box<dyn Sink<Vec<u8>, Error = io::Error> + Send>>,
+}
+
+impl PacketSink {
+    pub(crate) fn new<S>(sink: S) -> Self
+    where
+        S: Sink<Vec<u8>, Error = io::Error> + Send + 'static,
+    {
+        PacketSink {
+            sink: Box::pin(sink),
+        }
+    }
+}
+
+impl fmt::Debug for PacketSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PacketSink").finish()
+    }
+}
+
+impl Sink<Vec<u8>> for PacketSink {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.sink.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: Vec<u8>) -> io::Result<()> {
+        self.sink.as_mut().start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.sink.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.sink.as_mut().poll_close(cx)
+    }
+}
+
+/// Asks whether a read or write may go ahead; it is only carried out once `Allow` is sent.
+///
+/// Dropping `response` denies the request.
+#[derive(Debug)]
+pub struct AuthorizeRequest {
+    pub context: RequestContext,
+    pub access: Access,
+    pub response: oneshot::Sender<Authorization>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write(WriteKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    Allow,
+    /// Refuses the request with Insufficient Authorization.
+    Deny,
+}
+
+/// Where a request comes from, as far as the platform tells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestContext {
+    /// The characteristic or descriptor the request is for.
+    pub uuid: Uuid,
+    /// The central that sent the request: its object path with BlueZ, its identifier with
+    /// CoreBluetooth, its address with the simulator.
+    pub device: Option<String>,
+    /// The ATT MTU negotiated with the central.
+    pub mtu: Option<u16>,
+    pub link: Option<Link>,
+}
+
+impl RequestContext {
+    /// A context that knows nothing beyond the attribute.
+    pub(crate) fn new(uuid: Uuid) -> Self {
+        RequestContext {
+            uuid,
+            device: None,
+            mtu: None,
+            link: None,
+        }
+    }
+}
+
+/// The transport a central is connected over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    Le,
+    BrEdr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteKind {
+    /// A Write Request, which the central expects a response to.
+    Request,
+    /// A Write Command, i.e. a write without response.
+    Command,
+    /// The execution of a reliable write: the central prepared the data, possibly in several
+    /// pieces, and now writes it all at once. The response accepts or rejects it as a whole.
+    Reliable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Response {
     Success(Vec<u8>),
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InsufficientAuthentication,
+    InsufficientAuthorization,
+    InsufficientEncryption,
     InvalidOffset,
     InvalidAttributeLength,
     UnlikelyError,
+    ValueNotAllowed,
+    /// An application error, with a code from 0x80 to 0x9F defined by the profile.
+    ApplicationError(u8),
+}
+
+impl Response {
+    /// The ATT error code sent to the central, or `None` for `Success`.
+    ///
+    /// Application error codes outside of 0x80 to 0x9F are sent as `UnlikelyError`.
+    pub fn att_error_code(&self) -> Option<u8> {
+        Some(match self {
+            Response::Success(_) => return None,
+            Response::ReadNotPermitted => 0x02,
+            Response::WriteNotPermitted => 0x03,
+            Response::InsufficientAuthentication => 0x05,
+            Response::InvalidOffset => 0x07,
+            Response::InsufficientAuthorization => 0x08,
+            Response::InvalidAttributeLength => 0x0D,
+            Response::UnlikelyError => 0x0E,
+            Response::InsufficientEncryption => 0x0F,
+            Response::ValueNotAllowed => 0x13,
+            Response::ApplicationError(code @ 0x80..=0x9F) => *code,
+            Response::ApplicationError(_) => 0x0E,
+        })
+    }
+
+    /// Answers a read starting at `offset` from the whole value.
+    pub(crate) fn read_value(value: &[u8], offset: u16) -> Self {
+        match value.get(usize::from(offset)..) {
+            Some(rest) => Response::Success(rest.to_vec()),
+            None => Response::InvalidOffset,
+        }
+    }
+}
+
+/// Sends an `AuthorizeRequest` and waits for the answer; a handler that has gone away or
+/// dropped the request denies it.
+pub(crate) async fn authorize(
+    mut event_sender: EventSender,
+    context: RequestContext,
+    access: Access,
+) -> Authorization {
+    let (sender, receiver) = oneshot::channel();
+    let request = AuthorizeRequest {
+        context,
+        access,
+        response: sender,
+    };
+    if event_sender
+        .send(Event::AuthorizeRequest(request))
+        .await
+        .is_err()
+    {
+        return Authorization::Deny;
+    }
+    receiver.await.unwrap_or(Authorization::Deny)
+}
+
+/// Reads an attribute built with `long_values`: the handler is asked for the whole value, which
+/// is answered from `offset` on.
+///
+/// Fails if the handler has gone away.
+pub(crate) async fn read_long(
+    event_sender: EventSender,
+    context: RequestContext,
+    offset: u16,
+) -> Result<Response, oneshot::Canceled> {
+    Ok(match read(event_sender, context).await? {
+        Response::Success(value) => Response::read_value(&value, offset),
+        response => response,
+    })
+}
+
+/// What the library keeps of an attribute built with `long_values`: the value it last wrote,
+/// behind a lock that every write holds until the handler has answered it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LongValue(Arc<lock::Mutex<Vec<u8>>>);
+
+impl LongValue {
+    /// Writes `data` at `offset`, sending the handler the whole resulting value at offset 0.
+    ///
+    /// Like `CharacteristicHandle`, the value is cut at `offset` before `data` is appended, so
+    /// whatever followed `offset + data.len()` is gone. The value up to `offset` is read through
+    /// `read_sender`, or is the one last written if the attribute cannot be read. Writers take
+    /// turns from that read until the handler answers, so two centrals writing parts of the
+    /// value never splice into a stale one.
+    ///
+    /// Returns the response for the central, which is a success right away for a write command,
+    /// and fails if the handler has gone away.
+    pub(crate) async fn write(
+        &self,
+        read_sender: Option<EventSender>,
+        mut write_sender: EventSender,
+        context: RequestContext,
+        offset: u16,
+        data: Vec<u8>,
+        kind: WriteKind,
+    ) -> Result<Response, oneshot::Canceled> {
+        let offset = usize::from(offset);
+        if offset + data.len() > MAX_VALUE_LEN {
+            return Ok(Response::InvalidAttributeLength);
+        }
+        let mut written = self.0.lock().await;
+        let mut value = match read_sender {
+            _ if offset == 0 => vec![],
+            Some(read_sender) => match read(read_sender, context.clone()).await? {
+                Response::Success(value) => value,
+                response => return Ok(response),
+            },
+            None => written.clone(),
+        };
+        if offset > value.len() {
+            return Ok(Response::InvalidOffset);
+        }
+        value.truncate(offset);
+        value.extend(data);
+
+        let (sender, receiver) = oneshot::channel();
+        let request = WriteRequest {
+            context,
+            data: value.clone(),
+            offset: 0,
+            kind,
+            response: sender,
+        };
+        if write_sender
+            .send(Event::WriteRequest(request))
+            .await
+            .is_err()
+        {
+            return Err(oneshot::Canceled);
+        }
+        let response = if kind == WriteKind::Command {
+            Response::Success(vec![])
+        } else {
+            receiver.await?
+        };
+        if let Response::Success(_) = response {
+            *written = value;
+        }
+        Ok(response)
+    }
+}
+
+/// Asks the handler for the whole value.
+async fn read(
+    mut event_sender: EventSender,
+    context: RequestContext,
+) -> Result<Response, oneshot::Canceled> {
+    let (sender, receiver) = oneshot::channel();
+    let request = ReadRequest {
+        context,
+        offset: 0,
+        response: sender,
+    };
+    if event_sender
+        .send(Event::ReadRequest(request))
+        .await
+        .is_err()
+    {
+        return Err(oneshot::Canceled);
+    }
+    receiver.await
 }
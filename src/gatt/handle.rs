@@ -0,0 +1,173 @@
+use futures::{channel::mpsc, prelude::*};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    event::{Event, EventSender, Response, SubscriptionId},
+    handler::Server,
+};
+
+type Validator = Box<dyn Fn(&[u8]) -> Result<(), Response> + Send + Sync>;
+
+struct Managed {
+    value: Mutex<Vec<u8>>,
+    subscribers: Mutex<Vec<(SubscriptionId, mpsc::Sender<Vec<u8>>)>>,
+    validator: Option<Validator>,
+}
+
+/// The value of a characteristic that the library keeps on behalf of the application.
+///
+/// Reads are answered from the stored value and writes replace it once the validator, if any,
+/// accepts them. `set_value` notifies or indicates every subscribed central.
+///
+/// ```no_run
+/// # use bluster::{gatt::{handle::CharacteristicHandle, service::Service}, SdpShortUuid};
+/// # use uuid::Uuid;
+/// # async fn example() {
+/// let level = CharacteristicHandle::new(vec![100]);
+/// let service = Service::builder(Uuid::from_sdp_short_uuid(0x180Fu16))
+///     .primary()
+///     .characteristic(Uuid::from_sdp_short_uuid(0x2A19u16), |c| {
+///         c.managed(&level).read().notify()
+///     })
+///     .build()
+///     .unwrap();
+/// level.set_value(vec![99]).await;
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct CharacteristicHandle {
+    managed: Arc<Managed>,
+}
+
+impl fmt::Debug for CharacteristicHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CharacteristicHandle")
+            .field("value", &self.get_value())
+            .finish()
+    }
+}
+
+impl CharacteristicHandle {
+    pub fn new(value: Vec<u8>) -> Self {
+        CharacteristicHandle::build(value, None)
+    }
+
+    /// Like `new`, but every write is passed to `validator` first, with the value it would
+    /// result in; an `Err` is sent back to the central and leaves the value unchanged.
+    pub fn with_validator<F>(value: Vec<u8>, validator: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<(), Response> + Send + Sync + 'static,
+    {
+        CharacteristicHandle::build(value, Some(Box::new(validator)))
+    }
+
+    fn build(value: Vec<u8>, validator: Option<Validator>) -> Self {
+        CharacteristicHandle {
+            managed: Arc::new(Managed {
+                value: Mutex::new(value),
+                subscribers: Mutex::new(vec![]),
+                validator,
+            }),
+        }
+    }
+
+    pub fn get_value(&self) -> Vec<u8> {
+        self.managed.value.lock().unwrap().clone()
+    }
+
+    /// Stores a new value and sends it to every subscribed central. A central whose queue is
+    /// full misses the value rather than holding up the others.
+    pub async fn set_value(&self, value: Vec<u8>) {
+        *self.managed.value.lock().unwrap() = value.clone();
+
+        let mut subscribers = self.managed.subscribers.lock().unwrap();
+        for (_, subscriber) in subscribers.iter_mut() {
+            subscriber.try_send(value.clone()).ok();
+        }
+        subscribers.retain(|(_, subscriber)| !subscriber.is_closed());
+    }
+
+    /// Returns an event sender whose events are answered from the stored value; this spawns a
+    /// task on the Tokio runtime, which `CharacteristicBuilder::managed` leaves to `add_service`.
+    pub fn sender(&self) -> EventSender {
+        let (sender, server) = self.server();
+        server.start();
+        sender
+    }
+
+    pub(crate) fn server(&self) -> (EventSender, Server) {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let managed = Arc::clone(&self.managed);
+        let server = Server::new(async move {
+            while let Some(event) = receiver.next().await {
+                managed.handle(event);
+            }
+        });
+        (sender, server)
+    }
+}
+
+impl Managed {
+    fn handle(self: &Arc<Self>, event: Event) {
+        match event {
+            Event::ReadRequest(read_request) => {
+                let value = self.value.lock().unwrap();
+                let response = Response::read_value(&value, read_request.offset);
+                read_request.response.send(response).ok();
+            }
+            Event::WriteRequest(write_request) => {
+                let response = self.write(&write_request.data, write_request.offset);
+                write_request.response.send(response).ok();
+            }
+            Event::NotifySubscribe(notify_subscribe) => {
+                self.subscribers
+                    .lock()
+                    .unwrap()
+                    .push((notify_subscribe.id, notify_subscribe.notification));
+            }
+            Event::NotifyUnsubscribe(notify_unsubscribe) => self
+                .subscribers
+                .lock()
+                .unwrap()
+                .retain(|(id, _)| *id != notify_unsubscribe.id),
+            // `CharacteristicBuilder::build` refuses to authorize a managed characteristic.
+            Event::AuthorizeRequest(_) => {}
+            // Nobody waits for the response to a write command.
+            Event::AcquireWrite(mut acquire_write) => {
+                let managed = Arc::clone(self);
+                tokio::spawn(async move {
+                    while let Some(data) = acquire_write.stream.next().await {
+                        managed.write(&data, 0);
+                    }
+                });
+            }
+            Event::AcquireNotify(acquire_notify) => {
+                let subscription = acquire_notify.into_subscription();
+                self.subscribers
+                    .lock()
+                    .unwrap()
+                    .push((subscription.id, subscription.notification));
+            }
+        }
+    }
+
+    fn write(&self, data: &[u8], offset: u16) -> Response {
+        let mut value = self.value.lock().unwrap();
+        let offset = usize::from(offset);
+        if offset > value.len() {
+            return Response::InvalidOffset;
+        }
+        let mut new_value = value[..offset].to_vec();
+        new_value.extend_from_slice(data);
+        if let Some(ref validator) = self.validator {
+            if let Err(response) = validator(&new_value) {
+                return response;
+            }
+        }
+        *value = new_value;
+        Response::Success(vec![])
+    }
+}
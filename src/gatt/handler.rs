@@ -0,0 +1,296 @@
+use async_trait::async_trait;
+use futures::{channel::mpsc, future::BoxFuture, prelude::*};
+use std::{
+    error, fmt,
+    sync::{Arc, Mutex},
+};
+
+use super::event::{
+    Access, AcquireWrite, Authorization, Event, EventSender, NotifySubscribe, RequestContext,
+    Response, SubscriptionId, WriteKind,
+};
+use crate::Error;
+
+/// An ATT error a handler answers a request with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttError {
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InsufficientAuthentication,
+    InsufficientAuthorization,
+    InsufficientEncryption,
+    InvalidOffset,
+    InvalidAttributeLength,
+    ValueNotAllowed,
+    /// An application error, with a code from 0x80 to 0x9F defined by the profile.
+    Application(u8),
+    /// Anything else that went wrong while serving the request.
+    Unlikely,
+}
+
+impl fmt::Display for AttError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttError::ReadNotPermitted => write!(f, "read not permitted"),
+            AttError::WriteNotPermitted => write!(f, "write not permitted"),
+            AttError::InsufficientAuthentication => write!(f, "insufficient authentication"),
+            AttError::InsufficientAuthorization => write!(f, "insufficient authorization"),
+            AttError::InsufficientEncryption => write!(f, "insufficient encryption"),
+            AttError::InvalidOffset => write!(f, "invalid offset"),
+            AttError::InvalidAttributeLength => write!(f, "invalid attribute value length"),
+            AttError::ValueNotAllowed => write!(f, "value not allowed"),
+            AttError::Application(code) => write!(f, "application error {:#04x}", code),
+            AttError::Unlikely => write!(f, "unlikely error"),
+        }
+    }
+}
+
+impl error::Error for AttError {}
+
+impl From<Error> for AttError {
+    fn from(_: Error) -> Self {
+        AttError::Unlikely
+    }
+}
+
+impl From<AttError> for Response {
+    fn from(error: AttError) -> Self {
+        match error {
+            AttError::ReadNotPermitted => Response::ReadNotPermitted,
+            AttError::WriteNotPermitted => Response::WriteNotPermitted,
+            AttError::InsufficientAuthentication => Response::InsufficientAuthentication,
+            AttError::InsufficientAuthorization => Response::InsufficientAuthorization,
+            AttError::InsufficientEncryption => Response::InsufficientEncryption,
+            AttError::InvalidOffset => Response::InvalidOffset,
+            AttError::InvalidAttributeLength => Response::InvalidAttributeLength,
+            AttError::ValueNotAllowed => Response::ValueNotAllowed,
+            AttError::Application(code) => Response::ApplicationError(code),
+            AttError::Unlikely => Response::UnlikelyError,
+        }
+    }
+}
+
+/// Serves the requests for a characteristic, as an alternative to matching on `Event`s.
+///
+/// Attach it with `CharacteristicBuilder::handler`; once the service is added to a peripheral,
+/// the library calls it for every request, one at a time, and sends the result back to the
+/// central.
+#[async_trait]
+pub trait CharacteristicHandler: Send + Sync + 'static {
+    async fn on_read(&self, _ctx: &RequestContext, _offset: u16) -> Result<Vec<u8>, AttError> {
+        Err(AttError::ReadNotPermitted)
+    }
+
+    async fn on_write(
+        &self,
+        _ctx: &RequestContext,
+        _offset: u16,
+        _data: Vec<u8>,
+        _kind: WriteKind,
+    ) -> Result<(), AttError> {
+        Err(AttError::WriteNotPermitted)
+    }
+
+    /// A central subscribed; values sent through `subscription` are notified or indicated,
+    /// as its `mode` says, until `on_unsubscribe`.
+    ///
+    /// No other request is served until it returns, so it must return right away: keep
+    /// `subscription` somewhere, or spawn a task that feeds it, rather than sending from here.
+    async fn on_subscribe(&self, _ctx: &RequestContext, _subscription: NotifySubscribe) {}
+
+    /// The subscription `id` ended, because the central unsubscribed or disconnected.
+    async fn on_unsubscribe(&self, _ctx: &RequestContext, _id: SubscriptionId) {}
+
+    /// Decides whether a read or write of a characteristic built with `authorize` may go
+    /// ahead; by default none may.
+    async fn on_authorize(&self, _ctx: &RequestContext, _access: Access) -> Authorization {
+        Authorization::Deny
+    }
+}
+
+/// Serves the requests for a descriptor, see `CharacteristicHandler`.
+#[async_trait]
+pub trait DescriptorHandler: Send + Sync + 'static {
+    async fn on_read(&self, _ctx: &RequestContext, _offset: u16) -> Result<Vec<u8>, AttError> {
+        Err(AttError::ReadNotPermitted)
+    }
+
+    async fn on_write(
+        &self,
+        _ctx: &RequestContext,
+        _offset: u16,
+        _data: Vec<u8>,
+    ) -> Result<(), AttError> {
+        Err(AttError::WriteNotPermitted)
+    }
+
+    async fn on_authorize(&self, _ctx: &RequestContext, _access: Access) -> Authorization {
+        Authorization::Deny
+    }
+}
+
+/// Returns an event sender that turns events into calls on `handler`, and the server that does
+/// so once started.
+pub(crate) fn characteristic_server<H: CharacteristicHandler>(handler: H) -> (EventSender, Server) {
+    let handler = Arc::new(handler);
+    serve(move |event| {
+        let handler = Arc::clone(&handler);
+        async move {
+            match event {
+                Event::ReadRequest(read_request) => {
+                    let response = respond(
+                        handler
+                            .on_read(&read_request.context, read_request.offset)
+                            .await,
+                    );
+                    read_request.response.send(response).ok();
+                }
+                Event::WriteRequest(write_request) => {
+                    let result = handler
+                        .on_write(
+                            &write_request.context,
+                            write_request.offset,
+                            write_request.data,
+                            write_request.kind,
+                        )
+                        .await;
+                    write_request
+                        .response
+                        .send(respond(result.map(|()| vec![])))
+                        .ok();
+                }
+                Event::NotifySubscribe(notify_subscribe) => {
+                    let ctx = notify_subscribe.context.clone();
+                    handler.on_subscribe(&ctx, notify_subscribe).await
+                }
+                Event::NotifyUnsubscribe(notify_unsubscribe) => {
+                    handler
+                        .on_unsubscribe(&notify_unsubscribe.context, notify_unsubscribe.id)
+                        .await
+                }
+                Event::AuthorizeRequest(authorize_request) => {
+                    let authorization = handler
+                        .on_authorize(&authorize_request.context, authorize_request.access)
+                        .await;
+                    authorize_request.response.send(authorization).ok();
+                }
+                // Each packet is a write command; a task of its own keeps the stream from
+                // holding up other events.
+                Event::AcquireWrite(acquire_write) => {
+                    let AcquireWrite {
+                        context,
+                        mut stream,
+                        ..
+                    } = acquire_write;
+                    tokio::spawn(async move {
+                        while let Some(data) = stream.next().await {
+                            handler
+                                .on_write(&context, 0, data, WriteKind::Command)
+                                .await
+                                .ok();
+                        }
+                    });
+                }
+                Event::AcquireNotify(acquire_notify) => {
+                    let ctx = acquire_notify.context.clone();
+                    handler
+                        .on_subscribe(&ctx, acquire_notify.into_subscription())
+                        .await
+                }
+            }
+        }
+    })
+}
+
+/// Like `characteristic_server`, for descriptors.
+pub(crate) fn descriptor_server<H: DescriptorHandler>(handler: H) -> (EventSender, Server) {
+    let handler = Arc::new(handler);
+    serve(move |event| {
+        let handler = Arc::clone(&handler);
+        async move {
+            match event {
+                Event::ReadRequest(read_request) => {
+                    let response = respond(
+                        handler
+                            .on_read(&read_request.context, read_request.offset)
+                            .await,
+                    );
+                    read_request.response.send(response).ok();
+                }
+                Event::WriteRequest(write_request) => {
+                    let result = handler
+                        .on_write(
+                            &write_request.context,
+                            write_request.offset,
+                            write_request.data,
+                        )
+                        .await;
+                    write_request
+                        .response
+                        .send(respond(result.map(|()| vec![])))
+                        .ok();
+                }
+                Event::AuthorizeRequest(authorize_request) => {
+                    let authorization = handler
+                        .on_authorize(&authorize_request.context, authorize_request.access)
+                        .await;
+                    authorize_request.response.send(authorization).ok();
+                }
+                // Descriptors cannot be subscribed to or acquired.
+                Event::NotifySubscribe(_)
+                | Event::NotifyUnsubscribe(_)
+                | Event::AcquireWrite(_)
+                | Event::AcquireNotify(_) => {}
+            }
+        }
+    })
+}
+
+/// The task answering the events of an event sender from `managed` or `handler`.
+///
+/// Building a service only creates it; it is spawned on the Tokio runtime once the service is
+/// added to a peripheral, so services can be built outside of one.
+#[derive(Clone)]
+pub(crate) struct Server(Arc<Mutex<Option<BoxFuture<'static, ()>>>>);
+
+impl Server {
+    pub(crate) fn new<F: Future<Output = ()> + Send + 'static>(task: F) -> Self {
+        Server(Arc::new(Mutex::new(Some(task.boxed()))))
+    }
+
+    /// Spawns the task, unless an earlier call already did.
+    pub(crate) fn start(&self) {
+        if let Some(task) = self.0.lock().unwrap().take() {
+            tokio::spawn(task);
+        }
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("started", &self.0.lock().unwrap().is_none())
+            .finish()
+    }
+}
+
+fn serve<F, Fut>(handle: F) -> (EventSender, Server)
+where
+    F: Fn(Event) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let (sender, mut receiver) = mpsc::channel(1);
+    let server = Server::new(async move {
+        while let Some(event) = receiver.next().await {
+            handle(event).await;
+        }
+    });
+    (sender, server)
+}
+
+fn respond(result: Result<Vec<u8>, AttError>) -> Response {
+    match result {
+        Ok(value) => Response::Success(value),
+        Err(error) => error.into(),
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+use super::{
+    characteristic::{self, Characteristic},
+    descriptor::Descriptor,
+    service::Service,
+};
+
+// 128 bit FNV-1a
+const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+const PRIMARY_SERVICE: u16 = 0x2800;
+const SECONDARY_SERVICE: u16 = 0x2801;
+const CHARACTERISTIC: u16 = 0x2803;
+
+/// A fingerprint of the services, characteristics and descriptors of a GATT database.
+///
+/// Like the Database Hash of the Bluetooth specification it covers what a client caches, i.e.
+/// attribute types, UUIDs, properties and fixed descriptor values, so it only changes when the
+/// tree does. It leaves out attribute handles, which the platform assigns, but follows the
+/// declared order of the attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DatabaseHash(u128);
+
+impl DatabaseHash {
+    pub fn new<'a, I: IntoIterator<Item = &'a Service>>(services: I) -> Self {
+        let mut hasher = Hasher(OFFSET_BASIS);
+        for service in services {
+            hasher.write_service(service);
+        }
+        DatabaseHash(hasher.0)
+    }
+
+    pub fn to_bytes(self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl fmt::Display for DatabaseHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+struct Hasher(u128);
+
+impl Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u128::from(*byte);
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    fn write_service(&mut self, service: &Service) {
+        let declaration = if service.primary {
+            PRIMARY_SERVICE
+        } else {
+            SECONDARY_SERVICE
+        };
+        self.write(&declaration.to_le_bytes());
+        self.write(service.uuid.as_bytes());
+
+        for characteristic in &service.characteristics {
+            self.write(&CHARACTERISTIC.to_le_bytes());
+            self.write(&[characteristic_properties(characteristic)]);
+            self.write(characteristic.uuid.as_bytes());
+
+            for descriptor in &characteristic.descriptors {
+                self.write_descriptor(descriptor);
+            }
+        }
+    }
+
+    fn write_descriptor(&mut self, descriptor: &Descriptor) {
+        let mut properties = 0;
+        if descriptor.is_readable() {
+            properties |= 0x02;
+        }
+        if descriptor.properties.write.is_some() {
+            properties |= 0x08;
+        }
+        self.write(descriptor.uuid.as_bytes());
+        self.write(&[properties]);
+        match descriptor.value {
+            Some(ref value) => {
+                self.write(&(value.len() as u32).to_le_bytes());
+                self.write(value);
+            }
+            None => self.write(&[0xff; 4]),
+        }
+    }
+}
+
+/// The properties byte of a characteristic declaration.
+fn characteristic_properties(characteristic: &Characteristic) -> u8 {
+    let properties = &characteristic.properties;
+    let mut byte = 0;
+    if characteristic.is_readable() {
+        byte |= 0x02;
+    }
+    match properties.write {
+        Some(characteristic::Write::WithoutResponse(_)) => byte |= 0x04,
+        Some(characteristic::Write::WithResponse(_)) => byte |= 0x08,
+        None => {}
+    }
+    if properties.notify.is_some() {
+        byte |= 0x10;
+    }
+    if properties.indicate.is_some() {
+        byte |= 0x20;
+    }
+    byte
+}
@@ -3,11 +3,15 @@
 #[macro_use]
 mod gatt_properties;
 
-#[macro_use]
-mod gatt_uuid_hasher;
-
+pub mod builder;
 pub mod characteristic;
 pub mod descriptor;
+pub mod handle;
+pub mod handler;
+pub mod hash;
+pub mod notifier;
+pub mod security;
 pub mod service;
+pub mod subscribers;
 
 pub mod event;
@@ -0,0 +1,164 @@
+use futures::{channel::mpsc, prelude::*};
+use std::{
+    error, fmt,
+    sync::{Arc, Mutex},
+};
+
+use super::event::{IndicationError, NotifyMode, NotifySubscribe};
+
+/// How a `Notifier` copes with values coming in faster than the link takes them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NotifyPolicy {
+    /// `notify` waits for room in the bounded queue, so every value goes out, in order.
+    #[default]
+    Queue,
+    /// `notify` never waits: a value that has not gone out yet is replaced by the next one,
+    /// which suits sensors where only the latest reading matters.
+    LatestWins,
+}
+
+/// What became of a value passed to `Notifier::notify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Handed to the platform's queue; notifications are not acknowledged any further.
+    Queued,
+    /// Confirmed by the central, which only happens with indications.
+    Sent,
+    /// Waiting to go out in place of an older value, which was dropped.
+    Coalesced,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyError {
+    /// The central unsubscribed or disconnected.
+    Unsubscribed,
+    /// The central did not confirm an indication in time.
+    Timeout,
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            NotifyError::Unsubscribed => "the central is no longer subscribed",
+            NotifyError::Timeout => "the central did not confirm the indication in time",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl error::Error for NotifyError {}
+
+impl From<IndicationError> for NotifyError {
+    fn from(error: IndicationError) -> Self {
+        match error {
+            IndicationError::Timeout => NotifyError::Timeout,
+            IndicationError::NotIndicating | IndicationError::Disconnected => {
+                NotifyError::Unsubscribed
+            }
+        }
+    }
+}
+
+/// Sends values to a subscribed central, notifying or indicating them as it subscribed.
+///
+/// ```no_run
+/// # use bluster::gatt::{event::NotifySubscribe, notifier::{Notifier, NotifyPolicy}};
+/// # async fn example(subscription: NotifySubscribe) {
+/// let mut notifier = Notifier::new(subscription, NotifyPolicy::LatestWins);
+/// for reading in 0..100u8 {
+///     if notifier.notify(vec![reading]).await.is_err() {
+///         break;
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Notifier {
+    subscription: NotifySubscribe,
+    latest: Option<Latest>,
+}
+
+/// The value waiting to go out under `NotifyPolicy::LatestWins`.
+#[derive(Debug)]
+struct Latest {
+    value: Arc<Mutex<Option<Vec<u8>>>>,
+    /// Wakes the task sending the value up whenever one is put in an empty slot.
+    wake: mpsc::UnboundedSender<()>,
+}
+
+impl Notifier {
+    /// With `NotifyPolicy::LatestWins` this spawns a task on the Tokio runtime, which stops once
+    /// the central unsubscribes.
+    pub fn new(subscription: NotifySubscribe, policy: NotifyPolicy) -> Self {
+        let latest = match policy {
+            NotifyPolicy::Queue => None,
+            NotifyPolicy::LatestWins => {
+                let value = Arc::new(Mutex::new(None));
+                let (wake, mut woken) = mpsc::unbounded();
+                let mut subscription = subscription.clone();
+                let next = Arc::clone(&value);
+                tokio::spawn(async move {
+                    while woken.next().await.is_some() {
+                        let value = next.lock().unwrap().take();
+                        if let Some(value) = value {
+                            if deliver(&mut subscription, value).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                });
+                Some(Latest { value, wake })
+            }
+        };
+        Notifier {
+            subscription,
+            latest,
+        }
+    }
+
+    pub fn mode(&self) -> NotifyMode {
+        self.subscription.mode
+    }
+
+    /// Sends `value` according to the policy.
+    ///
+    /// With `NotifyPolicy::Queue`, notifications wait for room in the queue and indications
+    /// for the central's confirmation. With `NotifyPolicy::LatestWins`, this returns right away
+    /// and a failure only shows on the call after it.
+    pub async fn notify(&mut self, value: Vec<u8>) -> Result<Delivery, NotifyError> {
+        let latest = match self.latest {
+            Some(ref latest) => latest,
+            None => return deliver(&mut self.subscription, value).await,
+        };
+        if latest.wake.is_closed() {
+            return Err(NotifyError::Unsubscribed);
+        }
+        let replaced = latest.value.lock().unwrap().replace(value).is_some();
+        if replaced {
+            return Ok(Delivery::Coalesced);
+        }
+        latest
+            .wake
+            .unbounded_send(())
+            .map(|()| Delivery::Queued)
+            .map_err(|_| NotifyError::Unsubscribed)
+    }
+}
+
+async fn deliver(
+    subscription: &mut NotifySubscribe,
+    value: Vec<u8>,
+) -> Result<Delivery, NotifyError> {
+    match subscription.mode {
+        NotifyMode::Notify => subscription
+            .notification
+            .send(value)
+            .await
+            .map(|()| Delivery::Queued)
+            .map_err(|_| NotifyError::Unsubscribed),
+        NotifyMode::Indicate => {
+            subscription.indicate(value).await?;
+            Ok(Delivery::Sent)
+        }
+    }
+}
@@ -0,0 +1,29 @@
+/// How well the link to a central must be protected before an operation is allowed.
+///
+/// The levels are ordered, each one implying the ones below it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SecurityLevel {
+    /// Any link will do, even an unencrypted one.
+    #[default]
+    None,
+    /// The link is encrypted, possibly with keys from unauthenticated (Just Works) pairing.
+    Encrypted,
+    /// The link is encrypted with keys from pairing that was protected against MITM attacks.
+    Authenticated,
+    /// Like `Authenticated`, with keys from LE Secure Connections pairing.
+    SecureConnections,
+}
+
+/// The security level each operation on a characteristic or descriptor requires.
+///
+/// Descriptors cannot be subscribed to, so only `read` and `write` apply to them. Writes without
+/// response are not covered, as neither BlueZ nor CoreBluetooth can restrict them. CoreBluetooth
+/// cannot require more than `SecurityLevel::Encrypted`, so higher levels only get encryption
+/// there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Security {
+    pub read: SecurityLevel,
+    pub write: SecurityLevel,
+    pub notify: SecurityLevel,
+    pub indicate: SecurityLevel,
+}
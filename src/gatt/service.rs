@@ -1,20 +1,59 @@
-use super::characteristic::Characteristic;
-use std::collections::HashSet;
+use super::{builder::ServiceBuilder, characteristic::Characteristic};
 use uuid::Uuid;
 
+/// A service and its characteristics, which are laid out in the order given.
+///
+/// Several characteristics may share a UUID, e.g. the Report characteristics of a HID service.
 #[derive(Debug, Clone)]
 pub struct Service {
     pub(crate) uuid: Uuid,
     pub(crate) primary: bool,
-    pub(crate) characteristics: HashSet<Characteristic>,
+    pub(crate) characteristics: Vec<Characteristic>,
 }
 
 impl Service {
-    pub fn new(uuid: Uuid, primary: bool, characteristics: HashSet<Characteristic>) -> Self {
+    pub fn new(uuid: Uuid, primary: bool, characteristics: Vec<Characteristic>) -> Self {
         Service {
             uuid,
             primary,
             characteristics,
         }
     }
+
+    /// Starts building a secondary service; characteristics are checked by `build`.
+    ///
+    /// ```
+    /// # use futures::channel::mpsc;
+    /// # use bluster::{gatt::service::Service, SdpShortUuid};
+    /// # use uuid::Uuid;
+    /// let (sender, receiver) = mpsc::channel(1);
+    /// let service = Service::builder(Uuid::from_sdp_short_uuid(0x180Fu16))
+    ///     .primary()
+    ///     .characteristic(Uuid::from_sdp_short_uuid(0x2A19u16), |c| {
+    ///         c.sender(sender).read().notify().descriptor(
+    ///             Uuid::from_sdp_short_uuid(0x2901u16),
+    ///             |d| d.value("Battery level"),
+    ///         )
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder(uuid: Uuid) -> ServiceBuilder {
+        ServiceBuilder::new(uuid)
+    }
+
+    /// Spawns the servers of `managed` and `handler` attributes; peripherals call it when the
+    /// service is added, as building one must not need a Tokio runtime.
+    pub(crate) fn start(&self) {
+        for characteristic in &self.characteristics {
+            let descriptors = characteristic.descriptors.iter();
+            let servers = descriptors.map(|descriptor| &descriptor.server);
+            for server in std::iter::once(&characteristic.server)
+                .chain(servers)
+                .flatten()
+            {
+                server.start();
+            }
+        }
+    }
 }
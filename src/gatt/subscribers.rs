@@ -0,0 +1,43 @@
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use super::event::{NotifyMode, RequestContext, SubscriptionId};
+
+/// A central subscribed to a characteristic, as returned by `Peripheral::subscribers`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscriber {
+    pub id: SubscriptionId,
+    /// The characteristic and the central, as far as the backend knows it.
+    pub context: RequestContext,
+    pub mode: NotifyMode,
+}
+
+/// The subscriptions a backend has announced and not yet ended.
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl Subscribers {
+    pub fn add(&self, subscriber: Subscriber) {
+        self.subscribers.lock().unwrap().push(subscriber);
+    }
+
+    pub fn remove(&self, id: SubscriptionId) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.id != id);
+    }
+
+    /// The subscribers of every characteristic with the given UUID.
+    pub fn of(&self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|subscriber| subscriber.context.uuid == *characteristic)
+            .cloned()
+            .collect()
+    }
+}
@@ -4,7 +4,7 @@
 
 mod error;
 pub mod gatt;
-mod peripheral;
+pub mod peripheral;
 mod uuid;
 
-pub use self::{error::*, peripheral::Peripheral, uuid::*};
+pub use self::{error::*, peripheral::DefaultPeripheral as Peripheral, uuid::*};
@@ -1,17 +1,23 @@
 use dbus::{
     arg::{messageitem::MessageItem, RefArg, Variant},
+    message::MatchRule,
+    nonblock::MsgMatch,
     Path,
 };
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, fmt, sync::Arc};
 
 use super::{
     connection::Connection,
     constants::{
-        ADAPTER_IFACE, DBUS_OBJECTMANAGER_IFACE, DBUS_PROPERTIES_IFACE,
-        LE_ADVERTISING_MANAGER_IFACE,
+        ADAPTER_IFACE, BLUEZ_SERVICE_NAME, DBUS_OBJECTMANAGER_IFACE, DBUS_PROPERTIES_IFACE,
+        GATT_GATT_MANAGER_IFACE, LE_ADVERTISING_MANAGER_IFACE,
     },
+    options::AdapterSelector,
+};
+use crate::{
+    peripheral::event::{Event, EventBroadcaster, State},
+    Error, ErrorType,
 };
-use crate::Error;
 
 #[derive(Debug, Clone)]
 pub struct Adapter {
@@ -19,27 +25,123 @@ pub struct Adapter {
     connection: Arc<Connection>,
 }
 
-type ManagedObjectsProps =
-    HashMap<Path<'static>, HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>>;
+/// A Bluetooth controller known to bluetoothd.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdapterInfo {
+    /// The D-Bus object path, e.g. `/org/bluez/hci0`.
+    pub path: String,
+    pub address: String,
+    pub name: String,
+    pub alias: String,
+    pub powered: bool,
+    pub capabilities: AdapterCapabilities,
+}
+
+/// The BlueZ managers an adapter exposes; a peripheral needs both.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AdapterCapabilities {
+    /// The adapter can advertise, through `LEAdvertisingManager1`.
+    pub le_advertising: bool,
+    /// The adapter can host a GATT application, through `GattManager1`.
+    pub gatt_server: bool,
+}
+
+impl AdapterInfo {
+    fn is_capable(&self) -> bool {
+        self.capabilities.le_advertising && self.capabilities.gatt_server
+    }
+
+    fn matches(&self, selector: &AdapterSelector) -> bool {
+        match selector {
+            AdapterSelector::Name(name) => &self.name == name,
+            AdapterSelector::Address(address) => self.address.eq_ignore_ascii_case(address),
+            AdapterSelector::Path(path) => &self.path == path,
+        }
+    }
+}
+
+/// Forwards adapter signals to an `EventBroadcaster` for as long as it is kept around.
+pub struct AdapterWatch {
+    _matches: Vec<MsgMatch>,
+}
+
+impl fmt::Debug for AdapterWatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AdapterWatch")
+    }
+}
+
+type PropMap = HashMap<String, Variant<Box<dyn RefArg>>>;
+type ManagedObjectsProps = HashMap<Path<'static>, HashMap<String, PropMap>>;
 
 impl Adapter {
-    async fn find_adapter(connection: &Arc<Connection>) -> Result<Path<'static>, Error> {
+    /// Lists every adapter, ordered by object path.
+    pub async fn list(connection: &Connection) -> Result<Vec<AdapterInfo>, Error> {
         let path = "/".into();
         let proxy = connection.get_bluez_proxy(&path);
 
         let (props,): (ManagedObjectsProps,) = proxy
             .method_call(DBUS_OBJECTMANAGER_IFACE, "GetManagedObjects", ())
             .await?;
-        Ok(props
+        let mut adapters: Vec<AdapterInfo> = props
+            .iter()
+            .filter_map(|(path, interfaces)| {
+                let adapter = interfaces.get(ADAPTER_IFACE)?;
+                let string = |name| {
+                    adapter
+                        .get(name)
+                        .and_then(|value| value.0.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                Some(AdapterInfo {
+                    path: path.to_string(),
+                    address: string("Address"),
+                    name: string("Name"),
+                    alias: string("Alias"),
+                    powered: adapter
+                        .get("Powered")
+                        .and_then(|value| value.0.as_u64())
+                        .is_some_and(|powered| powered != 0),
+                    capabilities: AdapterCapabilities {
+                        le_advertising: interfaces.contains_key(LE_ADVERTISING_MANAGER_IFACE),
+                        gatt_server: interfaces.contains_key(GATT_GATT_MANAGER_IFACE),
+                    },
+                })
+            })
+            .collect();
+        adapters.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(adapters)
+    }
+
+    async fn find_adapter(
+        connection: &Arc<Connection>,
+        selector: Option<&AdapterSelector>,
+    ) -> Result<Path<'static>, Error> {
+        let mut adapters = Adapter::list(connection)
+            .await?
             .into_iter()
-            .find(|(_path, props)| props.contains_key(LE_ADVERTISING_MANAGER_IFACE))
-            .map(|(path, _props)| path)
-            .expect("LEAdvertisingManager1 interface not found"))
+            .filter(AdapterInfo::is_capable);
+
+        let adapter = match selector {
+            Some(selector) => adapters.find(|adapter| adapter.matches(selector)),
+            None => adapters.next(),
+        };
+        adapter.map(|adapter| adapter.path.into()).ok_or_else(|| {
+            let description = match selector {
+                Some(selector) => format!("no LE capable adapter matches {:?}", selector),
+                None => "no LE capable adapter found".to_string(),
+            };
+            Error::new("AdapterNotFound", description, ErrorType::Bluez)
+        })
     }
 
     #[allow(clippy::new_ret_no_self)]
-    pub async fn new(connection: Arc<Connection>) -> Result<Self, Error> {
-        Adapter::find_adapter(&connection)
+    pub async fn new(
+        connection: Arc<Connection>,
+        selector: Option<&AdapterSelector>,
+    ) -> Result<Self, Error> {
+        Adapter::find_adapter(&connection, selector)
             .await
             .map(|object_path| Adapter {
                 object_path,
@@ -47,6 +149,65 @@ impl Adapter {
             })
     }
 
+    /// Reports power and discoverable changes of this adapter, and adapters coming and going.
+    pub async fn watch(&self, events: Arc<EventBroadcaster>) -> Result<AdapterWatch, Error> {
+        let connection = self.connection.default();
+
+        let mut rule = MatchRule::new_signal(DBUS_PROPERTIES_IFACE, "PropertiesChanged");
+        rule.sender = Some(BLUEZ_SERVICE_NAME.into());
+        rule.path = Some(self.object_path.clone());
+        let changed_events = Arc::clone(&events);
+        let properties_changed = connection.add_match(rule).await?.cb(
+            move |_, (interface, changed, _): (String, PropMap, Vec<String>)| {
+                if interface != ADAPTER_IFACE {
+                    return true;
+                }
+                let flag = |name| changed.get(name).and_then(|value| value.0.as_u64());
+                if let Some(powered) = flag("Powered") {
+                    changed_events.broadcast(Event::StateChange(if powered != 0 {
+                        State::PoweredOn
+                    } else {
+                        State::PoweredOff
+                    }));
+                }
+                if let Some(discoverable) = flag("Discoverable") {
+                    changed_events.broadcast(Event::DiscoverableChange(discoverable != 0));
+                }
+                true
+            },
+        );
+
+        let mut rule = MatchRule::new_signal(DBUS_OBJECTMANAGER_IFACE, "InterfacesAdded");
+        rule.sender = Some(BLUEZ_SERVICE_NAME.into());
+        let added_events = Arc::clone(&events);
+        let interfaces_added = connection.add_match(rule).await?.cb(
+            move |_, (path, interfaces): (Path<'static>, HashMap<String, PropMap>)| {
+                if interfaces.contains_key(ADAPTER_IFACE) {
+                    added_events.broadcast(Event::AdapterAdded(path.to_string()));
+                }
+                true
+            },
+        );
+
+        let mut rule = MatchRule::new_signal(DBUS_OBJECTMANAGER_IFACE, "InterfacesRemoved");
+        rule.sender = Some(BLUEZ_SERVICE_NAME.into());
+        let interfaces_removed = connection.add_match(rule).await?.cb(
+            move |_, (path, interfaces): (Path<'static>, Vec<String>)| {
+                if interfaces
+                    .iter()
+                    .any(|interface| interface == ADAPTER_IFACE)
+                {
+                    events.broadcast(Event::AdapterRemoved(path.to_string()));
+                }
+                true
+            },
+        );
+
+        Ok(AdapterWatch {
+            _matches: vec![properties_changed, interfaces_added, interfaces_removed],
+        })
+    }
+
     pub async fn powered(self: &Self, on: bool) -> Result<(), Error> {
         let proxy = self.connection.get_bluez_proxy(&self.object_path);
         proxy
@@ -15,7 +15,7 @@ use std::{
 use super::{
     common,
     connection::Connection,
-    constants::{LE_ADVERTISEMENT_IFACE, LE_ADVERTISING_MANAGER_IFACE, PATH_BASE},
+    constants::{LE_ADVERTISEMENT_IFACE, LE_ADVERTISING_MANAGER_IFACE},
 };
 use crate::Error;
 
@@ -31,7 +31,7 @@ pub struct Advertisement {
 }
 
 impl Advertisement {
-    pub fn new(connection: Arc<Connection>, adapter: Path<'static>) -> Self {
+    pub fn new(connection: Arc<Connection>, adapter: Path<'static>, path_prefix: &Path) -> Self {
         let mut tree = common::Tree::new();
         let is_advertising = Arc::new(AtomicBool::new(false));
         let is_advertising_release = is_advertising.clone();
@@ -42,7 +42,7 @@ impl Advertisement {
         let uuids = Arc::new(Mutex::new(None));
         let uuids_property = uuids.clone();
 
-        let object_path: Path = format!("{}/advertisement{:04}", PATH_BASE, 0).into();
+        let object_path: Path = format!("{}/advertisement{:04}", path_prefix, 0).into();
 
         let iface_token = tree.register(LE_ADVERTISEMENT_IFACE, |b| {
             b.method_with_cr_async("Release", (), (), move |mut ctx, _cr, ()| {
@@ -69,30 +69,31 @@ impl Advertisement {
         let ifaces = [iface_token, tree.object_manager()];
         tree.insert(object_path.clone(), &ifaces, ());
 
-        let tree = Arc::new(Mutex::new(tree));
-
-        {
-            let tree = tree.clone();
-            let mut match_rule = MatchRule::new_method_call();
-            match_rule.path = Some(object_path.clone());
-            connection.default.start_receive(
-                match_rule,
-                Box::new(move |msg, conn| {
-                    tree.lock().unwrap().handle_message(msg, conn).unwrap();
-                    true
-                }),
-            );
-        }
-
-        Advertisement {
+        let advertisement = Advertisement {
             connection,
             adapter,
             object_path,
-            tree,
+            tree: Arc::new(Mutex::new(tree)),
             is_advertising,
             name,
             uuids,
-        }
+        };
+        advertisement.attach();
+        advertisement
+    }
+
+    /// Serves the advertisement object on the current connection.
+    pub fn attach(&self) {
+        let tree = self.tree.clone();
+        let mut match_rule = MatchRule::new_method_call();
+        match_rule.path = Some(self.object_path.clone());
+        self.connection.default().start_receive(
+            match_rule,
+            Box::new(move |msg, conn| {
+                tree.lock().unwrap().handle_message(msg, conn).unwrap();
+                true
+            }),
+        );
     }
 
     pub fn add_name<T: Into<String>>(self: &Self, name: T) {
@@ -1,13 +1,54 @@
-use std::fmt;
-use std::sync::Arc;
+use futures::{
+    channel::mpsc,
+    future::{self, AbortHandle},
+};
+use std::{
+    error, fmt,
+    future::Future,
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use dbus::{nonblock::SyncConnection, Path};
 
-use super::constants::{BLUEZ_DBUS_TIMEOUT, BLUEZ_SERVICE_NAME};
-use crate::Error;
+use super::{constants::BLUEZ_SERVICE_NAME, io};
+use crate::{Error, ErrorType};
+
+/// The D-Bus bus bluetoothd is reached over.
+#[derive(Clone, Default)]
+pub enum Bus {
+    /// The system bus, where bluetoothd normally runs.
+    #[default]
+    System,
+    /// The session bus of the current user.
+    Session,
+    /// A bus at a D-Bus address, e.g. `unix:path=/var/run/dbus/system_bus_socket`.
+    Address(String),
+    /// A connection set up by the application, which is responsible for keeping its I/O
+    /// resource running.
+    Connection(Arc<SyncConnection>),
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bus::System => write!(f, "System"),
+            Bus::Session => write!(f, "Session"),
+            Bus::Address(address) => write!(f, "Address({:?})", address),
+            Bus::Connection(_) => write!(f, "Connection"),
+        }
+    }
+}
+
+type Current = (Arc<SyncConnection>, Option<AbortHandle>);
 
 pub struct Connection {
-    pub default: Arc<SyncConnection>,
+    bus: Bus,
+    timeout: Duration,
+    current: Mutex<Current>,
+    lost: mpsc::UnboundedSender<String>,
+    lost_receiver: Mutex<Option<mpsc::UnboundedReceiver<String>>>,
 }
 
 impl fmt::Debug for Connection {
@@ -17,17 +58,96 @@ impl fmt::Debug for Connection {
 }
 
 impl<'a> Connection {
-    pub fn new() -> Result<Self, Error> {
-        let (resource, default) = dbus_tokio::connection::new_system_sync()?;
-        tokio::spawn(async {
-            let err = resource.await;
-            panic!("Lost connection to D-Bus: {}", err);
-        });
+    pub fn new(bus: Bus, timeout: Duration) -> Result<Self, Error> {
+        let (lost, lost_receiver) = mpsc::unbounded();
+        let current = connect(&bus, &lost)?;
+
+        Ok(Connection {
+            bus,
+            timeout,
+            current: Mutex::new(current),
+            lost,
+            lost_receiver: Mutex::new(Some(lost_receiver)),
+        })
+    }
+
+    /// The connection currently in use, which changes when `reconnect` succeeds.
+    pub fn default(&self) -> Arc<SyncConnection> {
+        Arc::clone(&self.current.lock().unwrap().0)
+    }
 
-        Ok(Connection { default })
+    /// Replaces a lost connection with a fresh one to the same bus.
+    pub fn reconnect(&self) -> Result<(), Error> {
+        if let Bus::Connection(_) = self.bus {
+            return Err(Error::new(
+                "CannotReconnect",
+                "the D-Bus connection is owned by the application",
+                ErrorType::Bluez,
+            ));
+        }
+        let current = connect(&self.bus, &self.lost)?;
+        let (_, resource) = mem::replace(&mut *self.current.lock().unwrap(), current);
+        if let Some(resource) = resource {
+            resource.abort();
+        }
+        Ok(())
     }
 
-    pub fn get_bluez_proxy(&'a self, path: &'a Path) -> dbus::nonblock::Proxy<&'a SyncConnection> {
-        dbus::nonblock::Proxy::new(BLUEZ_SERVICE_NAME, path, BLUEZ_DBUS_TIMEOUT, &self.default)
+    /// Yields the reason every time the connection to the bus is lost; only the first caller
+    /// gets the stream.
+    pub fn lost(&self) -> Option<mpsc::UnboundedReceiver<String>> {
+        self.lost_receiver.lock().unwrap().take()
     }
+
+    pub fn get_bluez_proxy(
+        &'a self,
+        path: &'a Path,
+    ) -> dbus::nonblock::Proxy<'a, Arc<SyncConnection>> {
+        dbus::nonblock::Proxy::new(BLUEZ_SERVICE_NAME, path, self.timeout, self.default())
+    }
+
+    pub fn get_dbus_proxy(&self) -> dbus::nonblock::Proxy<'static, Arc<SyncConnection>> {
+        dbus::nonblock::Proxy::new(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            self.timeout,
+            self.default(),
+        )
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(resource) = self.current.lock().unwrap().1.take() {
+            resource.abort();
+        }
+    }
+}
+
+fn connect(bus: &Bus, lost: &mpsc::UnboundedSender<String>) -> Result<Current, Error> {
+    Ok(match bus {
+        Bus::System => spawn(dbus_tokio::connection::new_system_sync()?, lost),
+        Bus::Session => spawn(dbus_tokio::connection::new_session_sync()?, lost),
+        Bus::Address(address) => spawn(io::new_sync(address)?, lost),
+        Bus::Connection(connection) => (Arc::clone(connection), None),
+    })
+}
+
+fn spawn<R, E>(
+    (resource, connection): (R, Arc<SyncConnection>),
+    lost: &mpsc::UnboundedSender<String>,
+) -> Current
+where
+    R: Future<Output = Box<E>> + Send + 'static,
+    E: error::Error + Send + Sync + ?Sized + 'static,
+{
+    let (resource, abort_handle) = future::abortable(resource);
+    let lost = lost.clone();
+    tokio::spawn(async move {
+        if let Ok(err) = resource.await {
+            log::error!("Lost connection to D-Bus: {}", err);
+            lost.unbounded_send(err.to_string()).ok();
+        }
+    });
+    (connection, Some(abort_handle))
 }
@@ -17,11 +17,19 @@ pub const GATT_GATT_MANAGER_IFACE: &str = "org.bluez.GattManager1";
 
 pub const BLUEZ_ERROR_FAILED: &str = "org.bluez.Error.Failed";
 // pub const BLUEZ_ERROR_INPROGRESS: &str = "org.bluez.Error.InProgress";
-// pub const BLUEZ_ERROR_NOTPERMITTED: &str = "org.bluez.Error.NotPermitted";
-// pub const BLUEZ_ERROR_NOTAUTHORIZED: &str = "org.bluez.Error.NotAuthorized";
-// pub const BLUEZ_ERROR_INVALIDOFFSET: &str = "org.bluez.Error.InvalidOffset";
+pub const BLUEZ_ERROR_NOTPERMITTED: &str = "org.bluez.Error.NotPermitted";
+pub const BLUEZ_ERROR_NOTAUTHORIZED: &str = "org.bluez.Error.NotAuthorized";
+pub const BLUEZ_ERROR_INVALIDOFFSET: &str = "org.bluez.Error.InvalidOffset";
+pub const BLUEZ_ERROR_INVALIDVALUELENGTH: &str = "org.bluez.Error.InvalidValueLength";
 pub const BLUEZ_ERROR_NOTSUPPORTED: &str = "org.bluez.Error.NotSupported";
+// Not BlueZ errors: bluetoothd answers names it does not know with Unlikely Error.
+pub const BLUEZ_ERROR_UNLIKELY: &str = "org.bluez.Error.UnlikelyError";
+pub const BLUEZ_ERROR_VALUENOTALLOWED: &str = "org.bluez.Error.ValueNotAllowed";
 
 pub const PATH_BASE: &str = "/org/bluez/example";
 
 pub const BLUEZ_DBUS_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const NOTIFICATION_CHANNEL_CAPACITY: usize = 1;
@@ -0,0 +1,174 @@
+//! Sockets handed to bluetoothd by `AcquireWrite` and `AcquireNotify`, which carry one ATT value
+//! per packet instead of a D-Bus message each.
+
+use dbus::arg::OwnedFd;
+use futures::{
+    prelude::*,
+    task::{Context, Poll},
+};
+use std::{
+    io,
+    os::unix::io::RawFd,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::io::Registration;
+
+/// The ATT MTU before any exchange, for when bluetoothd leaves it out of the options.
+pub const DEFAULT_MTU: u16 = 23;
+/// The opcode and handle in front of every notified value.
+const NOTIFICATION_HEADER_LEN: u16 = 3;
+
+/// Our end of a socket pair whose other end went to bluetoothd.
+///
+/// As a stream it yields the packets bluetoothd writes and ends when it closes its end; as a
+/// sink it sends packets of up to `mtu - 3` bytes.
+pub struct Socket {
+    // Dropped before `fd`, so the descriptor is deregistered before it is closed.
+    registration: Registration,
+    fd: Fd,
+    mtu: u16,
+    pending: Option<Vec<u8>>,
+    /// Cleared on drop, for the `WriteAcquired` and `NotifyAcquired` properties.
+    acquired: Arc<AtomicBool>,
+}
+
+struct Fd(RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Creates a socket pair, returning our end and the one to reply to bluetoothd with.
+pub fn pair(mtu: u16, acquired: &Arc<AtomicBool>) -> io::Result<(Socket, OwnedFd)> {
+    let mut fds: [RawFd; 2] = [0; 2];
+    let result = unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = Fd(fds[0]);
+    let remote = unsafe { OwnedFd::new(fds[1]) };
+    let registration = Registration::new(&mio::unix::EventedFd(&fd.0))?;
+    acquired.store(true, Ordering::Relaxed);
+    let socket = Socket {
+        registration,
+        fd,
+        mtu,
+        pending: None,
+        acquired: Arc::clone(acquired),
+    };
+    Ok((socket, remote))
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        self.acquired.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Stream for Socket {
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut packet = vec![0; usize::from(self.mtu)];
+        loop {
+            let read = unsafe {
+                libc::recv(
+                    self.fd.0,
+                    packet.as_mut_ptr() as *mut libc::c_void,
+                    packet.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if read > 0 {
+                packet.truncate(read as usize);
+                return Poll::Ready(Some(packet));
+            }
+            // bluetoothd never sends empty packets, so this is it closing its end.
+            if read == 0 {
+                return Poll::Ready(None);
+            }
+            match io::Error::last_os_error().kind() {
+                io::ErrorKind::Interrupted => continue,
+                io::ErrorKind::WouldBlock => {}
+                _ => return Poll::Ready(None),
+            }
+            // Readiness is edge-triggered, so only wait once the socket has been drained.
+            match self.registration.poll_read_ready(cx) {
+                Poll::Ready(Ok(_)) => continue,
+                Poll::Ready(Err(_)) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Sink<Vec<u8>> for Socket {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: Vec<u8>) -> io::Result<()> {
+        let max_len = self.mtu.saturating_sub(NOTIFICATION_HEADER_LEN);
+        if packet.len() > usize::from(max_len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} bytes do not fit in a notification of at most {}",
+                    packet.len(),
+                    max_len
+                ),
+            ));
+        }
+        self.pending = Some(packet);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        while let Some(ref packet) = self.pending {
+            // MSG_NOSIGNAL: a closed socket should fail the send, not raise SIGPIPE.
+            let sent = unsafe {
+                libc::send(
+                    self.fd.0,
+                    packet.as_ptr() as *const libc::c_void,
+                    packet.len(),
+                    libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+                )
+            };
+            if sent >= 0 {
+                self.pending = None;
+                break;
+            }
+            let error = io::Error::last_os_error();
+            match error.kind() {
+                io::ErrorKind::Interrupted => continue,
+                io::ErrorKind::WouldBlock => {}
+                _ => return Poll::Ready(Err(error)),
+            }
+            match self.registration.poll_write_ready(cx) {
+                Poll::Ready(Ok(_)) => continue,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
@@ -1,15 +1,21 @@
 use dbus::{
-    arg::{RefArg, Variant},
-    channel::Sender,
-    nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged,
-    tree::MethodErr,
-    Message, Path,
+    arg::Variant, channel::Sender,
+    nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, tree::MethodErr, Message,
+    Path,
 };
 use futures::{
     channel::{mpsc, oneshot},
+    future::{self, Either},
     prelude::*,
+    stream,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
-use std::{collections::HashMap, sync::Arc};
 
 use super::{
     super::{
@@ -18,11 +24,22 @@ use super::{
         constants::{BLUEZ_ERROR_FAILED, BLUEZ_ERROR_NOTSUPPORTED, GATT_CHARACTERISTIC_IFACE},
         Connection,
     },
+    acquired, authorize,
     flags::Flags,
+    offset, prepare_authorize, read_long, read_value_reply, reply, request_context, write_kind,
+    write_long, OptionsMap,
+};
+use crate::{
+    gatt,
+    gatt::{
+        event::{
+            Access, Indication, NotifyMode, PacketSink, PacketStream, RequestContext,
+            SubscriptionId, WriteKind, INDICATION_TIMEOUT,
+        },
+        subscribers::{Subscriber, Subscribers},
+    },
+    Error,
 };
-use crate::{gatt, Error};
-
-type OptionsMap = HashMap<String, Variant<Box<dyn RefArg>>>;
 
 #[derive(Debug, Clone)]
 pub struct Characteristic {
@@ -36,11 +53,13 @@ impl Characteristic {
         characteristic: &Arc<gatt::characteristic::Characteristic>,
         service: &Path<'static>,
         index: u64,
+        notification_channel_capacity: usize,
+        subscribers: &Arc<Subscribers>,
     ) -> Result<Self, Error> {
         let object_path: Path = format!("{}/characteristic{:04}", service, index).into();
         let object_path_data = common::GattDataType::Characteristic(Arc::clone(characteristic));
         // Setup a channel for notifications
-        let (message_sender, message_receiver) = mpsc::channel(1);
+        let (message_sender, message_receiver) = mpsc::channel(notification_channel_capacity);
         {
             let object_path = object_path.clone();
             let connection = Arc::clone(connection);
@@ -63,12 +82,20 @@ impl Characteristic {
                             &"PropertiesChanged".into(),
                         );
                         signal_message.append_all(signal);
-                        connection.default.send(signal_message).ok();
+                        if connection.default().send(signal_message).is_err() {
+                            log::warn!("Could not send a notification to bluetoothd");
+                        }
                     })
                     .collect::<()>(),
             );
         }
 
+        // Set between `StartNotify` and `StopNotify`.
+        let subscription: Arc<Mutex<Option<Subscription>>> = Arc::new(Mutex::new(None));
+        // Set while bluetoothd holds a socket from `AcquireWrite` or `AcquireNotify`.
+        let write_acquired = Arc::new(AtomicBool::new(false));
+        let notify_acquired = Arc::new(AtomicBool::new(false));
+
         let iface_token = tree.register::<GattDataType, _, _>(GATT_CHARACTERISTIC_IFACE, |b| {
             let message_sender = message_sender.clone();
             b.method_with_cr_async(
@@ -76,21 +103,36 @@ impl Characteristic {
                 ("options",),
                 ("value",),
                 |mut ctx, cr, (options,): (OptionsMap,)| {
-                    let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
                     let characteristic = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_characteristic();
+                    let offset = offset(&options);
+                    let context = request_context(characteristic.uuid, &options);
                     async move {
-                        let event_sender = characteristic
+                        if let Some(ref value) = characteristic.value {
+                            return read_value_reply(value, offset);
+                        }
+                        let mut event_sender = characteristic
                             .properties
                             .read
                             .clone()
+                            .map(|read| read.sender())
                             .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                        authorize(
+                            characteristic.authorize,
+                            &event_sender,
+                            &context,
+                            Access::Read,
+                        )
+                        .await?;
+                        if characteristic.long_value.is_some() {
+                            return read_long(event_sender, context, offset).await;
+                        }
                         let (sender, receiver) = oneshot::channel();
                         event_sender
-                            .sender()
                             .send(gatt::event::Event::ReadRequest(gatt::event::ReadRequest {
+                                context,
                                 offset,
                                 response: sender,
                             }))
@@ -99,10 +141,7 @@ impl Characteristic {
                         receiver
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
-                            })
+                            .and_then(reply)
                     }
                     .map(move |result| ctx.reply(result))
                 },
@@ -112,64 +151,164 @@ impl Characteristic {
                 ("data", "options"),
                 ("value",),
                 |mut ctx, cr, (data, options): (Vec<u8>, OptionsMap)| {
-                    let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
                     let characteristic = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_characteristic();
+                    let offset = offset(&options);
+                    let prepare_authorize = prepare_authorize(&options);
+                    // A prepared write is part of a reliable write, whatever its type says.
+                    let kind = if prepare_authorize {
+                        WriteKind::Reliable
+                    } else {
+                        write_kind(&options)
+                    };
+                    let context = request_context(characteristic.uuid, &options);
                     async move {
-                        let event_sender = characteristic
+                        let mut event_sender = characteristic
                             .properties
                             .write
                             .clone()
+                            .map(|write| write.sender())
                             .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                        authorize(
+                            characteristic.authorize,
+                            &event_sender,
+                            &context,
+                            Access::Write(kind),
+                        )
+                        .await?;
+                        if prepare_authorize {
+                            // bluetoothd queues the data itself and writes it on execution.
+                            return Ok((vec![],));
+                        }
+                        if let Some(ref long_value) = characteristic.long_value {
+                            let read_sender = characteristic
+                                .properties
+                                .read
+                                .clone()
+                                .map(|read| read.sender());
+                            return write_long(
+                                long_value,
+                                read_sender,
+                                event_sender,
+                                context,
+                                offset,
+                                data,
+                                kind,
+                            )
+                            .await;
+                        }
                         let (sender, receiver) = oneshot::channel();
                         event_sender
-                            .sender()
                             .send(gatt::event::Event::WriteRequest(
                                 gatt::event::WriteRequest {
+                                    context,
                                     data,
                                     offset,
-                                    without_response: false,
+                                    kind,
                                     response: sender,
                                 },
                             ))
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                        if kind == WriteKind::Command {
+                            // bluetoothd does not wait for a reply to a write command.
+                            return Ok((vec![],));
+                        }
                         receiver
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
-                            })
+                            .and_then(reply)
                     }
                     .map(move |result| ctx.reply(result))
                 },
             );
+            let start_subscription = Arc::clone(&subscription);
+            let start_subscribers = Arc::clone(subscribers);
             b.method_with_cr_async("StartNotify", (), (), move |mut ctx, cr, ()| {
                 let characteristic = cr
                     .data_mut::<GattDataType>(ctx.path())
                     .unwrap()
                     .get_characteristic();
                 let message_sender = message_sender.clone();
+                let subscription = Arc::clone(&start_subscription);
+                let subscribers = Arc::clone(&start_subscribers);
                 async move {
-                    let (sender, mut receiver) = mpsc::channel(1);
+                    let properties = &characteristic.properties;
+                    let (mode, mut event_sender) =
+                        match (properties.notify.clone(), properties.indicate.clone()) {
+                            (Some(notify), _) => (NotifyMode::Notify, notify),
+                            (None, Some(indicate)) => (NotifyMode::Indicate, indicate),
+                            (None, None) => {
+                                return Err(MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))
+                            }
+                        };
+                    let (stopped, stop) = oneshot::channel();
+                    let (sender, receiver) = mpsc::channel(notification_channel_capacity);
+                    // Dropping the receivers on `StopNotify` lets senders know the central left.
+                    let (indications, confirmations) = if mode == NotifyMode::Indicate {
+                        let (confirmation_sender, confirmation_receiver) = mpsc::unbounded();
+                        let (indication_sender, indication_receiver) =
+                            mpsc::channel(notification_channel_capacity);
+                        // Notifications go out as indications too, so they wait for their turn
+                        // and take their confirmation with them.
+                        let values = stream::select(
+                            receiver.map(|value| (value, None)),
+                            indication_receiver
+                                .map(|Indication { value, confirmed }| (value, Some(confirmed))),
+                        )
+                        .take_until(stop);
+                        tokio::spawn(forward_indications(
+                            values,
+                            confirmation_receiver,
+                            message_sender,
+                        ));
+                        (Some(indication_sender), Some(confirmation_sender))
+                    } else {
+                        let mut receiver = receiver.take_until(stop);
+                        tokio::spawn(async move {
+                            while let Some(notification) = receiver.next().await {
+                                let mut message_sender = message_sender.clone();
+                                let _ = message_sender.send(notification).await;
+                            }
+                        });
+                        (None, None)
+                    };
+                    // bluetoothd does not say which central subscribed.
+                    let context = RequestContext::new(characteristic.uuid);
+                    let id = SubscriptionId::next();
+                    let previous = subscription.lock().unwrap().replace(Subscription {
+                        id,
+                        _stopped: stopped,
+                        confirmations,
+                    });
+                    // Calling it again replaces the subscription, which the handler hears of
+                    // before the new one.
+                    if let Some(previous) = previous {
+                        subscribers.remove(previous.id);
+                        event_sender
+                            .send(gatt::event::Event::NotifyUnsubscribe(
+                                gatt::event::NotifyUnsubscribe {
+                                    id: previous.id,
+                                    context: context.clone(),
+                                },
+                            ))
+                            .await
+                            .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                    }
+                    subscribers.add(Subscriber {
+                        id,
+                        context: context.clone(),
+                        mode,
+                    });
                     let notify_subscribe = gatt::event::NotifySubscribe {
+                        id,
+                        context,
                         notification: sender,
+                        mode,
+                        indications,
                     };
-                    tokio::spawn(async move {
-                        while let Some(notification) = receiver.next().await {
-                            let mut message_sender = message_sender.clone();
-                            let _ = message_sender.send(notification).await;
-                        }
-                    });
-                    let mut event_sender = characteristic
-                        .properties
-                        .notify
-                        .clone()
-                        .or_else(|| characteristic.properties.indicate.clone())
-                        .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
                     event_sender
                         .send(gatt::event::Event::NotifySubscribe(notify_subscribe))
                         .await
@@ -178,12 +317,22 @@ impl Characteristic {
                 }
                 .map(move |result| ctx.reply(result))
             });
-            b.method_with_cr_async("StopNotify", (), (), |mut ctx, cr, ()| {
+            let stop_subscription = Arc::clone(&subscription);
+            let stop_subscribers = Arc::clone(subscribers);
+            b.method_with_cr_async("StopNotify", (), (), move |mut ctx, cr, ()| {
                 let characteristic = cr
                     .data_mut::<GattDataType>(ctx.path())
                     .unwrap()
                     .get_characteristic();
+                // Closes the subscription's channels, failing any indication in flight.
+                let stopped = stop_subscription.lock().unwrap().take();
+                let subscribers = Arc::clone(&stop_subscribers);
                 async move {
+                    let id = match stopped {
+                        Some(stopped) => stopped.id,
+                        None => return Ok(()),
+                    };
+                    subscribers.remove(id);
                     let mut event_sender = characteristic
                         .properties
                         .notify
@@ -191,20 +340,117 @@ impl Characteristic {
                         .or_else(|| characteristic.properties.indicate.clone())
                         .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
                     event_sender
-                        .send(gatt::event::Event::NotifyUnsubscribe)
+                        .send(gatt::event::Event::NotifyUnsubscribe(
+                            gatt::event::NotifyUnsubscribe {
+                                id,
+                                context: RequestContext::new(characteristic.uuid),
+                            },
+                        ))
                         .await
                         .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
                         .map(|_| ())
                 }
                 .map(move |result| ctx.reply(result))
             });
+            let subscription = Arc::clone(&subscription);
+            // bluetoothd calls it for every indication a central confirms.
+            b.method("Confirm", (), (), move |_ctx, _data, ()| {
+                let subscription = subscription.lock().unwrap();
+                let confirmations = subscription
+                    .as_ref()
+                    .and_then(|subscription| subscription.confirmations.as_ref());
+                if let Some(confirmations) = confirmations {
+                    confirmations.unbounded_send(()).ok();
+                }
+                Ok(())
+            });
+            if characteristic.acquire_write {
+                let method_acquired = Arc::clone(&write_acquired);
+                b.property("WriteAcquired")
+                    .get(move |_ctx, _data| Ok(write_acquired.load(Ordering::Relaxed)));
+                b.method_with_cr_async(
+                    "AcquireWrite",
+                    ("options",),
+                    ("fd", "mtu"),
+                    move |mut ctx, cr, (options,): (OptionsMap,)| {
+                        let characteristic = cr
+                            .data_mut::<GattDataType>(ctx.path())
+                            .unwrap()
+                            .get_characteristic();
+                        let context = request_context(characteristic.uuid, &options);
+                        let write_acquired = Arc::clone(&method_acquired);
+                        async move {
+                            let mut event_sender = characteristic
+                                .properties
+                                .write
+                                .clone()
+                                .map(|write| write.sender())
+                                .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                            let mtu = context.mtu.unwrap_or(acquired::DEFAULT_MTU);
+                            let (socket, fd) = acquired::pair(mtu, &write_acquired)
+                                .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                            event_sender
+                                .send(gatt::event::Event::AcquireWrite(
+                                    gatt::event::AcquireWrite {
+                                        context,
+                                        mtu,
+                                        stream: PacketStream::new(socket),
+                                    },
+                                ))
+                                .await
+                                .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                            Ok((fd, mtu))
+                        }
+                        .map(move |result| ctx.reply(result))
+                    },
+                );
+            }
+            if characteristic.acquire_notify {
+                let method_acquired = Arc::clone(&notify_acquired);
+                b.property("NotifyAcquired")
+                    .get(move |_ctx, _data| Ok(notify_acquired.load(Ordering::Relaxed)));
+                b.method_with_cr_async(
+                    "AcquireNotify",
+                    ("options",),
+                    ("fd", "mtu"),
+                    move |mut ctx, cr, (options,): (OptionsMap,)| {
+                        let characteristic = cr
+                            .data_mut::<GattDataType>(ctx.path())
+                            .unwrap()
+                            .get_characteristic();
+                        let context = request_context(characteristic.uuid, &options);
+                        let notify_acquired = Arc::clone(&method_acquired);
+                        async move {
+                            let mut event_sender =
+                                characteristic.properties.notify.clone().ok_or_else(|| {
+                                    MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, ""))
+                                })?;
+                            let mtu = context.mtu.unwrap_or(acquired::DEFAULT_MTU);
+                            let (socket, fd) = acquired::pair(mtu, &notify_acquired)
+                                .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                            event_sender
+                                .send(gatt::event::Event::AcquireNotify(
+                                    gatt::event::AcquireNotify {
+                                        context,
+                                        mtu,
+                                        sink: PacketSink::new(socket),
+                                    },
+                                ))
+                                .await
+                                .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                            Ok((fd, mtu))
+                        }
+                        .map(move |result| ctx.reply(result))
+                    },
+                );
+            }
             b.property("UUID")
                 .get(|_ctx, data| Ok(data.get_characteristic().uuid.to_string()));
             let service = service.clone();
             b.property("Service")
                 .get(move |_ctx, _data| Ok(service.clone()));
             b.property("Flags")
-                .get(move |_ctx, data| Ok(data.get_characteristic().properties.flags()));
+                .get(move |_ctx, data| Ok(data.get_characteristic().flags()));
         });
 
         tree.insert(object_path.clone(), &[iface_token], object_path_data);
@@ -212,3 +458,47 @@ impl Characteristic {
         Ok(Characteristic { object_path })
     }
 }
+
+/// A central's subscription, which ends when this is dropped.
+#[derive(Debug)]
+struct Subscription {
+    id: SubscriptionId,
+    /// Dropping it stops forwarding notifications.
+    _stopped: oneshot::Sender<()>,
+    /// Confirmations of indications, for a subscription to them.
+    confirmations: Option<mpsc::UnboundedSender<()>>,
+}
+
+/// Sends indications one at a time, each once the previous one was confirmed or timed out, so
+/// a confirmation always belongs to the indication in flight. Values without a waiter, sent
+/// through `notification`, are given as long as an `indicate` call would wait.
+///
+/// Stops when `confirmations` closes on `StopNotify`, failing the indication in flight and any
+/// queued behind it.
+async fn forward_indications<S>(
+    mut values: S,
+    mut confirmations: mpsc::UnboundedReceiver<()>,
+    mut message_sender: mpsc::Sender<Vec<u8>>,
+) where
+    S: Stream<Item = (Vec<u8>, Option<oneshot::Sender<()>>)> + Unpin,
+{
+    while let Some((value, mut confirmed)) = values.next().await {
+        // Confirmations of indications that have already timed out.
+        while let Ok(Some(())) = confirmations.try_next() {}
+        if message_sender.send(value).await.is_err() {
+            return;
+        }
+        let given_up = match confirmed {
+            Some(ref mut confirmed) => Either::Left(confirmed.cancellation()),
+            None => Either::Right(tokio::time::delay_for(INDICATION_TIMEOUT)),
+        };
+        let is_confirmed = match future::select(confirmations.next(), given_up).await {
+            Either::Left((Some(()), _)) => true,
+            Either::Left((None, _)) => return,
+            Either::Right(_) => false,
+        };
+        if let Some(confirmed) = confirmed.filter(|_| is_confirmed) {
+            confirmed.send(()).ok();
+        }
+    }
+}
@@ -1,10 +1,7 @@
-use dbus::{
-    arg::{RefArg, Variant},
-    Path,
-};
+use dbus::Path;
 use dbus_crossroads::MethodErr;
 use futures::{channel::oneshot, prelude::*};
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
 use super::{
     super::{
@@ -12,11 +9,16 @@ use super::{
         common::GattDataType,
         constants::{BLUEZ_ERROR_FAILED, BLUEZ_ERROR_NOTSUPPORTED, GATT_DESCRIPTOR_IFACE},
     },
+    authorize,
     flags::Flags,
+    offset, prepare_authorize, read_long, read_value_reply, reply, request_context, write_kind,
+    write_long, OptionsMap,
+};
+use crate::{
+    gatt,
+    gatt::event::{Access, WriteKind},
+    Error,
 };
-use crate::{gatt, Error};
-
-type OptionsMap = HashMap<String, Variant<Box<dyn RefArg>>>;
 
 #[derive(Debug, Clone)]
 pub struct Descriptor {
@@ -37,21 +39,31 @@ impl Descriptor {
                 ("options",),
                 ("value",),
                 |mut ctx, cr, (options,): (OptionsMap,)| {
-                    let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
                     let descriptor = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_descriptor();
+                    let offset = offset(&options);
+                    let context = request_context(descriptor.uuid, &options);
                     async move {
-                        let event_sender = descriptor
+                        if let Some(ref value) = descriptor.value {
+                            return read_value_reply(value, offset);
+                        }
+                        let mut event_sender = descriptor
                             .properties
                             .read
                             .clone()
+                            .map(|read| read.sender())
                             .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                        authorize(descriptor.authorize, &event_sender, &context, Access::Read)
+                            .await?;
+                        if descriptor.long_value.is_some() {
+                            return read_long(event_sender, context, offset).await;
+                        }
                         let (sender, receiver) = oneshot::channel();
                         event_sender
-                            .sender()
                             .send(gatt::event::Event::ReadRequest(gatt::event::ReadRequest {
+                                context,
                                 offset,
                                 response: sender,
                             }))
@@ -60,10 +72,7 @@ impl Descriptor {
                         receiver
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
-                            })
+                            .and_then(reply)
                     }
                     .map(move |result| ctx.reply(result))
                 },
@@ -73,37 +82,72 @@ impl Descriptor {
                 ("data", "options"),
                 ("value",),
                 |mut ctx, cr, (data, options): (Vec<u8>, OptionsMap)| {
-                    let offset = options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16;
                     let descriptor = cr
                         .data_mut::<GattDataType>(ctx.path())
                         .unwrap()
                         .get_descriptor();
+                    let offset = offset(&options);
+                    let prepare_authorize = prepare_authorize(&options);
+                    // A prepared write is part of a reliable write, whatever its type says.
+                    let kind = if prepare_authorize {
+                        WriteKind::Reliable
+                    } else {
+                        write_kind(&options)
+                    };
+                    let context = request_context(descriptor.uuid, &options);
                     async move {
-                        let event_sender = descriptor
+                        let mut event_sender = descriptor
                             .properties
                             .write
                             .clone()
+                            .map(|write| write.sender())
                             .ok_or_else(|| MethodErr::from((BLUEZ_ERROR_NOTSUPPORTED, "")))?;
+                        authorize(
+                            descriptor.authorize,
+                            &event_sender,
+                            &context,
+                            Access::Write(kind),
+                        )
+                        .await?;
+                        if prepare_authorize {
+                            // bluetoothd queues the data itself and writes it on execution.
+                            return Ok((vec![],));
+                        }
+                        if let Some(ref long_value) = descriptor.long_value {
+                            let read_sender =
+                                descriptor.properties.read.clone().map(|read| read.sender());
+                            return write_long(
+                                long_value,
+                                read_sender,
+                                event_sender,
+                                context,
+                                offset,
+                                data,
+                                kind,
+                            )
+                            .await;
+                        }
                         let (sender, receiver) = oneshot::channel();
                         event_sender
-                            .sender()
                             .send(gatt::event::Event::WriteRequest(
                                 gatt::event::WriteRequest {
+                                    context,
                                     data,
                                     offset,
-                                    without_response: false,
+                                    kind,
                                     response: sender,
                                 },
                             ))
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))?;
+                        if kind == WriteKind::Command {
+                            // bluetoothd does not wait for a reply to a write command.
+                            return Ok((vec![],));
+                        }
                         receiver
                             .await
                             .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
-                            .and_then(|resp| match resp {
-                                gatt::event::Response::Success(value) => Ok((value,)),
-                                _ => Err(MethodErr::from((BLUEZ_ERROR_FAILED, ""))),
-                            })
+                            .and_then(reply)
                     }
                     .map(move |result| ctx.reply(result))
                 },
@@ -114,7 +158,7 @@ impl Descriptor {
             b.property("Characteristic")
                 .get(move |_ctx, _data| Ok(characteristic.clone()));
             b.property("Flags")
-                .get(move |_ctx, data| Ok(data.get_descriptor().properties.flags()));
+                .get(move |_ctx, data| Ok(data.get_descriptor().flags()));
         });
         let object_path: Path =
             format!("{}/descriptor{:04}", characteristic.to_string(), index).into();
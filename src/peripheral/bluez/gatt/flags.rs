@@ -1,67 +1,75 @@
 use crate::gatt::{
-    characteristic::{self, Properties as CharacteristicProperties},
-    descriptor::{self, Properties as DescriptorProperties},
+    characteristic::{Characteristic, Write},
+    descriptor::Descriptor,
+    security::SecurityLevel,
 };
 
 pub trait Flags {
     fn flags(self: &Self) -> Vec<String>;
 }
 
-impl Flags for CharacteristicProperties {
+impl Flags for Characteristic {
     fn flags(self: &Self) -> Vec<String> {
+        let properties = &self.properties;
+        let security = &self.security;
         let mut flags = vec![];
-        if let Some(ref read) = self.read {
-            let read_flags: &[&str] = match read.0 {
-                characteristic::Secure::Secure(_) => &["secure-read", "encrypt-authenticated-read"],
-                characteristic::Secure::Insecure(_) => &["read"],
-            };
-            flags.extend_from_slice(read_flags);
+        if self.is_readable() {
+            flags.extend(security_flags("read", security.read));
         }
 
-        if let Some(ref write) = self.write {
-            let write_flag: &[&str] = match write {
-                characteristic::Write::WithResponse(secure) => match secure {
-                    characteristic::Secure::Secure(_) => {
-                        &["secure-write", "encrypt-authenticated-write"]
-                    }
-                    characteristic::Secure::Insecure(_) => &["write"],
-                },
-                characteristic::Write::WithoutResponse(_) => &["write-without-response"],
-            };
-            flags.extend_from_slice(write_flag);
+        match properties.write {
+            Some(Write::WithResponse(_)) => flags.extend(security_flags("write", security.write)),
+            Some(Write::WithoutResponse(_)) => flags.push("write-without-response".to_string()),
+            None => {}
         }
 
-        if self.notify.is_some() {
-            flags.push("notify");
+        if properties.notify.is_some() {
+            flags.extend(security_flags("notify", security.notify));
         }
 
-        if self.indicate.is_some() {
-            flags.push("indicate");
+        if properties.indicate.is_some() {
+            flags.extend(security_flags("indicate", security.indicate));
         }
 
-        flags.iter().map(|s| String::from(*s)).collect()
+        if self.authorize {
+            flags.push("authorize".to_string());
+        }
+
+        flags
     }
 }
 
-impl Flags for DescriptorProperties {
+impl Flags for Descriptor {
     fn flags(self: &Self) -> Vec<String> {
         let mut flags = vec![];
-        if let Some(ref read) = self.read {
-            let read_flags: &[&str] = match read.0 {
-                descriptor::Secure::Secure(_) => &["secure-read", "encrypt-authenticated-read"],
-                descriptor::Secure::Insecure(_) => &["read"],
-            };
-            flags.extend_from_slice(read_flags);
+        if self.is_readable() {
+            flags.extend(security_flags("read", self.security.read));
+        }
+
+        if self.properties.write.is_some() {
+            flags.extend(security_flags("write", self.security.write));
         }
 
-        if let Some(ref write) = self.write {
-            let write_flags: &[&str] = match write.0 {
-                descriptor::Secure::Secure(_) => &["secure-write", "encrypt-authenticated-write"],
-                descriptor::Secure::Insecure(_) => &["write"],
-            };
-            flags.extend_from_slice(write_flags);
+        if self.authorize {
+            flags.push("authorize".to_string());
         }
 
-        flags.iter().map(|s| String::from(*s)).collect()
+        flags
+    }
+}
+
+/// The flags for `operation` at `level`; bluetoothd sets the matching property for each of them.
+///
+/// Secure Connections comes with `encrypt-authenticated-*` as well, which is what `Secure::Secure`
+/// has always been exported as.
+fn security_flags(operation: &str, level: SecurityLevel) -> Vec<String> {
+    match level {
+        SecurityLevel::None => vec![operation.to_string()],
+        SecurityLevel::Encrypted => vec![format!("encrypt-{}", operation)],
+        SecurityLevel::Authenticated => vec![format!("encrypt-authenticated-{}", operation)],
+        SecurityLevel::SecureConnections => vec![
+            format!("secure-{}", operation),
+            format!("encrypt-authenticated-{}", operation),
+        ],
     }
 }
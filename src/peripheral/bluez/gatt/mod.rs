@@ -1,60 +1,110 @@
+mod acquired;
 mod application;
 mod characteristic;
 mod descriptor;
 mod flags;
 mod service;
 
-use dbus::{channel::MatchingReceiver, message::MatchRule, Path};
-use std::sync::{Arc, Mutex};
+use dbus::{
+    arg::{RefArg, Variant},
+    channel::MatchingReceiver,
+    message::MatchRule,
+    Path,
+};
+use dbus_crossroads::MethodErr;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use uuid::Uuid;
 
 use self::{
     application::Application, characteristic::Characteristic, descriptor::Descriptor,
     service::Service,
 };
-use super::{common, constants::PATH_BASE, Connection};
-use crate::{gatt, Error};
+use super::{
+    common,
+    constants::{
+        BLUEZ_ERROR_FAILED, BLUEZ_ERROR_INVALIDOFFSET, BLUEZ_ERROR_INVALIDVALUELENGTH,
+        BLUEZ_ERROR_NOTAUTHORIZED, BLUEZ_ERROR_NOTPERMITTED, BLUEZ_ERROR_UNLIKELY,
+        BLUEZ_ERROR_VALUENOTALLOWED,
+    },
+    Connection,
+};
+use crate::{
+    gatt::{
+        self,
+        event::{
+            self, Access, Authorization, EventSender, Link, LongValue, RequestContext, Response,
+            WriteKind,
+        },
+        hash::DatabaseHash,
+        subscribers::{Subscriber, Subscribers},
+    },
+    Error, ErrorType,
+};
+
+/// An added service and its object paths, the service's own first.
+type ServiceObjects = (gatt::service::Service, Vec<Path<'static>>);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Gatt {
     connection: Arc<Connection>,
     adapter: Path<'static>,
-    tree: Arc<Mutex<Option<common::Tree>>>,
+    path_prefix: Path<'static>,
+    notification_channel_capacity: usize,
+    tree: Arc<Mutex<common::Tree>>,
+    attached: Arc<AtomicBool>,
     application: Arc<Mutex<Option<Application>>>,
+    services: Arc<Mutex<Vec<ServiceObjects>>>,
     service_index: Arc<Mutex<u64>>,
     characteristic_index: Arc<Mutex<u64>>,
     descriptor_index: Arc<Mutex<u64>>,
+    subscribers: Arc<Subscribers>,
 }
 
 impl Gatt {
-    pub fn new(connection: Arc<Connection>, adapter: Path<'static>) -> Self {
-        let mut tree = common::Tree::new();
-        tree.set_async_support(Some((
-            connection.default.clone(),
-            Box::new(|x| {
-                tokio::spawn(x);
-            }),
-        )));
+    pub fn new(
+        connection: Arc<Connection>,
+        adapter: Path<'static>,
+        path_prefix: Path<'static>,
+        notification_channel_capacity: usize,
+    ) -> Self {
         Gatt {
             adapter,
+            path_prefix,
+            notification_channel_capacity,
             connection,
-            tree: Arc::new(Mutex::new(Some(tree))),
+            tree: Arc::new(Mutex::new(common::Tree::new())),
+            attached: Arc::new(AtomicBool::new(false)),
             application: Arc::new(Mutex::new(None)),
+            services: Arc::new(Mutex::new(vec![])),
             service_index: Arc::new(Mutex::new(0)),
             characteristic_index: Arc::new(Mutex::new(0)),
             descriptor_index: Arc::new(Mutex::new(0)),
+            subscribers: Arc::new(Subscribers::default()),
         }
     }
 
     pub fn add_service(self: &Self, service: &gatt::service::Service) -> Result<(), Error> {
         let mut tree = self.tree.lock().unwrap();
-        let tree = tree.as_mut().unwrap();
+        let tree = &mut *tree;
 
         let mut service_index = self.service_index.lock().unwrap();
         let mut characteristic_index = self.characteristic_index.lock().unwrap();
         let mut descriptor_index = self.descriptor_index.lock().unwrap();
 
-        let gatt_service = Service::new(tree, &Arc::new(service.clone()), *service_index)?;
+        let gatt_service = Service::new(
+            tree,
+            &Arc::new(service.clone()),
+            &self.path_prefix,
+            *service_index,
+        )?;
         *service_index += 1;
+        let mut object_paths = vec![gatt_service.object_path.clone()];
 
         for characteristic in service.characteristics.iter() {
             let gatt_characteristic = Characteristic::new(
@@ -63,30 +113,78 @@ impl Gatt {
                 &Arc::new(characteristic.clone()),
                 &Arc::new(gatt_service.object_path.clone()),
                 *characteristic_index,
+                self.notification_channel_capacity,
+                &self.subscribers,
             )?;
             *characteristic_index += 1;
+            object_paths.push(gatt_characteristic.object_path.clone());
 
             for descriptor in characteristic.descriptors.iter() {
-                Descriptor::new(
+                let gatt_descriptor = Descriptor::new(
                     tree,
                     &Arc::new(descriptor.clone()),
                     &Arc::new(gatt_characteristic.object_path.clone()),
                     *descriptor_index,
                 )?;
                 *descriptor_index += 1;
+                object_paths.push(gatt_descriptor.object_path);
             }
         }
 
+        self.services
+            .lock()
+            .unwrap()
+            .push((service.clone(), object_paths));
+        service.start();
         Ok(())
     }
 
-    pub async fn register(self: &Self) -> Result<(), Error> {
-        let mut tree = self.tree.lock().unwrap().take().unwrap();
+    /// Removes the services with the given UUID; once the application is registered, the
+    /// object manager tells bluetoothd with `InterfacesRemoved`.
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        let mut tree = self.tree.lock().unwrap();
+        let mut services = self.services.lock().unwrap();
+        let count = services.len();
+        services.retain(|(service, object_paths)| {
+            if service.uuid != *uuid {
+                return true;
+            }
+            for object_path in object_paths.iter().rev() {
+                tree.remove::<()>(object_path);
+            }
+            false
+        });
+
+        if services.len() == count {
+            return Err(Error::new(
+                "ServiceNotFound",
+                format!("no service with UUID {}", uuid),
+                ErrorType::Bluez,
+            ));
+        }
+        Ok(())
+    }
 
+    pub fn hash(&self) -> DatabaseHash {
+        DatabaseHash::new(
+            self.services
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(service, _)| service),
+        )
+    }
+
+    pub fn subscribers(&self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.subscribers.of(characteristic)
+    }
+
+    pub async fn register(self: &Self) -> Result<(), Error> {
         let new_application = Application::new(
             Arc::clone(&self.connection),
-            &mut tree,
+            &mut self.tree.lock().unwrap(),
             self.adapter.clone(),
+            self.path_prefix.clone(),
         );
 
         self.application
@@ -94,28 +192,189 @@ impl Gatt {
             .unwrap()
             .replace(new_application.clone());
 
+        if !self.attached.swap(true, Ordering::SeqCst) {
+            self.attach();
+        }
+
+        new_application.register().await
+    }
+
+    /// Registers the application again after bluetoothd lost it, e.g. because it restarted.
+    pub async fn reregister(&self) -> Result<(), Error> {
+        let application = self.application.lock().unwrap().clone();
+        match application {
+            Some(application) => application.register().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Serves the exported objects on the current connection, if they were served before.
+    pub fn reattach(&self) {
+        if self.attached.load(Ordering::SeqCst) {
+            self.attach();
+        }
+    }
+
+    fn attach(&self) {
+        let connection = self.connection.default();
+        {
+            let mut tree = self.tree.lock().unwrap();
+            tree.set_async_support(Some((
+                connection.clone(),
+                Box::new(|x| {
+                    tokio::spawn(x);
+                }),
+            )));
+            // Objects added or removed from now on are announced to bluetoothd.
+            tree.set_object_manager_support(Some(connection.clone()));
+        }
+
+        let tree = Arc::clone(&self.tree);
         let mut match_rule = MatchRule::new_method_call();
-        match_rule.path = Some(PATH_BASE.into());
+        match_rule.path = Some(self.path_prefix.clone());
         match_rule.path_is_namespace = true;
-        self.connection.default.start_receive(
+        connection.start_receive(
             match_rule,
             Box::new(move |msg, conn| {
-                tree.handle_message(msg, conn).unwrap();
+                tree.lock().unwrap().handle_message(msg, conn).unwrap();
                 true
             }),
         );
-
-        new_application.register().await
     }
 
     pub async fn unregister(self: &Self) -> Result<(), Error> {
-        self.application
-            .lock()
-            .unwrap()
-            .take()
-            .unwrap()
-            .unregister()
-            .await
-            .map(|_| ())
+        let application = self.application.lock().unwrap().take();
+        match application {
+            Some(application) => application.unregister().await,
+            None => Err(Error::new(
+                "NotRegistered",
+                "the GATT application is not registered",
+                ErrorType::Bluez,
+            )),
+        }
+    }
+}
+
+/// The options passed to `ReadValue` and `WriteValue`.
+type OptionsMap = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+fn offset(options: &OptionsMap) -> u16 {
+    options.get("offset").and_then(RefArg::as_u64).unwrap_or(0) as u16
+}
+
+/// Collects what BlueZ tells about the central sending a request on the attribute `uuid`.
+fn request_context(uuid: Uuid, options: &OptionsMap) -> RequestContext {
+    RequestContext {
+        uuid,
+        device: options
+            .get("device")
+            .and_then(RefArg::as_str)
+            .map(str::to_string),
+        mtu: options
+            .get("mtu")
+            .and_then(RefArg::as_u64)
+            .map(|mtu| mtu as u16),
+        link: options
+            .get("link")
+            .and_then(RefArg::as_str)
+            .and_then(|link| match link {
+                "LE" => Some(Link::Le),
+                "BR/EDR" => Some(Link::BrEdr),
+                _ => None,
+            }),
+    }
+}
+
+fn write_kind(options: &OptionsMap) -> WriteKind {
+    match options.get("type").and_then(RefArg::as_str) {
+        Some("command") => WriteKind::Command,
+        Some("reliable") => WriteKind::Reliable,
+        _ => WriteKind::Request,
+    }
+}
+
+/// Whether bluetoothd only asks to authorize a prepared write.
+fn prepare_authorize(options: &OptionsMap) -> bool {
+    options
+        .get("prepare-authorize")
+        .and_then(RefArg::as_u64)
+        .unwrap_or(0)
+        != 0
+}
+
+/// Sends an `AuthorizeRequest` if the attribute is `required` to, failing with Insufficient
+/// Authorization when it is denied.
+async fn authorize(
+    required: bool,
+    event_sender: &EventSender,
+    context: &RequestContext,
+    access: Access,
+) -> Result<(), MethodErr> {
+    if !required {
+        return Ok(());
+    }
+    match event::authorize(event_sender.clone(), context.clone(), access).await {
+        Authorization::Allow => Ok(()),
+        Authorization::Deny => reply(Response::InsufficientAuthorization).map(|_| ()),
     }
 }
+
+/// Replies to `ReadValue` on an attribute built with `long_values`, see `event::read_long`.
+async fn read_long(
+    event_sender: EventSender,
+    context: RequestContext,
+    offset: u16,
+) -> Result<(Vec<u8>,), MethodErr> {
+    event::read_long(event_sender, context, offset)
+        .await
+        .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
+        .and_then(reply)
+}
+
+/// Replies to `WriteValue` on an attribute built with `long_values`, see `LongValue::write`.
+async fn write_long(
+    long_value: &LongValue,
+    read_sender: Option<EventSender>,
+    write_sender: EventSender,
+    context: RequestContext,
+    offset: u16,
+    data: Vec<u8>,
+    kind: WriteKind,
+) -> Result<(Vec<u8>,), MethodErr> {
+    long_value
+        .write(read_sender, write_sender, context, offset, data, kind)
+        .await
+        .map_err(|_| MethodErr::from((BLUEZ_ERROR_FAILED, "")))
+        .and_then(reply)
+}
+
+/// Replies to `ReadValue` on an attribute with a fixed value.
+fn read_value_reply(value: &[u8], offset: u16) -> Result<(Vec<u8>,), MethodErr> {
+    reply(Response::read_value(value, offset))
+}
+
+/// Replies to `ReadValue` or `WriteValue` with a response from the application.
+///
+/// bluetoothd turns the error name back into an ATT error: `NotPermitted` into Read or Write Not
+/// Permitted depending on the method, `NotAuthorized` into Insufficient Authorization and
+/// `Failed` into an application error, with the code taken from the message. Any other name
+/// becomes Unlikely Error, as Value Not Allowed has to as well, and so do codes outside of the
+/// range reserved for applications.
+fn reply(response: Response) -> Result<(Vec<u8>,), MethodErr> {
+    let name = match response {
+        Response::Success(value) => return Ok((value,)),
+        Response::ReadNotPermitted
+        | Response::WriteNotPermitted
+        | Response::InsufficientAuthentication
+        | Response::InsufficientEncryption => BLUEZ_ERROR_NOTPERMITTED,
+        Response::InsufficientAuthorization => BLUEZ_ERROR_NOTAUTHORIZED,
+        Response::InvalidOffset => BLUEZ_ERROR_INVALIDOFFSET,
+        Response::InvalidAttributeLength => BLUEZ_ERROR_INVALIDVALUELENGTH,
+        Response::ValueNotAllowed => BLUEZ_ERROR_VALUENOTALLOWED,
+        Response::ApplicationError(0x80..=0x9F) => BLUEZ_ERROR_FAILED,
+        Response::ApplicationError(_) => BLUEZ_ERROR_UNLIKELY,
+        Response::UnlikelyError => BLUEZ_ERROR_UNLIKELY,
+    };
+    let code = response.att_error_code().unwrap_or_default();
+    Err(MethodErr::from((name, format!("{:#04x}", code))))
+}
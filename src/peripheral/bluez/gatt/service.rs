@@ -2,7 +2,7 @@ use dbus::Path;
 use std::sync::Arc;
 
 use super::super::common;
-use super::super::constants::{GATT_SERVICE_IFACE, PATH_BASE};
+use super::super::constants::GATT_SERVICE_IFACE;
 use crate::{gatt, Error};
 
 #[derive(Debug, Clone)]
@@ -14,6 +14,7 @@ impl Service {
     pub fn new(
         tree: &mut common::Tree,
         service: &Arc<gatt::service::Service>,
+        application: &Path<'static>,
         index: u64,
     ) -> Result<Self, Error> {
         let get_all = tree.register(GATT_SERVICE_IFACE, |b| {
@@ -24,7 +25,7 @@ impl Service {
             b.property("Primary")
                 .get(move |_ctx, _cr| Ok(service1.primary));
         });
-        let object_path: Path = format!("{}/service{:04}", PATH_BASE, index).into();
+        let object_path: Path = format!("{}/service{:04}", application, index).into();
         tree.insert(object_path.clone(), &[get_all], ());
         Ok(Service { object_path })
     }
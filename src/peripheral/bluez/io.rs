@@ -0,0 +1,132 @@
+//! Drives D-Bus connections that `dbus_tokio` cannot open itself.
+//!
+//! `dbus_tokio` 0.5 only connects to the well-known system and session buses, so this mirrors its
+//! `IOResource` for channels opened from an arbitrary address.
+
+use dbus::{
+    channel::Channel,
+    nonblock::{NonblockReply, Process, SyncConnection},
+};
+use std::{
+    error, future,
+    os::unix::io::RawFd,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::io::Registration;
+
+pub type ResourceError = Box<dyn error::Error + Send + Sync>;
+
+/// Keeps a connection flowing; it must be spawned onto the runtime and only finishes when the
+/// connection to the bus is lost.
+pub struct IOResource {
+    connection: Arc<SyncConnection>,
+    watch_fd: RawFd,
+    waker_resource: mio::Registration,
+    registrations: Option<(Registration, Registration)>,
+    write_pending: bool,
+}
+
+pub fn new_sync(address: &str) -> Result<(IOResource, Arc<SyncConnection>), dbus::Error> {
+    let mut channel = Channel::open_private(address)?;
+    channel.register()?;
+    channel.set_watch_enabled(true);
+    let watch_fd = channel.watch().fd;
+
+    let mut connection = SyncConnection::from(channel);
+    connection.set_timeout_maker(Some(make_timeout));
+
+    // Messages sent from other tasks have to wake the resource up so it flushes them.
+    let (waker_resource, waker) = mio::Registration::new2();
+    connection.set_waker(Some(Box::new(move || {
+        waker.set_readiness(mio::Ready::readable()).map_err(|_| ())
+    })));
+
+    let connection = Arc::new(connection);
+    let resource = IOResource {
+        connection: Arc::clone(&connection),
+        watch_fd,
+        waker_resource,
+        registrations: None,
+        write_pending: false,
+    };
+    Ok((resource, connection))
+}
+
+impl IOResource {
+    fn poll_internal(&mut self, cx: &mut Context<'_>) -> Result<(), ResourceError> {
+        if self.registrations.is_none() {
+            let watch = Registration::new(&mio::unix::EventedFd(&self.watch_fd))?;
+            let waker = Registration::new(&self.waker_resource)?;
+            self.registrations = Some((watch, waker));
+        }
+        let (watch, waker) = self.registrations.as_ref().unwrap();
+        let channel: &Channel = (*self.connection).as_ref();
+
+        let read_ready = is_ready(watch.poll_read_ready(cx)?);
+        let send_ready = is_ready(waker.poll_read_ready(cx)?);
+        let write_ready = watch
+            .take_write_ready()?
+            .map(|ready| ready.is_writable())
+            .unwrap_or(false);
+
+        if read_ready || send_ready || (self.write_pending && write_ready) {
+            loop {
+                self.write_pending = false;
+                channel
+                    .read_write(Some(Default::default()))
+                    .map_err(|_| dbus::Error::new_failed("Read/write failed"))?;
+                self.connection.process_all();
+
+                if channel.has_messages_to_send() {
+                    // The socket is full, so wait until it becomes writable again.
+                    self.write_pending = true;
+                    if is_ready(watch.poll_write_ready(cx)?) {
+                        continue;
+                    }
+                }
+
+                // libdbus is level-triggered and tokio edge-triggered, so keep going while data
+                // is still waiting on the socket.
+                let mut byte = 0u8;
+                let peeked = unsafe {
+                    libc::recv(
+                        self.watch_fd,
+                        &mut byte as *mut _ as *mut libc::c_void,
+                        1,
+                        libc::MSG_DONTWAIT | libc::MSG_PEEK,
+                    )
+                };
+                if peeked != 1 {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl future::Future for IOResource {
+    type Output = ResourceError;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.poll_internal(cx) {
+            Ok(()) => Poll::Pending,
+            Err(err) => Poll::Ready(err),
+        }
+    }
+}
+
+fn is_ready(poll: Poll<mio::Ready>) -> bool {
+    match poll {
+        Poll::Ready(ready) => !ready.is_empty(),
+        Poll::Pending => false,
+    }
+}
+
+fn make_timeout(timeout: Instant) -> Pin<Box<dyn future::Future<Output = ()> + Send + Sync>> {
+    Box::pin(tokio::time::delay_until(timeout.into()))
+}
@@ -5,33 +5,121 @@ mod connection;
 mod constants;
 mod error;
 mod gatt;
+mod io;
+mod options;
+mod supervisor;
 
+use async_trait::async_trait;
+use dbus::Path;
 use std::{string::ToString, sync::Arc};
 use uuid::Uuid;
 
-use self::{adapter::Adapter, advertisement::Advertisement, connection::Connection, gatt::Gatt};
-use crate::{gatt::service::Service, Error};
+use self::{
+    adapter::{Adapter, AdapterWatch},
+    advertisement::Advertisement,
+    connection::Connection,
+    constants::BLUEZ_DBUS_TIMEOUT,
+    gatt::Gatt,
+    supervisor::Supervisor,
+};
+pub use self::{
+    adapter::{AdapterCapabilities, AdapterInfo},
+    connection::Bus,
+    options::{AdapterSelector, PeripheralOptions, PowerPolicy},
+};
+use super::event::{EventBroadcaster, EventStream};
+use crate::{
+    gatt::{hash::DatabaseHash, service::Service, subscribers::Subscriber},
+    Error, ErrorType,
+};
 
 #[derive(Debug)]
 pub struct Peripheral {
     adapter: Adapter,
     gatt: Gatt,
     advertisement: Advertisement,
+    events: Arc<EventBroadcaster>,
+    // Supervised peripherals hand the adapter watch to their supervisor, which renews it after
+    // reconnecting.
+    _adapter_watch: Option<AdapterWatch>,
+    _supervisor: Option<Supervisor>,
 }
 
 impl Peripheral {
     #[allow(clippy::new_ret_no_self)]
     pub async fn new() -> Result<Self, Error> {
-        let connection = Arc::new(Connection::new()?);
-        let adapter = Adapter::new(connection.clone()).await?;
-        adapter.powered(true).await?;
-        let gatt = Gatt::new(connection.clone(), adapter.object_path.clone());
-        let advertisement = Advertisement::new(connection, adapter.object_path.clone());
+        Peripheral::with_options(PeripheralOptions::default()).await
+    }
+
+    /// Creates a peripheral that reaches bluetoothd over the given bus instead of the system bus.
+    pub async fn with_bus(bus: Bus) -> Result<Self, Error> {
+        Peripheral::with_options(PeripheralOptions::default().bus(bus)).await
+    }
+
+    /// Lists the adapters bluetoothd knows about on the given bus, capable or not.
+    pub async fn adapters(bus: Bus) -> Result<Vec<AdapterInfo>, Error> {
+        let connection = Connection::new(bus, BLUEZ_DBUS_TIMEOUT)?;
+        Adapter::list(&connection).await
+    }
+
+    pub async fn with_options(options: PeripheralOptions) -> Result<Self, Error> {
+        let path_prefix = Path::new(options.path_prefix.clone())
+            .map_err(|err| Error::new("InvalidPathPrefix", err, ErrorType::Bluez))?;
+
+        let connection = Arc::new(Connection::new(options.bus.clone(), options.timeout)?);
+        let adapter = Adapter::new(connection.clone(), options.adapter.as_ref()).await?;
+        let events = Arc::new(EventBroadcaster::with_capacity(
+            options.event_channel_capacity,
+        ));
+        let adapter_watch = adapter.watch(Arc::clone(&events)).await?;
+        match options.power_policy {
+            PowerPolicy::PowerOn => adapter.powered(true).await?,
+            PowerPolicy::Unchanged => {}
+            PowerPolicy::RequirePowered => {
+                if !adapter.is_powered().await? {
+                    return Err(Error::new(
+                        "NotPowered",
+                        format!("adapter {} is switched off", adapter.object_path),
+                        ErrorType::Bluez,
+                    ));
+                }
+            }
+        }
+        let gatt = Gatt::new(
+            connection.clone(),
+            adapter.object_path.clone(),
+            path_prefix.clone(),
+            options.notification_channel_capacity,
+        );
+        let advertisement = Advertisement::new(
+            connection.clone(),
+            adapter.object_path.clone(),
+            &path_prefix,
+        );
+
+        let (adapter_watch, supervisor) = if options.supervised {
+            let supervisor = Supervisor::start(
+                connection,
+                adapter.clone(),
+                gatt.clone(),
+                advertisement.clone(),
+                &options,
+                Arc::clone(&events),
+                adapter_watch,
+            )
+            .await?;
+            (None, Some(supervisor))
+        } else {
+            (Some(adapter_watch), None)
+        };
 
         Ok(Peripheral {
             adapter,
             gatt,
             advertisement,
+            events,
+            _adapter_watch: adapter_watch,
+            _supervisor: supervisor,
         })
     }
 
@@ -87,4 +175,75 @@ impl Peripheral {
     pub fn add_service(self: &Self, service: &Service) -> Result<(), Error> {
         self.gatt.add_service(service)
     }
+
+    pub fn remove_service(self: &Self, uuid: &Uuid) -> Result<(), Error> {
+        self.gatt.remove_service(uuid)
+    }
+
+    /// bluetoothd rebuilds its attribute table from the exported objects and indicates Service
+    /// Changed to bonded centrals itself.
+    pub fn database_hash(self: &Self) -> DatabaseHash {
+        self.gatt.hash()
+    }
+
+    pub fn events(self: &Self) -> EventStream {
+        self.events.subscribe()
+    }
+
+    /// bluetoothd subscribes once on behalf of all centrals, so there is at most one subscriber
+    /// per characteristic and it does not name a device.
+    pub fn subscribers(self: &Self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.gatt.subscribers(characteristic)
+    }
+}
+
+#[async_trait]
+impl super::Peripheral for Peripheral {
+    async fn new() -> Result<Self, Error> {
+        Peripheral::new().await
+    }
+
+    async fn is_powered(self: &Self) -> Result<bool, Error> {
+        self.is_powered().await
+    }
+
+    async fn register_gatt(self: &Self) -> Result<(), Error> {
+        self.register_gatt().await
+    }
+
+    async fn unregister_gatt(self: &Self) -> Result<(), Error> {
+        self.unregister_gatt().await
+    }
+
+    async fn start_advertising(self: &Self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
+        self.start_advertising(name, uuids).await
+    }
+
+    async fn stop_advertising(self: &Self) -> Result<(), Error> {
+        self.stop_advertising().await
+    }
+
+    async fn is_advertising(self: &Self) -> Result<bool, Error> {
+        self.is_advertising().await
+    }
+
+    fn add_service(self: &Self, service: &Service) -> Result<(), Error> {
+        self.add_service(service)
+    }
+
+    fn remove_service(self: &Self, uuid: &Uuid) -> Result<(), Error> {
+        self.remove_service(uuid)
+    }
+
+    fn database_hash(self: &Self) -> DatabaseHash {
+        self.database_hash()
+    }
+
+    fn events(self: &Self) -> EventStream {
+        self.events()
+    }
+
+    fn subscribers(self: &Self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.subscribers(characteristic)
+    }
 }
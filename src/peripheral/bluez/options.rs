@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use super::{
+    connection::Bus,
+    constants::{BLUEZ_DBUS_TIMEOUT, NOTIFICATION_CHANNEL_CAPACITY, PATH_BASE, RECONNECT_INTERVAL},
+};
+use crate::peripheral::event::EVENT_CHANNEL_CAPACITY;
+
+/// Picks the Bluetooth controller a peripheral uses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterSelector {
+    /// The `Name` property of the adapter.
+    Name(String),
+    /// The Bluetooth address of the adapter, e.g. `00:1A:7D:DA:71:13`.
+    Address(String),
+    /// The D-Bus object path of the adapter, e.g. `/org/bluez/hci1`.
+    Path(String),
+}
+
+/// What to do with the adapter's power state when the peripheral is created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerPolicy {
+    /// Switch the adapter on.
+    PowerOn,
+    /// Leave the adapter as it is.
+    Unchanged,
+    /// Fail creating the peripheral if the adapter is switched off.
+    RequirePowered,
+}
+
+/// Settings for `Peripheral::with_options`.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use bluster::peripheral::bluez::{AdapterSelector, PeripheralOptions, PowerPolicy};
+/// let options = PeripheralOptions::default()
+///     .adapter(AdapterSelector::Path("/org/bluez/hci1".to_string()))
+///     .power_policy(PowerPolicy::RequirePowered)
+///     .path_prefix("/com/example/gateway")
+///     .timeout(Duration::from_secs(5))
+///     .supervised(true);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PeripheralOptions {
+    pub(super) bus: Bus,
+    pub(super) adapter: Option<AdapterSelector>,
+    pub(super) power_policy: PowerPolicy,
+    pub(super) path_prefix: String,
+    pub(super) timeout: Duration,
+    pub(super) event_channel_capacity: usize,
+    pub(super) notification_channel_capacity: usize,
+    pub(super) supervised: bool,
+    pub(super) reconnect_interval: Duration,
+}
+
+impl Default for PeripheralOptions {
+    fn default() -> Self {
+        PeripheralOptions {
+            bus: Bus::default(),
+            adapter: None,
+            power_policy: PowerPolicy::PowerOn,
+            path_prefix: PATH_BASE.to_string(),
+            timeout: BLUEZ_DBUS_TIMEOUT,
+            event_channel_capacity: EVENT_CHANNEL_CAPACITY,
+            notification_channel_capacity: NOTIFICATION_CHANNEL_CAPACITY,
+            supervised: false,
+            reconnect_interval: RECONNECT_INTERVAL,
+        }
+    }
+}
+
+impl PeripheralOptions {
+    /// The bus bluetoothd is reached over, the system bus by default.
+    pub fn bus(mut self, bus: Bus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// The adapter to use, the first one capable of LE advertising by default.
+    pub fn adapter(mut self, adapter: AdapterSelector) -> Self {
+        self.adapter = Some(adapter);
+        self
+    }
+
+    /// What to do with the adapter's power state, `PowerPolicy::PowerOn` by default.
+    pub fn power_policy(mut self, power_policy: PowerPolicy) -> Self {
+        self.power_policy = power_policy;
+        self
+    }
+
+    /// The D-Bus path the GATT application and advertisement are exported under.
+    pub fn path_prefix<T: Into<String>>(mut self, path_prefix: T) -> Self {
+        self.path_prefix = path_prefix.into();
+        self
+    }
+
+    /// How long to wait for bluetoothd to answer a method call.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many events each `Peripheral::events` stream buffers before dropping new ones.
+    pub fn event_channel_capacity(mut self, capacity: usize) -> Self {
+        self.event_channel_capacity = capacity;
+        self
+    }
+
+    /// How many notifications each subscription buffers before the handler has to wait.
+    pub fn notification_channel_capacity(mut self, capacity: usize) -> Self {
+        self.notification_channel_capacity = capacity;
+        self
+    }
+
+    /// Whether to recover from bluetoothd restarting or the bus connection dropping, off by
+    /// default.
+    ///
+    /// A supervised peripheral reports the outage as `State::Resetting`, registers its GATT
+    /// application and advertisement again once bluetoothd is back, and then reports the
+    /// adapter's power state.
+    pub fn supervised(mut self, supervised: bool) -> Self {
+        self.supervised = supervised;
+        self
+    }
+
+    /// How long a supervised peripheral waits between attempts to reconnect to the bus.
+    pub fn reconnect_interval(mut self, interval: Duration) -> Self {
+        self.reconnect_interval = interval;
+        self
+    }
+}
@@ -0,0 +1,202 @@
+use dbus::{message::MatchRule, nonblock::MsgMatch};
+use futures::{
+    channel::mpsc,
+    future::{self, AbortHandle},
+    prelude::*,
+    stream,
+};
+use std::{fmt, sync::Arc, time::Duration};
+
+use super::{
+    adapter::{Adapter, AdapterWatch},
+    advertisement::Advertisement,
+    connection::Connection,
+    constants::BLUEZ_SERVICE_NAME,
+    gatt::Gatt,
+    options::{PeripheralOptions, PowerPolicy},
+};
+use crate::{
+    peripheral::event::{Event, EventBroadcaster, State},
+    Error, ErrorType,
+};
+
+/// Brings a peripheral back after bluetoothd restarts or the bus connection drops, for as long
+/// as it is kept around.
+pub struct Supervisor {
+    task: AbortHandle,
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Supervisor")
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+type OwnerWatch = (MsgMatch, mpsc::UnboundedReceiver<String>);
+
+enum Signal {
+    /// The connection to the bus was lost.
+    Lost,
+    /// `org.bluez` changed hands; an empty owner means bluetoothd went away.
+    OwnerChanged(String),
+}
+
+struct Supervised {
+    connection: Arc<Connection>,
+    adapter: Adapter,
+    gatt: Gatt,
+    advertisement: Advertisement,
+    power_policy: PowerPolicy,
+    reconnect_interval: Duration,
+    events: Arc<EventBroadcaster>,
+}
+
+impl Supervisor {
+    pub async fn start(
+        connection: Arc<Connection>,
+        adapter: Adapter,
+        gatt: Gatt,
+        advertisement: Advertisement,
+        options: &PeripheralOptions,
+        events: Arc<EventBroadcaster>,
+        adapter_watch: AdapterWatch,
+    ) -> Result<Self, Error> {
+        let lost = connection.lost().ok_or_else(|| {
+            Error::new(
+                "AlreadySupervised",
+                "the connection is already supervised",
+                ErrorType::Bluez,
+            )
+        })?;
+        let owner_watch = watch_owner(&connection).await?;
+        let supervised = Supervised {
+            connection,
+            adapter,
+            gatt,
+            advertisement,
+            power_policy: options.power_policy,
+            reconnect_interval: options.reconnect_interval,
+            events,
+        };
+
+        let (task, abort_handle) =
+            future::abortable(supervised.run(lost, adapter_watch, owner_watch));
+        tokio::spawn(task);
+        Ok(Supervisor { task: abort_handle })
+    }
+}
+
+impl Supervised {
+    async fn run(
+        self,
+        mut lost: mpsc::UnboundedReceiver<String>,
+        mut _adapter_watch: AdapterWatch,
+        mut owner_watch: OwnerWatch,
+    ) {
+        loop {
+            {
+                let mut signals = stream::select(
+                    lost.by_ref().map(|_| Signal::Lost),
+                    owner_watch.1.by_ref().map(Signal::OwnerChanged),
+                );
+                while let Some(signal) = signals.next().await {
+                    match signal {
+                        Signal::Lost => break,
+                        Signal::OwnerChanged(owner) if owner.is_empty() => {
+                            log::warn!("bluetoothd went away");
+                            self.events.broadcast(Event::StateChange(State::Resetting));
+                        }
+                        Signal::OwnerChanged(_) => self.restore().await,
+                    }
+                }
+            }
+
+            self.events.broadcast(Event::StateChange(State::Resetting));
+            loop {
+                tokio::time::delay_for(self.reconnect_interval).await;
+                match self.reconnect().await {
+                    Ok((new_adapter_watch, new_owner_watch, bluez_running)) => {
+                        _adapter_watch = new_adapter_watch;
+                        owner_watch = new_owner_watch;
+                        if bluez_running {
+                            self.restore().await;
+                        }
+                        break;
+                    }
+                    Err(err) => log::warn!("Could not reconnect to D-Bus: {:?}", err),
+                }
+            }
+        }
+    }
+
+    /// Opens a new connection and serves the exported objects and signal watches on it again.
+    async fn reconnect(&self) -> Result<(AdapterWatch, OwnerWatch, bool), Error> {
+        self.connection.reconnect()?;
+        self.gatt.reattach();
+        self.advertisement.attach();
+
+        let adapter_watch = self.adapter.watch(Arc::clone(&self.events)).await?;
+        let owner_watch = watch_owner(&self.connection).await?;
+        let (bluez_running,): (bool,) = self
+            .connection
+            .get_dbus_proxy()
+            .method_call(
+                "org.freedesktop.DBus",
+                "NameHasOwner",
+                (BLUEZ_SERVICE_NAME,),
+            )
+            .await?;
+        Ok((adapter_watch, owner_watch, bluez_running))
+    }
+
+    /// Registers everything bluetoothd forgot, then reports the adapter's state.
+    async fn restore(&self) {
+        if let Err(err) = self.try_restore().await {
+            log::error!("Could not restore the peripheral: {:?}", err);
+        }
+    }
+
+    async fn try_restore(&self) -> Result<(), Error> {
+        let powered = self.adapter.is_powered().await?;
+        let powering_on = !powered && self.power_policy == PowerPolicy::PowerOn;
+        if powering_on {
+            // The adapter watch reports the adapter coming back on.
+            self.adapter.powered(true).await?;
+        }
+        self.gatt.reregister().await?;
+        if self.advertisement.is_advertising() {
+            self.advertisement.register().await?;
+        }
+        if !powering_on {
+            self.events.broadcast(Event::StateChange(if powered {
+                State::PoweredOn
+            } else {
+                State::PoweredOff
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Streams the new owner of `org.bluez` whenever bluetoothd stops or starts.
+async fn watch_owner(connection: &Connection) -> Result<OwnerWatch, Error> {
+    let (sender, receiver) = mpsc::unbounded();
+    let mut rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+    rule.sender = Some("org.freedesktop.DBus".into());
+    let owner_changed = connection.default().add_match(rule).await?.cb(
+        move |_, (name, _, new_owner): (String, String, String)| {
+            if name == BLUEZ_SERVICE_NAME {
+                sender.unbounded_send(new_owner).is_ok()
+            } else {
+                true
+            }
+        },
+    );
+    Ok((owner_changed, receiver))
+}
@@ -1,39 +1,53 @@
 use super::ffi::{CBAttributePermissions, CBCharacteristicProperties};
-use crate::gatt::characteristic::{Characteristic, Secure, Write};
+use crate::gatt::{
+    characteristic::{Characteristic, Write},
+    security::SecurityLevel,
+};
 
+/// CoreBluetooth only tells encrypted links apart from unencrypted ones and leaves the kind of
+/// pairing to the system, so every level above `SecurityLevel::None` requires encryption; those
+/// above `SecurityLevel::Encrypted` are weakened to it, with a warning.
 pub fn get_properties_and_permissions(characteristic: &Characteristic) -> (u16, u8) {
+    let security = &characteristic.security;
+    let levels = [
+        security.read,
+        security.write,
+        security.notify,
+        security.indicate,
+    ];
+    if let Some(level) = levels
+        .iter()
+        .find(|level| **level > SecurityLevel::Encrypted)
+    {
+        log::warn!(
+            "CoreBluetooth cannot require {:?} for characteristic {}, only encryption",
+            level,
+            characteristic.uuid
+        );
+    }
+
     let mut properties: u16 = 0;
     let mut permissions: u8 = 0;
 
-    if let Some(secure) = &characteristic.properties.read {
+    // Fixed values are readable too, CoreBluetooth answers those reads itself.
+    if characteristic.is_readable() {
         properties |= CBCharacteristicProperties::CBCharacteristicPropertyRead as u16;
-
-        match secure.0 {
-            Secure::Secure(_) => {
-                permissions |=
-                    CBAttributePermissions::CBAttributePermissionsReadEncryptionRequired as u8;
-            }
-            Secure::Insecure(_) => {
-                permissions |= CBAttributePermissions::CBAttributePermissionsReadable as u8;
-            }
+        let permission = match security.read {
+            SecurityLevel::None => CBAttributePermissions::CBAttributePermissionsReadable,
+            _ => CBAttributePermissions::CBAttributePermissionsReadEncryptionRequired,
         };
+        permissions |= permission as u8;
     }
 
     if let Some(write) = &characteristic.properties.write {
         match write {
-            Write::WithResponse(secure) => {
+            Write::WithResponse(_) => {
                 properties |= CBCharacteristicProperties::CBCharacteristicPropertyWrite as u16;
-                match secure {
-                    Secure::Secure(_) => {
-                        permissions |=
-                            CBAttributePermissions::CBAttributePermissionsWriteEncryptionRequired
-                                as u8;
-                    }
-                    Secure::Insecure(_) => {
-                        permissions |=
-                            CBAttributePermissions::CBAttributePermissionsWriteable as u8;
-                    }
+                let permission = match security.write {
+                    SecurityLevel::None => CBAttributePermissions::CBAttributePermissionsWriteable,
+                    _ => CBAttributePermissions::CBAttributePermissionsWriteEncryptionRequired,
                 };
+                permissions |= permission as u8;
             }
             Write::WithoutResponse(_) => {
                 properties |=
@@ -44,10 +58,19 @@ pub fn get_properties_and_permissions(characteristic: &Characteristic) -> (u16,
 
     if characteristic.properties.notify.is_some() {
         properties |= CBCharacteristicProperties::CBCharacteristicPropertyNotify as u16;
+        if security.notify > SecurityLevel::None {
+            properties |=
+                CBCharacteristicProperties::CBCharacteristicPropertyNotifyEncryptionRequired as u16;
+        }
     }
 
     if characteristic.properties.indicate.is_some() {
         properties |= CBCharacteristicProperties::CBCharacteristicPropertyIndicate as u16;
+        if security.indicate > SecurityLevel::None {
+            properties |=
+                CBCharacteristicProperties::CBCharacteristicPropertyIndicateEncryptionRequired
+                    as u16;
+        }
     }
 
     (properties, permissions)
@@ -1,3 +1,11 @@
 pub const PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME: &str = "PeripheralManagerDelegate";
 pub const PERIPHERAL_MANAGER_IVAR: &str = "peripheralManager";
 pub const POWERED_ON_IVAR: &str = "poweredOn";
+pub const EVENT_BROADCASTER_IVAR: &str = "eventBroadcaster";
+pub const SUBSCRIPTIONS_IVAR: &str = "subscriptions";
+
+pub const NOTIFICATION_CHANNEL_CAPACITY: usize = 1;
+
+// Descriptors `CBMutableDescriptor` accepts
+pub const USER_DESCRIPTION: u16 = 0x2901;
+pub const PRESENTATION_FORMAT: u16 = 0x2904;
@@ -4,12 +4,33 @@ use objc::{
     sel, sel_impl,
 };
 use objc_foundation::{INSArray, INSString, NSArray, NSObject, NSString};
+use std::os::raw::c_void;
 
 use super::{
-    constants::POWERED_ON_IVAR,
+    constants::{EVENT_BROADCASTER_IVAR, POWERED_ON_IVAR, SUBSCRIPTIONS_IVAR},
     ffi::{CBATTError, CBManagerState},
     into_bool::IntoBool,
+    subscriptions::Subscriptions,
 };
+use crate::peripheral::event::{Event, EventBroadcaster, State};
+
+fn broadcast(delegate: &Object, event: Event) {
+    unsafe {
+        let events = *delegate.get_ivar::<*mut c_void>(EVENT_BROADCASTER_IVAR);
+        if let Some(events) = (events as *const EventBroadcaster).as_ref() {
+            events.broadcast(event);
+        }
+    }
+}
+
+fn with_subscriptions<F: FnOnce(&Subscriptions)>(delegate: &Object, f: F) {
+    unsafe {
+        let subscriptions = *delegate.get_ivar::<*mut c_void>(SUBSCRIPTIONS_IVAR);
+        if let Some(subscriptions) = (subscriptions as *const Subscriptions).as_ref() {
+            f(subscriptions);
+        }
+    }
+}
 
 // TODO: Implement event stream for all below callback
 
@@ -20,31 +41,39 @@ pub extern "C" fn peripheral_manager_did_update_state(
 ) {
     println!("peripheral_manager_did_update_state");
 
-    unsafe {
+    let state = unsafe {
         let state: CBManagerState = msg_send![peripheral, state];
         match state {
             CBManagerState::CBManagerStateUnknown => {
                 println!("CBManagerStateUnknown");
+                State::Unknown
             }
             CBManagerState::CBManagerStateResetting => {
                 println!("CBManagerStateResetting");
+                State::Resetting
             }
             CBManagerState::CBManagerStateUnsupported => {
                 println!("CBManagerStateUnsupported");
+                State::Unsupported
             }
             CBManagerState::CBManagerStateUnauthorized => {
                 println!("CBManagerStateUnauthorized");
+                State::Unauthorized
             }
             CBManagerState::CBManagerStatePoweredOff => {
                 println!("CBManagerStatePoweredOff");
                 delegate.set_ivar::<*mut Object>(POWERED_ON_IVAR, NO as *mut Object);
+                State::PoweredOff
             }
             CBManagerState::CBManagerStatePoweredOn => {
                 println!("CBManagerStatePoweredOn");
                 delegate.set_ivar::<*mut Object>(POWERED_ON_IVAR, YES as *mut Object);
+                State::PoweredOn
             }
-        };
-    }
+        }
+    };
+
+    broadcast(delegate, Event::StateChange(state));
 }
 
 pub extern "C" fn peripheral_manager_did_start_advertising_error(
@@ -101,3 +130,35 @@ pub extern "C" fn peripheral_manager_did_receive_write_requests(
         }
     }
 }
+
+pub extern "C" fn peripheral_manager_central_did_subscribe_to_characteristic(
+    delegate: &mut Object,
+    _cmd: Sel,
+    peripheral: *mut Object,
+    central: *mut Object,
+    characteristic: *mut Object,
+) {
+    with_subscriptions(delegate, |subscriptions| {
+        subscriptions.subscribe(peripheral, central, characteristic)
+    });
+}
+
+pub extern "C" fn peripheral_manager_central_did_unsubscribe_from_characteristic(
+    delegate: &mut Object,
+    _cmd: Sel,
+    _peripheral: *mut Object,
+    central: *mut Object,
+    characteristic: *mut Object,
+) {
+    with_subscriptions(delegate, |subscriptions| {
+        subscriptions.unsubscribe(central, characteristic)
+    });
+}
+
+pub extern "C" fn peripheral_manager_is_ready_to_update_subscribers(
+    delegate: &mut Object,
+    _cmd: Sel,
+    _peripheral: *mut Object,
+) {
+    with_subscriptions(delegate, Subscriptions::ready);
+}
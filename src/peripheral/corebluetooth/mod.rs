@@ -6,11 +6,17 @@ mod ffi;
 mod into_bool;
 mod into_cbuuid;
 mod peripheral_manager;
+mod subscriptions;
 
+use async_trait::async_trait;
 use uuid::Uuid;
 
 use self::peripheral_manager::PeripheralManager;
-use crate::{gatt::service::Service, Error};
+use super::event::EventStream;
+use crate::{
+    gatt::{hash::DatabaseHash, service::Service, subscribers::Subscriber},
+    Error, ErrorType,
+};
 
 pub struct Peripheral {
     peripheral_manager: PeripheralManager,
@@ -52,6 +58,83 @@ impl Peripheral {
 
     pub fn add_service(&self, service: &Service) -> Result<(), Error> {
         self.peripheral_manager.add_service(service);
+        service.start();
         Ok(())
     }
+
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        if self.peripheral_manager.remove_service(uuid) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                "ServiceNotFound",
+                format!("no service with UUID {}", uuid),
+                ErrorType::CoreBluetooth,
+            ))
+        }
+    }
+
+    /// CoreBluetooth indicates Service Changed to connected centrals itself.
+    pub fn database_hash(&self) -> DatabaseHash {
+        self.peripheral_manager.database_hash()
+    }
+
+    pub fn events(&self) -> EventStream {
+        self.peripheral_manager.events()
+    }
+
+    pub fn subscribers(&self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.peripheral_manager.subscribers(characteristic)
+    }
+}
+
+#[async_trait]
+impl super::Peripheral for Peripheral {
+    async fn new() -> Result<Self, Error> {
+        Peripheral::new().await
+    }
+
+    async fn is_powered(self: &Self) -> Result<bool, Error> {
+        self.is_powered().await
+    }
+
+    async fn register_gatt(self: &Self) -> Result<(), Error> {
+        self.register_gatt().await
+    }
+
+    async fn unregister_gatt(self: &Self) -> Result<(), Error> {
+        self.unregister_gatt().await
+    }
+
+    async fn start_advertising(self: &Self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
+        self.start_advertising(name, uuids).await
+    }
+
+    async fn stop_advertising(self: &Self) -> Result<(), Error> {
+        self.stop_advertising().await
+    }
+
+    async fn is_advertising(self: &Self) -> Result<bool, Error> {
+        self.is_advertising().await
+    }
+
+    fn add_service(self: &Self, service: &Service) -> Result<(), Error> {
+        self.add_service(service)
+    }
+
+    fn remove_service(self: &Self, uuid: &Uuid) -> Result<(), Error> {
+        self.remove_service(uuid)
+    }
+
+    fn database_hash(self: &Self) -> DatabaseHash {
+        self.database_hash()
+    }
+
+    fn events(self: &Self) -> EventStream {
+        self.events()
+    }
+
+    fn subscribers(self: &Self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.subscribers(characteristic)
+    }
 }
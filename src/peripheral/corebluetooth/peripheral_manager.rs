@@ -1,6 +1,8 @@
 use std::{
     ffi::CString,
-    sync::{Once, ONCE_INIT},
+    os::raw::c_void,
+    ptr,
+    sync::{Arc, Mutex, Once, ONCE_INIT},
 };
 
 use objc::{
@@ -15,17 +17,28 @@ use objc_foundation::{
 };
 use objc_id::{Id, Shared};
 
+use tokio::runtime::Handle;
 use uuid::Uuid;
 
-use crate::gatt::service::Service;
+use crate::{
+    gatt::{descriptor::Descriptor, hash::DatabaseHash, service::Service, subscribers::Subscriber},
+    peripheral::event::{EventBroadcaster, EventStream},
+    SdpShortUuid,
+};
 
 use super::{
     characteristic_flags::get_properties_and_permissions,
-    constants::{PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME, PERIPHERAL_MANAGER_IVAR, POWERED_ON_IVAR},
+    constants::{
+        EVENT_BROADCASTER_IVAR, PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME, PERIPHERAL_MANAGER_IVAR,
+        POWERED_ON_IVAR, PRESENTATION_FORMAT, SUBSCRIPTIONS_IVAR, USER_DESCRIPTION,
+    },
     events::{
+        peripheral_manager_central_did_subscribe_to_characteristic,
+        peripheral_manager_central_did_unsubscribe_from_characteristic,
         peripheral_manager_did_add_service_error, peripheral_manager_did_receive_read_request,
         peripheral_manager_did_receive_write_requests,
         peripheral_manager_did_start_advertising_error, peripheral_manager_did_update_state,
+        peripheral_manager_is_ready_to_update_subscribers,
     },
     ffi::{
         dispatch_queue_create, nil, CBAdvertisementDataLocalNameKey,
@@ -33,6 +46,7 @@ use super::{
     },
     into_bool::IntoBool,
     into_cbuuid::IntoCBUUID,
+    subscriptions::Subscriptions,
 };
 
 static REGISTER_DELEGATE_CLASS: Once = ONCE_INIT;
@@ -40,8 +54,16 @@ static REGISTER_DELEGATE_CLASS: Once = ONCE_INIT;
 #[derive(Debug)]
 pub struct PeripheralManager {
     peripheral_manager_delegate: Id<Object, Shared>,
+    events: Arc<EventBroadcaster>,
+    services: Mutex<Vec<(Service, Id<Object, Shared>)>>,
+    subscriptions: Arc<Subscriptions>,
 }
 
+// `CBPeripheralManager` delivers delegate callbacks on its own dispatch queue and may be messaged
+// from any thread.
+unsafe impl Send for PeripheralManager {}
+unsafe impl Sync for PeripheralManager {}
+
 impl PeripheralManager {
     pub fn new() -> Self {
         REGISTER_DELEGATE_CLASS.call_once(|| {
@@ -51,6 +73,8 @@ impl PeripheralManager {
 
             decl.add_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR);
             decl.add_ivar::<*mut Object>(POWERED_ON_IVAR);
+            decl.add_ivar::<*mut c_void>(EVENT_BROADCASTER_IVAR);
+            decl.add_ivar::<*mut c_void>(SUBSCRIPTIONS_IVAR);
 
             unsafe {
                 decl.add_method(
@@ -82,23 +106,61 @@ impl PeripheralManager {
                     peripheral_manager_did_receive_write_requests
                         as extern "C" fn(&mut Object, Sel, *mut Object, *mut Object),
                 );
+                decl.add_method(
+                    sel!(peripheralManager:central:didSubscribeToCharacteristic:),
+                    peripheral_manager_central_did_subscribe_to_characteristic
+                        as extern "C" fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object),
+                );
+                decl.add_method(
+                    sel!(peripheralManager:central:didUnsubscribeFromCharacteristic:),
+                    peripheral_manager_central_did_unsubscribe_from_characteristic
+                        as extern "C" fn(&mut Object, Sel, *mut Object, *mut Object, *mut Object),
+                );
+                decl.add_method(
+                    sel!(peripheralManagerIsReadyToUpdateSubscribers:),
+                    peripheral_manager_is_ready_to_update_subscribers
+                        as extern "C" fn(&mut Object, Sel, *mut Object),
+                );
             }
 
             decl.register();
         });
 
+        let events = Arc::new(EventBroadcaster::default());
+        // `Peripheral::new` is async, so this runs on the runtime subscriptions are served on.
+        let subscriptions = Arc::new(Subscriptions::new(Handle::current()));
+
         let peripheral_manager_delegate = unsafe {
             let cls = Class::get(PERIPHERAL_MANAGER_DELEGATE_CLASS_NAME).unwrap();
             let mut obj: *mut Object = msg_send![cls, alloc];
             obj = msg_send![obj, init];
+            (*obj).set_ivar::<*mut c_void>(
+                EVENT_BROADCASTER_IVAR,
+                Arc::into_raw(Arc::clone(&events)) as *mut c_void,
+            );
+            (*obj).set_ivar::<*mut c_void>(
+                SUBSCRIPTIONS_IVAR,
+                Arc::into_raw(Arc::clone(&subscriptions)) as *mut c_void,
+            );
             Id::from_ptr(obj).share()
         };
 
         PeripheralManager {
             peripheral_manager_delegate,
+            events,
+            services: Mutex::new(vec![]),
+            subscriptions,
         }
     }
 
+    pub fn events(&self) -> EventStream {
+        self.events.subscribe()
+    }
+
+    pub fn subscribers(&self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.subscriptions.of(characteristic)
+    }
+
     pub fn is_powered(self: &Self) -> bool {
         unsafe {
             let powered_on = *self
@@ -184,6 +246,19 @@ impl PeripheralManager {
                                                 permissions:permissions],
                     };
 
+                    let descriptors: Vec<Id<NSObject>> = characteristic
+                        .descriptors
+                        .iter()
+                        .filter_map(mutable_descriptor)
+                        .collect();
+                    let _: Result<(), ()> = msg_send![mutable_characteristic, setValue:NSArray::from_vec(descriptors)
+                                                   forKey:NSString::from_str("descriptors")];
+
+                    self.subscriptions.add(
+                        service.uuid,
+                        characteristic,
+                        Id::from_ptr(mutable_characteristic).share(),
+                    );
                     Id::from_ptr(mutable_characteristic as *mut NSObject)
                 }
             })
@@ -192,16 +267,99 @@ impl PeripheralManager {
         unsafe {
             let cls = class!(CBMutableService);
             let obj: *mut Object = msg_send![cls, alloc];
-            let service: *mut Object = msg_send![obj, initWithType:service.uuid.into_cbuuid()
-                                                           primary:YES];
-            let _: Result<(), ()> = msg_send![service, setValue:NSArray::from_vec(characteristics)
+            let mutable_service: *mut Object = msg_send![obj, initWithType:service.uuid.into_cbuuid()
+                                                                   primary:YES];
+            let _: Result<(), ()> = msg_send![mutable_service, setValue:NSArray::from_vec(characteristics)
                                  forKey:NSString::from_str("characteristics")];
 
             let peripheral_manager = *self
                 .peripheral_manager_delegate
                 .get_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR);
 
-            let _: Result<(), ()> = msg_send![peripheral_manager, addService: service];
+            let _: Result<(), ()> = msg_send![peripheral_manager, addService: mutable_service];
+
+            // `removeService:` needs the very object that was added.
+            self.services.lock().unwrap().push((
+                service.clone(),
+                Id::from_retained_ptr(mutable_service).share(),
+            ));
+        }
+    }
+
+    pub fn remove_service(self: &Self, uuid: &Uuid) -> bool {
+        let mut services = self.services.lock().unwrap();
+        let count = services.len();
+        services.retain(|(added, service)| {
+            if added.uuid != *uuid {
+                return true;
+            }
+            unsafe {
+                let peripheral_manager = *self
+                    .peripheral_manager_delegate
+                    .get_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR);
+                let service: &Object = service;
+                let _: Result<(), ()> = msg_send![peripheral_manager, removeService: service];
+            }
+            false
+        });
+        self.subscriptions.remove(uuid);
+        services.len() != count
+    }
+
+    pub fn database_hash(&self) -> DatabaseHash {
+        DatabaseHash::new(
+            self.services
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(service, _)| service),
+        )
+    }
+}
+
+/// CoreBluetooth only publishes descriptors with a fixed value, which it serves itself, and of
+/// those only User Descriptions and Presentation Formats.
+fn mutable_descriptor(descriptor: &Descriptor) -> Option<Id<NSObject>> {
+    let value = descriptor.value.as_ref()?;
+    let string;
+    let data;
+    let value: *const Object = if descriptor.uuid == Uuid::from_sdp_short_uuid(USER_DESCRIPTION) {
+        string = NSString::from_str(&String::from_utf8_lossy(value));
+        &*string as *const NSString as *const Object
+    } else if descriptor.uuid == Uuid::from_sdp_short_uuid(PRESENTATION_FORMAT) {
+        data = NSData::with_bytes(value);
+        &*data as *const NSData as *const Object
+    } else {
+        log::warn!(
+            "CoreBluetooth does not support descriptor {}, leaving it out",
+            descriptor.uuid
+        );
+        return None;
+    };
+
+    unsafe {
+        let cls = class!(CBMutableDescriptor);
+        let obj: *mut Object = msg_send![cls, alloc];
+        let mutable_descriptor: *mut Object =
+            msg_send![obj, initWithType:descriptor.uuid.into_cbuuid() value:value];
+        Some(Id::from_ptr(mutable_descriptor as *mut NSObject))
+    }
+}
+
+impl Drop for PeripheralManager {
+    fn drop(&mut self) {
+        unsafe {
+            let delegate = &*self.peripheral_manager_delegate as *const Object as *mut Object;
+            let events = *(*delegate).get_ivar::<*mut c_void>(EVENT_BROADCASTER_IVAR);
+            (*delegate).set_ivar::<*mut c_void>(EVENT_BROADCASTER_IVAR, ptr::null_mut());
+            if !events.is_null() {
+                drop(Arc::from_raw(events as *const EventBroadcaster));
+            }
+            let subscriptions = *(*delegate).get_ivar::<*mut c_void>(SUBSCRIPTIONS_IVAR);
+            (*delegate).set_ivar::<*mut c_void>(SUBSCRIPTIONS_IVAR, ptr::null_mut());
+            if !subscriptions.is_null() {
+                drop(Arc::from_raw(subscriptions as *const Subscriptions));
+            }
         }
     }
 }
@@ -228,6 +386,8 @@ extern "C" fn init(delegate: &mut Object, _cmd: Sel) -> *mut Object {
         delegate.set_ivar::<*mut Object>(PERIPHERAL_MANAGER_IVAR, obj);
 
         delegate.set_ivar::<*mut Object>(POWERED_ON_IVAR, NO as *mut Object);
+        delegate.set_ivar::<*mut c_void>(EVENT_BROADCASTER_IVAR, ptr::null_mut());
+        delegate.set_ivar::<*mut c_void>(SUBSCRIPTIONS_IVAR, ptr::null_mut());
 
         delegate
     }
@@ -0,0 +1,303 @@
+//! Centrals subscribed to characteristics, shared by the `PeripheralManager` and its delegate.
+
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    stream,
+};
+use objc::{
+    msg_send,
+    runtime::{Object, BOOL, YES},
+    sel, sel_impl,
+};
+use objc_foundation::{INSArray, INSData, INSString, NSArray, NSData, NSObject, NSString};
+use objc_id::{Id, Shared};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+use super::constants::NOTIFICATION_CHANNEL_CAPACITY;
+use crate::gatt::{
+    characteristic::Characteristic,
+    event::{
+        Event, EventSender, Indication, Link, NotifyMode, NotifySubscribe, NotifyUnsubscribe,
+        RequestContext, SubscriptionId,
+    },
+    subscribers::{Subscriber, Subscribers},
+};
+
+type Waiters = Arc<Mutex<Vec<oneshot::Sender<()>>>>;
+
+pub struct Subscriptions {
+    /// Delegate callbacks come in on CoreBluetooth's dispatch queue, outside of any runtime.
+    runtime: Handle,
+    /// The characteristics that can be subscribed to, with the UUID of their service and the
+    /// `CBMutableCharacteristic` CoreBluetooth knows them by.
+    characteristics: Mutex<Vec<(Uuid, Characteristic, Id<Object, Shared>)>>,
+    open: Mutex<Vec<Open>>,
+    subscribers: Subscribers,
+    /// Sends subscription events in the order CoreBluetooth reported them.
+    events: mpsc::UnboundedSender<(EventSender, Event)>,
+    /// Values waiting for `peripheralManagerIsReadyToUpdateSubscribers:`.
+    waiters: Waiters,
+}
+
+struct Open {
+    id: SubscriptionId,
+    context: RequestContext,
+    characteristic: Id<Object, Shared>,
+    event_sender: EventSender,
+    // Dropping it stops forwarding values, and fails any indication in flight.
+    _stopped: oneshot::Sender<()>,
+}
+
+// Like `PeripheralManager`, the objects it holds may be messaged from any thread.
+unsafe impl Send for Subscriptions {}
+unsafe impl Sync for Subscriptions {}
+
+impl Subscriptions {
+    pub fn new(runtime: Handle) -> Self {
+        let (events, mut receiver) = mpsc::unbounded::<(EventSender, Event)>();
+        runtime.spawn(async move {
+            while let Some((mut event_sender, event)) = receiver.next().await {
+                event_sender.send(event).await.ok();
+            }
+        });
+        Subscriptions {
+            runtime,
+            characteristics: Mutex::new(vec![]),
+            open: Mutex::new(vec![]),
+            subscribers: Subscribers::default(),
+            events,
+            waiters: Arc::default(),
+        }
+    }
+
+    /// Lets centrals subscribe to `characteristic`, if it notifies or indicates.
+    pub fn add(&self, service: Uuid, characteristic: &Characteristic, object: Id<Object, Shared>) {
+        let properties = &characteristic.properties;
+        if properties.notify.is_some() || properties.indicate.is_some() {
+            self.characteristics
+                .lock()
+                .unwrap()
+                .push((service, characteristic.clone(), object));
+        }
+    }
+
+    /// Ends the subscriptions to the characteristics of `service`, which is being removed.
+    pub fn remove(&self, service: &Uuid) {
+        let mut characteristics = self.characteristics.lock().unwrap();
+        let (removed, kept) = characteristics
+            .drain(..)
+            .partition::<Vec<_>, _>(|(uuid, _, _)| uuid == service);
+        *characteristics = kept;
+        drop(characteristics);
+
+        let mut open = self.open.lock().unwrap();
+        let (ended, kept) = open.drain(..).partition::<Vec<_>, _>(|open| {
+            removed
+                .iter()
+                .any(|(_, _, object)| is_same(object, &*open.characteristic))
+        });
+        *open = kept;
+        drop(open);
+        for open in ended {
+            self.end(open);
+        }
+    }
+
+    pub fn of(&self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.subscribers.of(characteristic)
+    }
+
+    /// Called by `peripheralManager:central:didSubscribeToCharacteristic:`.
+    pub fn subscribe(
+        &self,
+        peripheral_manager: *mut Object,
+        central: *mut Object,
+        object: *mut Object,
+    ) {
+        let (characteristic, object) = match self
+            .characteristics
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, _, added)| is_same(added, object))
+        {
+            Some((_, characteristic, object)) => (characteristic.clone(), object.clone()),
+            None => return,
+        };
+        let properties = &characteristic.properties;
+        // CoreBluetooth does not say which one a central enabled, like BlueZ.
+        let (mode, event_sender) = match (&properties.notify, &properties.indicate) {
+            (Some(notify), _) => (NotifyMode::Notify, notify.clone()),
+            (None, Some(indicate)) => (NotifyMode::Indicate, indicate.clone()),
+            (None, None) => return,
+        };
+
+        let context = unsafe { context(characteristic.uuid, central) };
+        let id = SubscriptionId::next();
+        let (notification, notifications) = mpsc::channel(NOTIFICATION_CHANNEL_CAPACITY);
+        let (indications, indication_receiver) = if mode == NotifyMode::Indicate {
+            let (sender, receiver) = mpsc::channel(NOTIFICATION_CHANNEL_CAPACITY);
+            (Some(sender), Some(receiver))
+        } else {
+            (None, None)
+        };
+        let (stopped, stop) = oneshot::channel();
+        let values = stream::select(
+            notifications.map(|value| (value, None)),
+            stream::iter(indication_receiver)
+                .flatten()
+                .map(|Indication { value, confirmed }| (value, Some(confirmed))),
+        )
+        .take_until(stop);
+        let target = unsafe {
+            Target {
+                peripheral_manager: Id::from_ptr(peripheral_manager).share(),
+                characteristic: object.clone(),
+                central: Id::from_ptr(central as *mut NSObject).share(),
+            }
+        };
+        self.runtime
+            .spawn(forward(target, values, Arc::clone(&self.waiters)));
+
+        self.subscribers.add(Subscriber {
+            id,
+            context: context.clone(),
+            mode,
+        });
+        self.open.lock().unwrap().push(Open {
+            id,
+            context: context.clone(),
+            characteristic: object,
+            event_sender: event_sender.clone(),
+            _stopped: stopped,
+        });
+        let notify_subscribe = NotifySubscribe {
+            id,
+            context,
+            notification,
+            mode,
+            indications,
+        };
+        self.events
+            .unbounded_send((event_sender, Event::NotifySubscribe(notify_subscribe)))
+            .ok();
+    }
+
+    /// Called by `peripheralManager:central:didUnsubscribeFromCharacteristic:`.
+    pub fn unsubscribe(&self, central: *mut Object, object: *mut Object) {
+        let device = unsafe { identifier(central) };
+        let mut open = self.open.lock().unwrap();
+        let index = open.iter().position(|open| {
+            is_same(&open.characteristic, object) && open.context.device.as_ref() == Some(&device)
+        });
+        let ended = index.map(|index| open.remove(index));
+        drop(open);
+        if let Some(ended) = ended {
+            self.end(ended);
+        }
+    }
+
+    /// Called by `peripheralManagerIsReadyToUpdateSubscribers:`.
+    pub fn ready(&self) {
+        for waiter in self.waiters.lock().unwrap().drain(..) {
+            waiter.send(()).ok();
+        }
+    }
+
+    fn end(&self, open: Open) {
+        let Open {
+            id,
+            context,
+            event_sender,
+            ..
+        } = open;
+        self.subscribers.remove(id);
+        let notify_unsubscribe = NotifyUnsubscribe { id, context };
+        self.events
+            .unbounded_send((event_sender, Event::NotifyUnsubscribe(notify_unsubscribe)))
+            .ok();
+    }
+}
+
+/// Where `updateValue:forCharacteristic:onSubscribedCentrals:` sends the values of a
+/// subscription.
+struct Target {
+    peripheral_manager: Id<Object, Shared>,
+    characteristic: Id<Object, Shared>,
+    central: Id<NSObject, Shared>,
+}
+
+unsafe impl Send for Target {}
+
+impl Target {
+    /// Whether CoreBluetooth took the value; it refuses it while its queue is full.
+    fn update(&self, value: &[u8]) -> bool {
+        let data = NSData::with_bytes(value);
+        let centrals = NSArray::from_vec(vec![self.central.clone()]);
+        let peripheral_manager: &Object = &self.peripheral_manager;
+        let characteristic: &Object = &self.characteristic;
+        let data: &NSData = &data;
+        let centrals: &NSArray<NSObject, Shared> = &centrals;
+        let updated: BOOL = unsafe {
+            msg_send![peripheral_manager, updateValue:data
+                                    forCharacteristic:characteristic
+                                 onSubscribedCentrals:centrals]
+        };
+        updated == YES
+    }
+}
+
+/// Sends the values of a subscription until it ends, waiting for room in CoreBluetooth's queue
+/// whenever it is full.
+async fn forward<S>(target: Target, mut values: S, waiters: Waiters)
+where
+    S: Stream<Item = (Vec<u8>, Option<oneshot::Sender<()>>)> + Unpin,
+{
+    while let Some((value, confirmed)) = values.next().await {
+        loop {
+            // Registered before trying, so readiness in between is not missed.
+            let (waiter, ready) = oneshot::channel();
+            {
+                let mut waiters = waiters.lock().unwrap();
+                waiters.retain(|waiter| !waiter.is_canceled());
+                waiters.push(waiter);
+            }
+            if target.update(&value) {
+                break;
+            }
+            if ready.await.is_err() {
+                return;
+            }
+        }
+        // CoreBluetooth waits for the confirmation of an indication itself and does not report
+        // it, so a queued indication counts as confirmed.
+        if let Some(confirmed) = confirmed {
+            confirmed.send(()).ok();
+        }
+    }
+}
+
+fn is_same(object: &Id<Object, Shared>, other: *const Object) -> bool {
+    &**object as *const Object == other
+}
+
+/// The `identifier` of a `CBCentral`, which stays the same for as long as it is connected.
+unsafe fn identifier(central: *mut Object) -> String {
+    let identifier: *mut Object = msg_send![central, identifier];
+    let string: *mut Object = msg_send![identifier, UUIDString];
+    (*(string as *mut NSString)).as_str().to_string()
+}
+
+unsafe fn context(uuid: Uuid, central: *mut Object) -> RequestContext {
+    let maximum_update_value_length: usize = msg_send![central, maximumUpdateValueLength];
+    RequestContext {
+        uuid,
+        device: Some(identifier(central)),
+        // The longest value it takes leaves out the three bytes of the notification header.
+        mtu: Some((maximum_update_value_length + 3) as u16),
+        link: Some(Link::Le),
+    }
+}
@@ -0,0 +1,65 @@
+use futures::channel::mpsc;
+use std::sync::Mutex;
+
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 16;
+
+pub type EventStream = mpsc::Receiver<Event>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The controller changed state, e.g. it was switched on or off.
+    StateChange(State),
+    /// The controller started or stopped being discoverable.
+    DiscoverableChange(bool),
+    /// A controller was plugged in, identified the way the backend names adapters.
+    AdapterAdded(String),
+    /// A controller was unplugged, identified the way the backend names adapters.
+    AdapterRemoved(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Unknown,
+    Resetting,
+    Unsupported,
+    Unauthorized,
+    PoweredOff,
+    PoweredOn,
+}
+
+#[derive(Debug)]
+pub(crate) struct EventBroadcaster {
+    capacity: usize,
+    subscribers: Mutex<Vec<mpsc::Sender<Event>>>,
+}
+
+impl Default for EventBroadcaster {
+    fn default() -> Self {
+        EventBroadcaster::with_capacity(EVENT_CHANNEL_CAPACITY)
+    }
+}
+
+impl EventBroadcaster {
+    pub fn with_capacity(capacity: usize) -> Self {
+        EventBroadcaster {
+            capacity,
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn subscribe(self: &Self) -> EventStream {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn broadcast(self: &Self, event: Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| !subscriber.is_closed());
+        for subscriber in subscribers.iter_mut() {
+            if subscriber.try_send(event.clone()).is_err() {
+                log::warn!("Dropping peripheral event {:?}, subscriber is full", event);
+            }
+        }
+    }
+}
@@ -1,22 +1,72 @@
 #[cfg(any(target_os = "macos", target_os = "ios"))]
-mod corebluetooth;
+pub mod corebluetooth;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
-pub use self::corebluetooth::Peripheral;
+pub use self::corebluetooth::Peripheral as DefaultPeripheral;
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
-mod bluez;
+pub mod bluez;
 #[cfg(any(target_os = "linux", target_os = "android"))]
-pub use self::bluez::Peripheral;
+pub use self::bluez::Peripheral as DefaultPeripheral;
 
 #[cfg(any(target_os = "windows", target_os = "freebsd"))]
 mod usb;
 #[cfg(any(target_os = "windows", target_os = "freebsd"))]
-pub use self::usb::Peripheral;
+pub use self::usb::Peripheral as DefaultPeripheral;
 
-// TODO: Add struct / traits to implement for each OS
+pub mod event;
+pub mod sim;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use self::event::EventStream;
+use crate::{
+    gatt::{hash::DatabaseHash, service::Service, subscribers::Subscriber},
+    Error,
+};
+
+/// A BLE peripheral backend.
+///
+/// Every platform backend implements this trait, so application code can be written generically
+/// over the backend and exercised against a test double.
+#[async_trait]
+pub trait Peripheral: Sized + Send + Sync {
+    async fn new() -> Result<Self, Error>;
+
+    async fn is_powered(self: &Self) -> Result<bool, Error>;
+
+    async fn register_gatt(self: &Self) -> Result<(), Error>;
+
+    async fn unregister_gatt(self: &Self) -> Result<(), Error>;
+
+    async fn start_advertising(self: &Self, name: &str, uuids: &[Uuid]) -> Result<(), Error>;
+
+    async fn stop_advertising(self: &Self) -> Result<(), Error>;
+
+    async fn is_advertising(self: &Self) -> Result<bool, Error>;
+
+    /// Adds a service to the GATT database, also after `register_gatt`.
+    fn add_service(self: &Self, service: &Service) -> Result<(), Error>;
+
+    /// Removes every service with the given UUID from the GATT database.
+    fn remove_service(self: &Self, uuid: &Uuid) -> Result<(), Error>;
+
+    /// Returns the hash of the services currently in the GATT database.
+    ///
+    /// The backend tells connected centrals about added and removed services with a Service
+    /// Changed indication; BlueZ and CoreBluetooth do this on their own.
+    fn database_hash(self: &Self) -> DatabaseHash;
+
+    /// Returns a new stream of state changes reported by the backend.
+    fn events(self: &Self) -> EventStream;
+
+    /// Returns the centrals currently subscribed to every characteristic with the given UUID.
+    fn subscribers(self: &Self, characteristic: &Uuid) -> Vec<Subscriber>;
+}
+
+// TODO: Add remaining events to `event::Event`
 //
 // pub enum BindingsEvent {
-//     StateChange,
 //     Platform,
 //     AddressChange,
 //     AdvertisingStart,
@@ -29,16 +79,6 @@ pub use self::usb::Peripheral;
 // }
 //
 // #[derive(Debug, Clone)]
-// pub enum State {
-//     Unknown,
-//     Resetting,
-//     Unsupported,
-//     Unauthorized,
-//     PoweredOff,
-//     PoweredOn,
-// }
-//
-// #[derive(Debug, Clone)]
 // pub struct Ble {
 //     initialized: bool,
 //     platform: String, // TODO: Make this an enum?
@@ -0,0 +1,686 @@
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    task::{Context, Poll},
+};
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+};
+use uuid::Uuid;
+
+use super::{
+    database::{Attribute, RemoteService},
+    Advertisement, Inner,
+};
+use crate::{
+    gatt::{
+        characteristic,
+        event::{
+            self, Access, Authorization, Event, EventSender, Indication, Link, LongValue,
+            NotifyMode, NotifySubscribe, NotifyUnsubscribe, ReadRequest, RequestContext, Response,
+            SubscriptionId, WriteKind, WriteRequest,
+        },
+        security::{Security, SecurityLevel},
+        subscribers::Subscriber,
+    },
+    Error, ErrorType,
+};
+
+const NOTIFICATION_CHANNEL_CAPACITY: usize = 16;
+/// The ATT MTU until the central exchanges a bigger one.
+const DEFAULT_MTU: u16 = 23;
+
+/// A scriptable central connected to a simulated `Peripheral`.
+#[derive(Debug)]
+pub struct VirtualCentral {
+    peripheral: Arc<Inner>,
+    address: String,
+    mtu: AtomicU16,
+    security: Mutex<SecurityLevel>,
+    connected: AtomicBool,
+    subscriptions: Mutex<HashMap<u16, Subscription>>,
+    prepared: Mutex<Vec<(u16, u16, Vec<u8>)>>,
+}
+
+impl VirtualCentral {
+    pub(super) fn new(peripheral: Arc<Inner>, index: u16) -> Self {
+        let [high, low] = index.to_be_bytes();
+        VirtualCentral {
+            peripheral,
+            address: format!("00:00:00:00:{:02X}:{:02X}", high, low),
+            mtu: AtomicU16::new(DEFAULT_MTU),
+            security: Mutex::new(SecurityLevel::None),
+            connected: AtomicBool::new(true),
+            subscriptions: Mutex::new(HashMap::new()),
+            prepared: Mutex::new(vec![]),
+        }
+    }
+
+    /// The Bluetooth address the peripheral sees requests from, unique per central.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn mtu(&self) -> u16 {
+        self.mtu.load(Ordering::Relaxed)
+    }
+
+    /// Simulates an MTU exchange; later requests carry the new MTU.
+    pub fn set_mtu(&self, mtu: u16) {
+        self.mtu.store(mtu, Ordering::Relaxed);
+    }
+
+    /// Simulates pairing or encrypting the link; centrals start out at `SecurityLevel::None`.
+    pub fn set_security(&self, level: SecurityLevel) {
+        *self.security.lock().unwrap() = level;
+    }
+
+    /// Returns the advertisement currently broadcast by the peripheral, if any.
+    pub fn scan(&self) -> Option<Advertisement> {
+        self.peripheral.advertisement.lock().unwrap().clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub async fn discover_services(&self) -> Result<Vec<RemoteService>, Error> {
+        self.check_ready()?;
+        Ok(self.peripheral.database.lock().unwrap().services())
+    }
+
+    pub async fn read(&self, handle: u16, offset: u16) -> Result<Response, Error> {
+        let attribute = self.attribute(handle)?;
+        let (security, value, read) = match attribute {
+            Attribute::Characteristic(ref characteristic) => (
+                characteristic.security,
+                characteristic.value.clone(),
+                characteristic
+                    .properties
+                    .read
+                    .clone()
+                    .map(|read| read.sender()),
+            ),
+            Attribute::Descriptor(ref descriptor) => (
+                descriptor.security,
+                descriptor.value.clone(),
+                descriptor.properties.read.clone().map(|read| read.sender()),
+            ),
+        };
+        if let Err(response) = self.check_security(security.read) {
+            return Ok(response);
+        }
+        if let Some(value) = value {
+            return Ok(Response::read_value(&value, offset));
+        }
+        let event_sender = read.ok_or_else(|| not_supported("read", handle))?;
+        let target = Target::new(&attribute, event_sender);
+        if !self.authorized(&target, Access::Read).await {
+            return Ok(Response::InsufficientAuthorization);
+        }
+        if target.long_value.is_some() {
+            let context = self.context(target.uuid);
+            return event::read_long(target.event_sender, context, offset)
+                .await
+                .map_err(|_| handler_gone());
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        request(
+            target.event_sender,
+            Event::ReadRequest(ReadRequest {
+                context: self.context(target.uuid),
+                offset,
+                response: sender,
+            }),
+            receiver,
+        )
+        .await
+    }
+
+    pub async fn write<T: Into<Vec<u8>>>(
+        &self,
+        handle: u16,
+        offset: u16,
+        data: T,
+    ) -> Result<Response, Error> {
+        self.write_kind(handle, offset, data.into(), WriteKind::Request)
+            .await
+    }
+
+    /// Sends a write command, which the peripheral does not answer.
+    pub async fn write_without_response<T: Into<Vec<u8>>>(
+        &self,
+        handle: u16,
+        data: T,
+    ) -> Result<(), Error> {
+        let attribute = self.attribute(handle)?;
+        let event_sender = match attribute {
+            Attribute::Characteristic(ref characteristic) => {
+                match characteristic.properties.write {
+                    Some(characteristic::Write::WithoutResponse(ref event_sender)) => {
+                        Some(event_sender.clone())
+                    }
+                    _ => None,
+                }
+            }
+            Attribute::Descriptor(_) => None,
+        }
+        .ok_or_else(|| not_supported("write without response", handle))?;
+        let mut target = Target::new(&attribute, event_sender);
+        // An unauthorized command is dropped without telling the central.
+        if !self
+            .authorized(&target, Access::Write(WriteKind::Command))
+            .await
+        {
+            return Ok(());
+        }
+
+        let (sender, _) = oneshot::channel();
+        target
+            .event_sender
+            .send(Event::WriteRequest(WriteRequest {
+                context: self.context(target.uuid),
+                data: data.into(),
+                offset: 0,
+                kind: WriteKind::Command,
+                response: sender,
+            }))
+            .await
+            .map_err(|_| handler_gone())
+    }
+
+    /// Queues part of a reliable write, to be written by `execute_write`.
+    pub fn prepare_write<T: Into<Vec<u8>>>(
+        &self,
+        handle: u16,
+        offset: u16,
+        data: T,
+    ) -> Result<(), Error> {
+        self.writable(handle)?;
+        self.prepared
+            .lock()
+            .unwrap()
+            .push((handle, offset, data.into()));
+        Ok(())
+    }
+
+    /// Writes or, if `execute` is false, discards the queued parts of a reliable write.
+    ///
+    /// Like bluetoothd, consecutive parts for the same attribute are joined into a single
+    /// `WriteKind::Reliable` request. Every part is checked for security and authorization
+    /// before any is written, so a part refused there leaves all of them unwritten.
+    ///
+    /// This is not atomic, though: the application only sees a part when it is written, and
+    /// may still refuse it after earlier parts went through. Those stay written, the refusal
+    /// is returned and the rest of the queue is dropped.
+    pub async fn execute_write(&self, execute: bool) -> Result<Response, Error> {
+        let prepared = self.prepared.lock().unwrap().split_off(0);
+        if !execute {
+            return Ok(Response::Success(vec![]));
+        }
+        let mut writes: Vec<(u16, u16, Vec<u8>)> = vec![];
+        for (handle, offset, data) in prepared {
+            match writes.last_mut() {
+                Some((last_handle, last_offset, last_data))
+                    if *last_handle == handle
+                        && usize::from(*last_offset) + last_data.len() == usize::from(offset) =>
+                {
+                    last_data.extend(data)
+                }
+                _ => writes.push((handle, offset, data)),
+            }
+        }
+
+        let mut checked = vec![];
+        for (handle, offset, data) in writes {
+            match self
+                .check_write(handle, offset, data, WriteKind::Reliable)
+                .await?
+            {
+                Ok(write) => checked.push(write),
+                Err(response) => return Ok(response),
+            }
+        }
+        for write in checked {
+            let response = write.send().await?;
+            if response != Response::Success(vec![]) {
+                return Ok(response);
+            }
+        }
+        Ok(Response::Success(vec![]))
+    }
+
+    async fn write_kind(
+        &self,
+        handle: u16,
+        offset: u16,
+        data: Vec<u8>,
+        kind: WriteKind,
+    ) -> Result<Response, Error> {
+        match self.check_write(handle, offset, data, kind).await? {
+            Ok(write) => write.send().await,
+            Err(response) => Ok(response),
+        }
+    }
+
+    /// Takes a write as far as it goes before the application sees it, returning the response
+    /// if it is refused on the way.
+    async fn check_write(
+        &self,
+        handle: u16,
+        offset: u16,
+        data: Vec<u8>,
+        kind: WriteKind,
+    ) -> Result<Result<CheckedWrite, Response>, Error> {
+        let target = self.writable(handle)?;
+        if let Err(response) = self.check_security(target.security.write) {
+            return Ok(Err(response));
+        }
+        if !self.authorized(&target, Access::Write(kind)).await {
+            return Ok(Err(Response::InsufficientAuthorization));
+        }
+        Ok(Ok(CheckedWrite {
+            context: self.context(target.uuid),
+            target,
+            data,
+            offset,
+            kind,
+        }))
+    }
+
+    /// Subscribes to notifications, or to indications if the characteristic cannot notify.
+    pub async fn subscribe(&self, handle: u16) -> Result<Notifications, Error> {
+        let mode = match self.attribute(handle)? {
+            Attribute::Characteristic(ref characteristic)
+                if characteristic.properties.notify.is_none() =>
+            {
+                NotifyMode::Indicate
+            }
+            _ => NotifyMode::Notify,
+        };
+        self.subscribe_with(handle, mode).await
+    }
+
+    /// Subscribes in `mode`; indications are confirmed as the `Notifications` yield them.
+    pub async fn subscribe_with(
+        &self,
+        handle: u16,
+        mode: NotifyMode,
+    ) -> Result<Notifications, Error> {
+        let (uuid, level, mut event_sender) = match self.attribute(handle)? {
+            Attribute::Characteristic(characteristic) => {
+                let uuid = characteristic.uuid;
+                let security = characteristic.security;
+                let properties = characteristic.properties;
+                match mode {
+                    NotifyMode::Notify => properties
+                        .notify
+                        .map(|event_sender| (uuid, security.notify, event_sender)),
+                    NotifyMode::Indicate => properties
+                        .indicate
+                        .map(|event_sender| (uuid, security.indicate, event_sender)),
+                }
+            }
+            Attribute::Descriptor(_) => None,
+        }
+        .ok_or_else(|| not_supported("subscribe", handle))?;
+        self.check_security(level).map_err(|response| {
+            Error::new(
+                "InsufficientSecurity",
+                format!("subscribing to {:#06x} was refused: {:?}", handle, response),
+                ErrorType::Sim,
+            )
+        })?;
+
+        // Subscribing again replaces the mode, like writing the CCC descriptor does.
+        let previous = self.subscriptions.lock().unwrap().remove(&handle);
+        if let Some(previous) = previous {
+            self.end(previous).await?;
+        }
+
+        let (sender, receiver) = mpsc::channel(NOTIFICATION_CHANNEL_CAPACITY);
+        // Without a sender, the indications end right away for a notify subscription.
+        let (indication_sender, indications) = mpsc::channel(NOTIFICATION_CHANNEL_CAPACITY);
+        let id = SubscriptionId::next();
+        let context = self.context(uuid);
+        event_sender
+            .send(Event::NotifySubscribe(NotifySubscribe {
+                id,
+                context: context.clone(),
+                notification: sender,
+                mode,
+                indications: Some(indication_sender).filter(|_| mode == NotifyMode::Indicate),
+            }))
+            .await
+            .map_err(|_| handler_gone())?;
+        self.peripheral.subscribers.add(Subscriber {
+            id,
+            context: context.clone(),
+            mode,
+        });
+        self.subscriptions.lock().unwrap().insert(
+            handle,
+            Subscription {
+                id,
+                context,
+                event_sender,
+            },
+        );
+
+        Ok(Notifications {
+            receiver,
+            indications,
+        })
+    }
+
+    pub async fn unsubscribe(&self, handle: u16) -> Result<(), Error> {
+        self.check_ready()?;
+        let subscription = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .remove(&handle)
+            .ok_or_else(|| {
+                Error::new(
+                    "NotSubscribed",
+                    format!("not subscribed to handle {:#06x}", handle),
+                    ErrorType::Sim,
+                )
+            })?;
+        self.end(subscription).await
+    }
+
+    /// Subscribes to the Service Changed characteristic, which indicates the handle range of
+    /// every service added or removed while the GATT application is registered.
+    pub fn service_changes(&self) -> ServiceChanges {
+        let (sender, receiver) = mpsc::unbounded();
+        self.peripheral.service_changed.lock().unwrap().push(sender);
+        ServiceChanges { receiver }
+    }
+
+    /// Drops the connection, unsubscribing from every characteristic like a real link loss would.
+    pub async fn disconnect(&self) {
+        self.connected.store(false, Ordering::Relaxed);
+        let subscriptions: Vec<Subscription> = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, subscription)| subscription)
+            .collect();
+        for subscription in subscriptions {
+            self.end(subscription).await.ok();
+        }
+    }
+
+    async fn end(&self, mut subscription: Subscription) -> Result<(), Error> {
+        self.peripheral.subscribers.remove(subscription.id);
+        subscription
+            .event_sender
+            .send(Event::NotifyUnsubscribe(NotifyUnsubscribe {
+                id: subscription.id,
+                context: subscription.context,
+            }))
+            .await
+            .map_err(|_| handler_gone())
+    }
+
+    fn check_ready(&self) -> Result<(), Error> {
+        if !self.is_connected() {
+            return Err(Error::new(
+                "NotConnected",
+                "central is disconnected",
+                ErrorType::Sim,
+            ));
+        }
+        if !self.peripheral.powered.load(Ordering::Relaxed) {
+            return Err(Error::new(
+                "NotReady",
+                "peripheral is powered off",
+                ErrorType::Sim,
+            ));
+        }
+        if !self.peripheral.registered.load(Ordering::Relaxed) {
+            return Err(Error::new(
+                "NotRegistered",
+                "peripheral has not registered its GATT application",
+                ErrorType::Sim,
+            ));
+        }
+        Ok(())
+    }
+
+    /// The attribute `handle`, if it can be written with a response.
+    fn writable(&self, handle: u16) -> Result<Target, Error> {
+        let attribute = self.attribute(handle)?;
+        let write = match attribute {
+            Attribute::Characteristic(ref characteristic) => {
+                match characteristic.properties.write {
+                    Some(characteristic::Write::WithResponse(ref secure)) => {
+                        Some(secure.clone().sender())
+                    }
+                    _ => None,
+                }
+            }
+            Attribute::Descriptor(ref descriptor) => descriptor
+                .properties
+                .write
+                .clone()
+                .map(|write| write.sender()),
+        };
+        let event_sender = write.ok_or_else(|| not_supported("write", handle))?;
+        Ok(Target::new(&attribute, event_sender))
+    }
+
+    /// Refuses an operation that requires a better secured link, like an ATT server would.
+    fn check_security(&self, required: SecurityLevel) -> Result<(), Response> {
+        let security = *self.security.lock().unwrap();
+        if security >= required {
+            Ok(())
+        } else if required == SecurityLevel::Encrypted {
+            Err(Response::InsufficientEncryption)
+        } else {
+            Err(Response::InsufficientAuthentication)
+        }
+    }
+
+    /// Whether `target` may be accessed, asking it first if it requires authorization.
+    async fn authorized(&self, target: &Target, access: Access) -> bool {
+        if !target.authorize {
+            return true;
+        }
+        let context = self.context(target.uuid);
+        event::authorize(target.event_sender.clone(), context, access).await == Authorization::Allow
+    }
+
+    fn context(&self, uuid: Uuid) -> RequestContext {
+        RequestContext {
+            uuid,
+            device: Some(self.address.clone()),
+            mtu: Some(self.mtu()),
+            link: Some(Link::Le),
+        }
+    }
+
+    fn attribute(&self, handle: u16) -> Result<Attribute, Error> {
+        self.check_ready()?;
+        self.peripheral
+            .database
+            .lock()
+            .unwrap()
+            .attribute(handle)
+            .ok_or_else(|| {
+                Error::new(
+                    "InvalidHandle",
+                    format!("no attribute with handle {:#06x}", handle),
+                    ErrorType::Sim,
+                )
+            })
+    }
+}
+
+/// A subscription of this central, ended by sending `NotifyUnsubscribe` to its event sender.
+#[derive(Debug)]
+struct Subscription {
+    id: SubscriptionId,
+    context: RequestContext,
+    event_sender: EventSender,
+}
+
+/// An attribute a request is sent to.
+struct Target {
+    uuid: Uuid,
+    authorize: bool,
+    security: Security,
+    long_value: Option<LongValue>,
+    event_sender: EventSender,
+    /// Where reads go, for splicing long writes.
+    read_sender: Option<EventSender>,
+}
+
+impl Target {
+    fn new(attribute: &Attribute, event_sender: EventSender) -> Self {
+        let (uuid, authorize, security, long_value, read) = match attribute {
+            Attribute::Characteristic(characteristic) => (
+                characteristic.uuid,
+                characteristic.authorize,
+                characteristic.security,
+                characteristic.long_value.clone(),
+                characteristic
+                    .properties
+                    .read
+                    .clone()
+                    .map(|read| read.sender()),
+            ),
+            Attribute::Descriptor(descriptor) => (
+                descriptor.uuid,
+                descriptor.authorize,
+                descriptor.security,
+                descriptor.long_value.clone(),
+                descriptor.properties.read.clone().map(|read| read.sender()),
+            ),
+        };
+        Target {
+            uuid,
+            authorize,
+            security,
+            long_value,
+            event_sender,
+            read_sender: read,
+        }
+    }
+}
+
+/// Values notified or indicated by the peripheral after a `VirtualCentral::subscribe`.
+#[derive(Debug)]
+pub struct Notifications {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    indications: mpsc::Receiver<Indication>,
+}
+
+impl Stream for Notifications {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let notification = self.receiver.poll_next_unpin(cx);
+        if let Poll::Ready(Some(value)) = notification {
+            return Poll::Ready(Some(value));
+        }
+        match self.indications.poll_next_unpin(cx) {
+            Poll::Ready(Some(indication)) => {
+                // The central confirms an indication as soon as it receives it.
+                indication.confirmed.send(()).ok();
+                Poll::Ready(Some(indication.value))
+            }
+            Poll::Ready(None) if notification.is_ready() => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+/// Handle ranges indicated by the peripheral after a `VirtualCentral::service_changes`.
+#[derive(Debug)]
+pub struct ServiceChanges {
+    receiver: mpsc::UnboundedReceiver<RangeInclusive<u16>>,
+}
+
+impl Stream for ServiceChanges {
+    type Item = RangeInclusive<u16>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_next_unpin(cx)
+    }
+}
+
+/// A write that passed `check_write`, for the application to answer.
+struct CheckedWrite {
+    target: Target,
+    context: RequestContext,
+    data: Vec<u8>,
+    offset: u16,
+    kind: WriteKind,
+}
+
+impl CheckedWrite {
+    async fn send(self) -> Result<Response, Error> {
+        let target = self.target;
+        if let Some(long_value) = target.long_value {
+            return long_value
+                .write(
+                    target.read_sender,
+                    target.event_sender,
+                    self.context,
+                    self.offset,
+                    self.data,
+                    self.kind,
+                )
+                .await
+                .map_err(|_| handler_gone());
+        }
+        let (sender, receiver) = oneshot::channel();
+        request(
+            target.event_sender,
+            Event::WriteRequest(WriteRequest {
+                context: self.context,
+                data: self.data,
+                offset: self.offset,
+                kind: self.kind,
+                response: sender,
+            }),
+            receiver,
+        )
+        .await
+    }
+}
+
+async fn request(
+    mut event_sender: EventSender,
+    event: Event,
+    receiver: oneshot::Receiver<Response>,
+) -> Result<Response, Error> {
+    event_sender.send(event).await.map_err(|_| handler_gone())?;
+    receiver.await.map_err(|_| handler_gone())
+}
+
+fn not_supported(operation: &str, handle: u16) -> Error {
+    Error::new(
+        "NotSupported",
+        format!("attribute {:#06x} does not support {}", handle, operation),
+        ErrorType::Sim,
+    )
+}
+
+fn handler_gone() -> Error {
+    Error::new(
+        "Failed",
+        "the event handler for the attribute has gone away",
+        ErrorType::Sim,
+    )
+}
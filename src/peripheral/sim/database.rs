@@ -0,0 +1,169 @@
+use std::ops::RangeInclusive;
+use uuid::Uuid;
+
+use crate::gatt::{
+    characteristic::{self, Characteristic},
+    descriptor::Descriptor,
+    hash::DatabaseHash,
+    service::Service,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteService {
+    pub handle: u16,
+    pub end_handle: u16,
+    pub uuid: Uuid,
+    pub primary: bool,
+    pub characteristics: Vec<RemoteCharacteristic>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteCharacteristic {
+    pub handle: u16,
+    pub uuid: Uuid,
+    pub properties: RemoteProperties,
+    pub descriptors: Vec<RemoteDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteDescriptor {
+    pub handle: u16,
+    pub uuid: Uuid,
+    pub properties: RemoteProperties,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteProperties {
+    pub read: bool,
+    pub write: bool,
+    pub write_without_response: bool,
+    pub notify: bool,
+    pub indicate: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Attribute {
+    Characteristic(Characteristic),
+    Descriptor(Descriptor),
+}
+
+#[derive(Debug, Default)]
+pub struct Database {
+    definitions: Vec<Service>,
+    services: Vec<RemoteService>,
+    attributes: Vec<(u16, Attribute)>,
+    next_handle: u16,
+}
+
+impl Database {
+    /// Adds a service and returns the handle range it occupies.
+    pub fn add_service(&mut self, service: &Service) -> RangeInclusive<u16> {
+        // Every service, characteristic declaration, characteristic value and descriptor
+        // occupies one handle, following the layout of a real attribute table.
+        let handle = self.allocate_handle();
+        let characteristics = service
+            .characteristics
+            .iter()
+            .map(|characteristic| {
+                let _declaration = self.allocate_handle();
+                let handle = self.allocate_handle();
+                self.attributes
+                    .push((handle, Attribute::Characteristic(characteristic.clone())));
+                let descriptors = characteristic
+                    .descriptors
+                    .iter()
+                    .map(|descriptor| {
+                        let handle = self.allocate_handle();
+                        self.attributes
+                            .push((handle, Attribute::Descriptor(descriptor.clone())));
+                        RemoteDescriptor {
+                            handle,
+                            uuid: descriptor.uuid,
+                            properties: RemoteProperties {
+                                read: descriptor.is_readable(),
+                                write: descriptor.properties.write.is_some(),
+                                ..Default::default()
+                            },
+                        }
+                    })
+                    .collect();
+                RemoteCharacteristic {
+                    handle,
+                    uuid: characteristic.uuid,
+                    properties: RemoteProperties {
+                        read: characteristic.is_readable(),
+                        ..RemoteProperties::from(&characteristic.properties)
+                    },
+                    descriptors,
+                }
+            })
+            .collect();
+
+        self.services.push(RemoteService {
+            handle,
+            end_handle: self.next_handle,
+            uuid: service.uuid,
+            primary: service.primary,
+            characteristics,
+        });
+        self.definitions.push(service.clone());
+        handle..=self.next_handle
+    }
+
+    /// Removes the services with the given UUID and returns the handle ranges they occupied;
+    /// their handles are not handed out again.
+    pub fn remove_service(&mut self, uuid: &Uuid) -> Vec<RangeInclusive<u16>> {
+        self.definitions.retain(|service| service.uuid != *uuid);
+        let (removed, services) = self
+            .services
+            .drain(..)
+            .partition::<Vec<_>, _>(|service| service.uuid == *uuid);
+        self.services = services;
+        self.attributes.retain(|(handle, _)| {
+            !removed
+                .iter()
+                .any(|service| (service.handle..=service.end_handle).contains(handle))
+        });
+        removed
+            .iter()
+            .map(|service| service.handle..=service.end_handle)
+            .collect()
+    }
+
+    pub fn hash(&self) -> DatabaseHash {
+        DatabaseHash::new(&self.definitions)
+    }
+
+    pub fn services(&self) -> Vec<RemoteService> {
+        self.services.clone()
+    }
+
+    pub fn attribute(&self, handle: u16) -> Option<Attribute> {
+        self.attributes
+            .iter()
+            .find(|(attribute_handle, _)| *attribute_handle == handle)
+            .map(|(_, attribute)| attribute.clone())
+    }
+
+    fn allocate_handle(&mut self) -> u16 {
+        self.next_handle += 1;
+        self.next_handle
+    }
+}
+
+impl From<&characteristic::Properties> for RemoteProperties {
+    fn from(properties: &characteristic::Properties) -> Self {
+        let (write, write_without_response) = match properties.write {
+            Some(characteristic::Write::WithResponse(_)) => (true, false),
+            Some(characteristic::Write::WithoutResponse(_)) => (false, true),
+            None => (false, false),
+        };
+        RemoteProperties {
+            read: properties.read.is_some(),
+            write,
+            write_without_response,
+            notify: properties.notify.is_some(),
+            indicate: properties.indicate.is_some(),
+        }
+    }
+}
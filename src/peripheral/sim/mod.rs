@@ -0,0 +1,231 @@
+mod central;
+mod database;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use std::{
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+};
+use uuid::Uuid;
+
+use self::database::Database;
+pub use self::{
+    central::{Notifications, ServiceChanges, VirtualCentral},
+    database::{RemoteCharacteristic, RemoteDescriptor, RemoteProperties, RemoteService},
+};
+use super::event::{Event, EventBroadcaster, EventStream, State};
+use crate::{
+    gatt::{
+        hash::DatabaseHash,
+        service::Service,
+        subscribers::{Subscriber, Subscribers},
+    },
+    Error, ErrorType,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advertisement {
+    pub name: String,
+    pub uuids: Vec<Uuid>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    powered: AtomicBool,
+    registered: AtomicBool,
+    advertisement: Mutex<Option<Advertisement>>,
+    database: Mutex<Database>,
+    events: EventBroadcaster,
+    service_changed: Mutex<Vec<mpsc::UnboundedSender<RangeInclusive<u16>>>>,
+    centrals: AtomicU16,
+    subscribers: Subscribers,
+}
+
+impl Inner {
+    /// Indicates Service Changed to every central that asked for it, once the database is
+    /// visible to them.
+    fn service_changed(&self, range: RangeInclusive<u16>) {
+        if self.registered.load(Ordering::Relaxed) {
+            self.service_changed
+                .lock()
+                .unwrap()
+                .retain(|sender| sender.unbounded_send(range.clone()).is_ok());
+        }
+    }
+}
+
+/// An in-memory peripheral that keeps the GATT tree to itself instead of talking to a radio.
+///
+/// Requests from a `VirtualCentral` are delivered to the characteristic and descriptor event
+/// senders exactly like the platform backends deliver them.
+#[derive(Debug)]
+pub struct Peripheral {
+    inner: Arc<Inner>,
+}
+
+impl Peripheral {
+    #[allow(clippy::new_ret_no_self)]
+    pub async fn new() -> Result<Self, Error> {
+        Ok(Peripheral {
+            inner: Arc::new(Inner {
+                powered: AtomicBool::new(true),
+                registered: AtomicBool::new(false),
+                advertisement: Mutex::new(None),
+                database: Mutex::new(Database::default()),
+                events: EventBroadcaster::default(),
+                service_changed: Mutex::new(Vec::new()),
+                centrals: AtomicU16::new(0),
+                subscribers: Subscribers::default(),
+            }),
+        })
+    }
+
+    /// Connects a new virtual central to the peripheral.
+    pub fn central(&self) -> VirtualCentral {
+        let index = self.inner.centrals.fetch_add(1, Ordering::Relaxed) + 1;
+        VirtualCentral::new(Arc::clone(&self.inner), index)
+    }
+
+    /// Simulates the radio being switched on or off.
+    pub fn set_powered(&self, powered: bool) {
+        if self.inner.powered.swap(powered, Ordering::Relaxed) != powered {
+            let state = if powered {
+                State::PoweredOn
+            } else {
+                State::PoweredOff
+            };
+            self.inner.events.broadcast(Event::StateChange(state));
+        }
+    }
+
+    pub async fn is_powered(&self) -> Result<bool, Error> {
+        Ok(self.inner.powered.load(Ordering::Relaxed))
+    }
+
+    pub async fn register_gatt(&self) -> Result<(), Error> {
+        self.inner.registered.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub async fn unregister_gatt(&self) -> Result<(), Error> {
+        self.inner.registered.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub async fn start_advertising(&self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
+        if !self.inner.powered.load(Ordering::Relaxed) {
+            return Err(Error::new(
+                "NotReady",
+                "cannot advertise while powered off",
+                ErrorType::Sim,
+            ));
+        }
+        self.inner
+            .advertisement
+            .lock()
+            .unwrap()
+            .replace(Advertisement {
+                name: name.to_string(),
+                uuids: uuids.to_vec(),
+            });
+        Ok(())
+    }
+
+    pub async fn stop_advertising(&self) -> Result<(), Error> {
+        self.inner.advertisement.lock().unwrap().take();
+        Ok(())
+    }
+
+    pub async fn is_advertising(&self) -> Result<bool, Error> {
+        Ok(self.inner.advertisement.lock().unwrap().is_some())
+    }
+
+    pub fn add_service(&self, service: &Service) -> Result<(), Error> {
+        let range = self.inner.database.lock().unwrap().add_service(service);
+        self.inner.service_changed(range);
+        service.start();
+        Ok(())
+    }
+
+    pub fn remove_service(&self, uuid: &Uuid) -> Result<(), Error> {
+        let ranges = self.inner.database.lock().unwrap().remove_service(uuid);
+        if ranges.is_empty() {
+            return Err(Error::new(
+                "ServiceNotFound",
+                format!("no service with UUID {}", uuid),
+                ErrorType::Sim,
+            ));
+        }
+        for range in ranges {
+            self.inner.service_changed(range);
+        }
+        Ok(())
+    }
+
+    pub fn database_hash(&self) -> DatabaseHash {
+        self.inner.database.lock().unwrap().hash()
+    }
+
+    pub fn events(&self) -> EventStream {
+        self.inner.events.subscribe()
+    }
+
+    pub fn subscribers(&self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.inner.subscribers.of(characteristic)
+    }
+}
+
+#[async_trait]
+impl super::Peripheral for Peripheral {
+    async fn new() -> Result<Self, Error> {
+        Peripheral::new().await
+    }
+
+    async fn is_powered(self: &Self) -> Result<bool, Error> {
+        self.is_powered().await
+    }
+
+    async fn register_gatt(self: &Self) -> Result<(), Error> {
+        self.register_gatt().await
+    }
+
+    async fn unregister_gatt(self: &Self) -> Result<(), Error> {
+        self.unregister_gatt().await
+    }
+
+    async fn start_advertising(self: &Self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
+        self.start_advertising(name, uuids).await
+    }
+
+    async fn stop_advertising(self: &Self) -> Result<(), Error> {
+        self.stop_advertising().await
+    }
+
+    async fn is_advertising(self: &Self) -> Result<bool, Error> {
+        self.is_advertising().await
+    }
+
+    fn add_service(self: &Self, service: &Service) -> Result<(), Error> {
+        self.add_service(service)
+    }
+
+    fn remove_service(self: &Self, uuid: &Uuid) -> Result<(), Error> {
+        self.remove_service(uuid)
+    }
+
+    fn database_hash(self: &Self) -> DatabaseHash {
+        self.database_hash()
+    }
+
+    fn events(self: &Self) -> EventStream {
+        self.events()
+    }
+
+    fn subscribers(self: &Self, characteristic: &Uuid) -> Vec<Subscriber> {
+        self.subscribers(characteristic)
+    }
+}
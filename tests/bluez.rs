@@ -0,0 +1,1243 @@
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+mod common;
+mod fake_bluez;
+
+use async_trait::async_trait;
+use futures::{channel::mpsc::channel, prelude::*};
+use std::{
+    io::{self, Read, Write},
+    sync::Mutex,
+    time::Duration,
+};
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic,
+        characteristic::Characteristic,
+        descriptor,
+        descriptor::Descriptor,
+        event::{
+            Access, Authorization, Event, IndicationError, Link, NotifyMode, RequestContext,
+            Response, WriteKind,
+        },
+        handle::CharacteristicHandle,
+        handler::{AttError, CharacteristicHandler},
+        notifier::{Delivery, Notifier, NotifyError, NotifyPolicy},
+        security::SecurityLevel,
+        service::Service,
+    },
+    peripheral::{
+        bluez::{
+            AdapterCapabilities, AdapterSelector, Bus, Peripheral, PeripheralOptions, PowerPolicy,
+        },
+        event::{Event as PeripheralEvent, State},
+        sim,
+    },
+    SdpShortUuid,
+};
+
+use self::common::{handle_events, service, CHARACTERISTIC_UUID, DESCRIPTOR_UUID, SERVICE_UUID};
+use self::fake_bluez::{
+    AdapterState, FakeBluez, Notification, RequestOptions, ADAPTER_PATH, DEVICE_PATH,
+    GATT_CHARACTERISTIC_IFACE,
+};
+
+fn offset(offset: u16) -> RequestOptions {
+    RequestOptions {
+        offset,
+        ..Default::default()
+    }
+}
+
+async fn peripheral(fake: &FakeBluez) -> Peripheral {
+    Peripheral::with_bus(Bus::Address(fake.address().to_string()))
+        .await
+        .unwrap()
+}
+
+fn options_for(fake: &FakeBluez) -> PeripheralOptions {
+    PeripheralOptions::default().bus(Bus::Address(fake.address().to_string()))
+}
+
+#[tokio::test]
+async fn it_powers_on_the_adapter() {
+    let fake = FakeBluez::start();
+    assert!(!fake.adapter().powered);
+
+    let peripheral = peripheral(&fake).await;
+    assert!(fake.adapter().powered);
+    assert!(peripheral.is_powered().await.unwrap());
+
+    peripheral.set_alias("bluster").await.unwrap();
+    assert_eq!(peripheral.get_alias().await.unwrap(), "bluster");
+    assert_eq!(fake.adapter().alias, "bluster");
+    assert_eq!(peripheral.get_name().await.unwrap(), fake.adapter().name);
+}
+
+#[tokio::test]
+async fn it_registers_advertisements() {
+    let fake = FakeBluez::start();
+    let peripheral = peripheral(&fake).await;
+    let uuid = Uuid::from_sdp_short_uuid(SERVICE_UUID);
+
+    peripheral
+        .start_advertising("hello", &[uuid])
+        .await
+        .unwrap();
+    assert!(peripheral.is_advertising().await.unwrap());
+    let advertisement = fake.advertisement().unwrap();
+    assert_eq!(advertisement.kind, "peripheral");
+    assert_eq!(advertisement.local_name, Some("hello".to_string()));
+    assert_eq!(advertisement.service_uuids, vec![uuid.to_string()]);
+
+    peripheral.stop_advertising().await.unwrap();
+    assert!(!peripheral.is_advertising().await.unwrap());
+    assert_eq!(fake.advertisement(), None);
+}
+
+#[tokio::test]
+async fn it_registers_the_gatt_application() {
+    let fake = FakeBluez::start();
+    let (service, _, _) = service();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let application = fake.application().unwrap();
+    let gatt_service = application
+        .service(&Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .unwrap();
+    assert_eq!(gatt_service.primary, Some(true));
+
+    let gatt_characteristic = application
+        .characteristic(&Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID))
+        .unwrap();
+    assert_eq!(gatt_characteristic.parent, Some(gatt_service.path.clone()));
+    assert_eq!(gatt_characteristic.flags, vec!["read", "write", "notify"]);
+
+    let gatt_descriptor = application
+        .descriptor(&Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID))
+        .unwrap();
+    assert_eq!(
+        gatt_descriptor.parent,
+        Some(gatt_characteristic.path.clone())
+    );
+    assert_eq!(gatt_descriptor.flags, vec!["read"]);
+
+    peripheral.unregister_gatt().await.unwrap();
+    assert_eq!(fake.application(), None);
+}
+
+#[tokio::test]
+async fn it_serves_read_and_write_requests() {
+    let fake = FakeBluez::start();
+    let (service, receiver_characteristic, receiver_descriptor) = service();
+    let value = handle_events(receiver_characteristic, "hello");
+    handle_events(receiver_descriptor, "description");
+
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let application = fake.application().unwrap();
+    let characteristic = &application
+        .characteristic(&Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID))
+        .unwrap()
+        .path;
+    let descriptor = &application
+        .descriptor(&Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID))
+        .unwrap()
+        .path;
+
+    assert_eq!(
+        fake.read_value(characteristic, offset(0)).await,
+        Ok(b"hello".to_vec())
+    );
+    assert_eq!(
+        fake.read_value(characteristic, offset(3)).await,
+        Ok(b"lo".to_vec())
+    );
+    assert_eq!(
+        fake.read_value(descriptor, offset(0)).await,
+        Ok(b"description".to_vec())
+    );
+
+    fake.write_value(characteristic, b"world", offset(0))
+        .await
+        .unwrap();
+    assert_eq!(*value.lock().unwrap(), b"world".to_vec());
+    assert_eq!(
+        fake.write_value(descriptor, b"nope", offset(0)).await,
+        Err("org.bluez.Error.NotSupported".to_string())
+    );
+}
+
+#[tokio::test]
+async fn it_notifies_through_properties_changed() {
+    let fake = FakeBluez::start();
+    let (service, receiver_characteristic, _) = service();
+    handle_events(receiver_characteristic, "hello");
+
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let application = fake.application().unwrap();
+    let characteristic = application
+        .characteristic(&Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID))
+        .unwrap();
+    assert_eq!(characteristic.interface, GATT_CHARACTERISTIC_IFACE);
+    let path = &characteristic.path;
+
+    let mut notifications = fake.notifications();
+    fake.start_notify(path).await.unwrap();
+    fake.write_value(path, b"one", offset(0)).await.unwrap();
+    assert_eq!(
+        notifications.next().await,
+        Some(Notification {
+            path: path.clone(),
+            value: b"one".to_vec(),
+        })
+    );
+
+    fake.stop_notify(path).await.unwrap();
+}
+
+#[tokio::test]
+async fn it_waits_for_indications_to_be_confirmed() {
+    let fake = FakeBluez::start();
+    let (sender, mut receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| c.sender(sender).indicate())
+        .build()
+        .unwrap();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let application = fake.application().unwrap();
+    let path = &application.characteristic(&uuid).unwrap().path;
+
+    let mut notifications = fake.notifications();
+    fake.start_notify(path).await.unwrap();
+    let subscription = match receiver.next().await {
+        Some(Event::NotifySubscribe(notify_subscribe)) => notify_subscribe,
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(subscription.mode, NotifyMode::Indicate);
+
+    let indication = {
+        let subscription = subscription.clone();
+        tokio::spawn(async move { subscription.indicate(b"one".to_vec()).await })
+    };
+    assert_eq!(notifications.next().await.unwrap().value, b"one".to_vec());
+    fake.confirm(path).await.unwrap();
+    assert_eq!(indication.await.unwrap(), Ok(()));
+
+    // Plain notifications are indicated too, so the next confirmation is theirs.
+    let mut notification = subscription.notification.clone();
+    notification.send(b"plain".to_vec()).await.unwrap();
+    assert_eq!(notifications.next().await.unwrap().value, b"plain".to_vec());
+    let mut indication = {
+        let subscription = subscription.clone();
+        tokio::spawn(async move { subscription.indicate(b"three".to_vec()).await })
+    };
+    fake.confirm(path).await.unwrap();
+    assert_eq!(notifications.next().await.unwrap().value, b"three".to_vec());
+    assert!((&mut indication).now_or_never().is_none());
+    fake.confirm(path).await.unwrap();
+    assert_eq!(indication.await.unwrap(), Ok(()));
+
+    let indication = tokio::spawn(async move { subscription.indicate(b"two".to_vec()).await });
+    assert_eq!(notifications.next().await.unwrap().value, b"two".to_vec());
+    fake.stop_notify(path).await.unwrap();
+    assert_eq!(
+        indication.await.unwrap(),
+        Err(IndicationError::Disconnected)
+    );
+}
+
+#[tokio::test]
+async fn it_reports_notifications_after_unsubscribing() {
+    let fake = FakeBluez::start();
+    let (sender, mut receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| c.sender(sender).notify())
+        .build()
+        .unwrap();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let application = fake.application().unwrap();
+    let path = &application.characteristic(&uuid).unwrap().path;
+
+    let mut notifications = fake.notifications();
+    fake.start_notify(path).await.unwrap();
+    let mut notifier = match receiver.next().await {
+        Some(Event::NotifySubscribe(notify_subscribe)) => {
+            Notifier::new(notify_subscribe, NotifyPolicy::Queue)
+        }
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(notifier.notify(b"one".to_vec()).await, Ok(Delivery::Queued));
+    assert_eq!(notifications.next().await.unwrap().value, b"one".to_vec());
+
+    let subscribers = peripheral.subscribers(&uuid);
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0].context.device, None);
+
+    fake.stop_notify(path).await.unwrap();
+    match receiver.next().await {
+        Some(Event::NotifyUnsubscribe(notify_unsubscribe)) => {
+            assert_eq!(notify_unsubscribe.id, subscribers[0].id)
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    assert!(peripheral.subscribers(&uuid).is_empty());
+    // Let the forwarding task see the subscription end.
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    assert_eq!(
+        notifier.notify(b"two".to_vec()).await,
+        Err(NotifyError::Unsubscribed)
+    );
+}
+
+#[tokio::test]
+async fn it_ends_a_subscription_that_is_replaced() {
+    let fake = FakeBluez::start();
+    let (sender, mut receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| c.sender(sender).notify())
+        .build()
+        .unwrap();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let application = fake.application().unwrap();
+    let path = &application.characteristic(&uuid).unwrap().path;
+
+    let (started, event) = futures::join!(fake.start_notify(path), receiver.next());
+    started.unwrap();
+    let first = match event {
+        Some(Event::NotifySubscribe(notify_subscribe)) => notify_subscribe.id,
+        event => panic!("unexpected event {:?}", event),
+    };
+
+    let events = async { (receiver.next().await, receiver.next().await) };
+    let (started, events) = futures::join!(fake.start_notify(path), events);
+    started.unwrap();
+    let second = match events {
+        (
+            Some(Event::NotifyUnsubscribe(notify_unsubscribe)),
+            Some(Event::NotifySubscribe(notify_subscribe)),
+        ) if notify_unsubscribe.id == first => notify_subscribe.id,
+        events => panic!("unexpected events {:?}", events),
+    };
+    assert_ne!(first, second);
+    let subscribers = peripheral.subscribers(&uuid);
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0].id, second);
+}
+
+#[tokio::test]
+async fn it_follows_the_power_policy() {
+    let fake = FakeBluez::start();
+
+    let options = options_for(&fake).power_policy(PowerPolicy::RequirePowered);
+    assert!(Peripheral::with_options(options).await.is_err());
+
+    let options = options_for(&fake).power_policy(PowerPolicy::Unchanged);
+    let peripheral = Peripheral::with_options(options).await.unwrap();
+    assert!(!peripheral.is_powered().await.unwrap());
+    assert!(!fake.adapter().powered);
+}
+
+#[tokio::test]
+async fn it_selects_adapters() {
+    let fake = FakeBluez::start();
+
+    let address = fake.adapter().address.to_lowercase();
+    let options = options_for(&fake).adapter(AdapterSelector::Address(address));
+    assert!(Peripheral::with_options(options).await.is_ok());
+
+    let options = options_for(&fake).adapter(AdapterSelector::Name(fake.adapter().name));
+    assert!(Peripheral::with_options(options).await.is_ok());
+
+    let options = options_for(&fake).adapter(AdapterSelector::Path("/org/bluez/hci1".to_string()));
+    assert!(Peripheral::with_options(options).await.is_err());
+
+    fake.add_adapter(AdapterState {
+        path: "/org/bluez/hci1".to_string(),
+        address: "00:00:5E:00:53:01".to_string(),
+        ..Default::default()
+    });
+    let options = options_for(&fake).adapter(AdapterSelector::Path("/org/bluez/hci1".to_string()));
+    let peripheral = Peripheral::with_options(options).await.unwrap();
+    peripheral.register_gatt().await.unwrap();
+    assert_eq!(fake.application().unwrap().adapter, "/org/bluez/hci1");
+    assert!(fake.adapter_at("/org/bluez/hci1").unwrap().powered);
+}
+
+#[tokio::test]
+async fn it_lists_adapters() {
+    let fake = FakeBluez::start();
+    fake.add_adapter(AdapterState {
+        path: "/org/bluez/hci1".to_string(),
+        address: "00:00:5E:00:53:01".to_string(),
+        name: "classic".to_string(),
+        alias: "classic".to_string(),
+        powered: true,
+        le: false,
+        ..Default::default()
+    });
+
+    let adapters = Peripheral::adapters(Bus::Address(fake.address().to_string()))
+        .await
+        .unwrap();
+    assert_eq!(adapters.len(), 2);
+    assert_eq!(adapters[0].path, ADAPTER_PATH);
+    assert_eq!(adapters[0].address, fake.adapter().address);
+    assert_eq!(adapters[0].name, "fake-bluez");
+    assert!(!adapters[0].powered);
+    assert_eq!(
+        adapters[0].capabilities,
+        AdapterCapabilities {
+            le_advertising: true,
+            gatt_server: true,
+        }
+    );
+    assert_eq!(adapters[1].path, "/org/bluez/hci1");
+    assert_eq!(adapters[1].alias, "classic");
+    assert!(adapters[1].powered);
+    assert_eq!(adapters[1].capabilities, AdapterCapabilities::default());
+}
+
+#[tokio::test]
+async fn it_fails_without_an_le_adapter() {
+    let fake = FakeBluez::start();
+    fake.remove_adapter(ADAPTER_PATH);
+    assert!(
+        Peripheral::with_bus(Bus::Address(fake.address().to_string()))
+            .await
+            .is_err()
+    );
+
+    fake.add_adapter(AdapterState {
+        le: false,
+        ..Default::default()
+    });
+    assert!(
+        Peripheral::with_bus(Bus::Address(fake.address().to_string()))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn it_exports_objects_under_the_path_prefix() {
+    let fake = FakeBluez::start();
+    let (service, _, _) = service();
+
+    let options = options_for(&fake).path_prefix("/com/example/gateway");
+    let peripheral = Peripheral::with_options(options).await.unwrap();
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    peripheral.start_advertising("hello", &[]).await.unwrap();
+
+    let application = fake.application().unwrap();
+    assert_eq!(application.path, "/com/example/gateway");
+    assert!(application
+        .objects
+        .iter()
+        .all(|object| object.path.starts_with("/com/example/gateway/")));
+    assert!(fake
+        .advertisement()
+        .unwrap()
+        .path
+        .starts_with("/com/example/gateway/"));
+
+    let options = options_for(&fake).path_prefix("not a path");
+    assert!(Peripheral::with_options(options).await.is_err());
+}
+
+#[tokio::test]
+async fn it_reports_adapter_state_changes() {
+    let fake = FakeBluez::start();
+    let peripheral = peripheral(&fake).await;
+    let mut events = peripheral.events();
+
+    fake.set_powered(ADAPTER_PATH, false);
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::PoweredOff))
+    );
+    assert!(!peripheral.is_powered().await.unwrap());
+
+    fake.set_discoverable(ADAPTER_PATH, true);
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::DiscoverableChange(true))
+    );
+
+    fake.add_adapter(AdapterState {
+        path: "/org/bluez/hci1".to_string(),
+        ..Default::default()
+    });
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::AdapterAdded("/org/bluez/hci1".to_string()))
+    );
+
+    // Only the adapter in use reports its power state.
+    fake.set_powered("/org/bluez/hci1", true);
+    fake.remove_adapter(ADAPTER_PATH);
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::AdapterRemoved(ADAPTER_PATH.to_string()))
+    );
+}
+
+#[tokio::test]
+async fn it_recovers_when_bluez_restarts() {
+    let fake = FakeBluez::start();
+    let (service, _, _) = service();
+
+    let options = options_for(&fake).supervised(true);
+    let peripheral = Peripheral::with_options(options).await.unwrap();
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    peripheral.start_advertising("hello", &[]).await.unwrap();
+    let mut events = peripheral.events();
+
+    fake.restart();
+    assert_eq!(fake.application(), None);
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::Resetting))
+    );
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::PoweredOn))
+    );
+    assert!(fake
+        .application()
+        .unwrap()
+        .characteristic(&Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID))
+        .is_some());
+    assert_eq!(
+        fake.advertisement().unwrap().local_name,
+        Some("hello".to_string())
+    );
+}
+
+#[tokio::test]
+async fn it_reconnects_when_the_bus_restarts() {
+    let mut fake = FakeBluez::start();
+    let (service, receiver_characteristic, _) = service();
+    handle_events(receiver_characteristic, "hello");
+
+    let options = options_for(&fake)
+        .supervised(true)
+        .reconnect_interval(Duration::from_millis(10));
+    let peripheral = Peripheral::with_options(options).await.unwrap();
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let mut events = peripheral.events();
+
+    fake.restart_bus();
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::Resetting))
+    );
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::PoweredOn))
+    );
+
+    let characteristic = fake
+        .application()
+        .unwrap()
+        .characteristic(&Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID))
+        .unwrap()
+        .path
+        .clone();
+    assert_eq!(
+        fake.read_value(&characteristic, offset(0)).await,
+        Ok(b"hello".to_vec())
+    );
+    assert!(peripheral.is_powered().await.unwrap());
+}
+
+#[tokio::test]
+async fn it_adds_and_removes_services_after_registering() {
+    let fake = FakeBluez::start();
+    let (service, _, _) = service();
+    let battery_uuid = Uuid::from_sdp_short_uuid(0x180Fu16);
+    let battery = Service::new(battery_uuid, true, vec![]);
+
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    // Signals reach the fake before the reply to any later method call does.
+    peripheral.add_service(&battery).unwrap();
+    peripheral.is_powered().await.unwrap();
+    assert!(fake.application().unwrap().service(&battery_uuid).is_some());
+
+    let service_uuid = Uuid::from_sdp_short_uuid(SERVICE_UUID);
+    peripheral.remove_service(&service_uuid).unwrap();
+    peripheral.is_powered().await.unwrap();
+    let application = fake.application().unwrap();
+    assert!(application.service(&service_uuid).is_none());
+    assert!(application
+        .characteristic(&Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID))
+        .is_none());
+    assert!(application
+        .descriptor(&Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID))
+        .is_none());
+    assert!(peripheral.remove_service(&service_uuid).is_err());
+
+    for _ in 0..3 {
+        peripheral.unregister_gatt().await.unwrap();
+        assert_eq!(fake.application(), None);
+        peripheral.register_gatt().await.unwrap();
+        assert!(fake.application().unwrap().service(&battery_uuid).is_some());
+    }
+    peripheral.unregister_gatt().await.unwrap();
+    assert!(peripheral.unregister_gatt().await.is_err());
+}
+
+#[tokio::test]
+async fn it_keeps_the_database_hash_across_restarts() {
+    let fake = FakeBluez::start();
+    let (same_service, _, _) = service();
+    let (service, _, _) = service();
+
+    let options = options_for(&fake).supervised(true);
+    let peripheral = Peripheral::with_options(options).await.unwrap();
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let hash = peripheral.database_hash();
+    let mut events = peripheral.events();
+
+    fake.restart();
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::Resetting))
+    );
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::PoweredOn))
+    );
+    assert_eq!(peripheral.database_hash(), hash);
+
+    // The hash only depends on the tree, not on the backend serving it.
+    let simulated = sim::Peripheral::new().await.unwrap();
+    simulated.add_service(&same_service).unwrap();
+    assert_eq!(simulated.database_hash(), hash);
+}
+
+#[tokio::test]
+async fn it_exports_attributes_with_the_same_uuid_in_order() {
+    let fake = FakeBluez::start();
+    let (sender, _receiver) = channel(1);
+    let report_uuid = Uuid::from_sdp_short_uuid(0x2A4Du16);
+    let report = |properties| {
+        Characteristic::new(
+            report_uuid,
+            properties,
+            None,
+            vec![Descriptor::new(
+                Uuid::from_sdp_short_uuid(0x2908u16),
+                descriptor::Properties::new(None, None),
+                None,
+            )],
+        )
+    };
+    let input = characteristic::Properties::new(None, None, Some(sender.clone()), None);
+    let output = characteristic::Properties::new(
+        None,
+        Some(characteristic::Write::WithResponse(
+            characteristic::Secure::Insecure(sender),
+        )),
+        None,
+        None,
+    );
+    let hid = Service::new(
+        Uuid::from_sdp_short_uuid(0x1812u16),
+        true,
+        vec![report(input), report(output)],
+    );
+
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&hid).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let application = fake.application().unwrap();
+    let mut reports: Vec<_> = application
+        .objects
+        .iter()
+        .filter(|object| {
+            object.interface == GATT_CHARACTERISTIC_IFACE && object.uuid == report_uuid.to_string()
+        })
+        .collect();
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].flags, vec!["notify".to_string()]);
+    assert_eq!(reports[1].flags, vec!["write".to_string()]);
+    assert_eq!(
+        application
+            .objects
+            .iter()
+            .filter(|object| object.parent.as_ref() == Some(&reports[1].path))
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn it_serves_fixed_values() {
+    let fake = FakeBluez::start();
+    let revision_uuid = Uuid::from_sdp_short_uuid(0x2A26u16);
+    let format_uuid = Uuid::from_sdp_short_uuid(0x2904u16);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(0x180Au16))
+        .primary()
+        .characteristic(revision_uuid, |c| {
+            c.value("1.0.2")
+                .descriptor(format_uuid, |d| d.value(vec![0x19, 0, 0, 0, 1, 0, 0]))
+        })
+        .build()
+        .unwrap();
+
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let application = fake.application().unwrap();
+    let revision = application.characteristic(&revision_uuid).unwrap();
+    assert_eq!(revision.flags, vec!["read".to_string()]);
+    assert_eq!(
+        fake.read_value(&revision.path, offset(0)).await,
+        Ok(b"1.0.2".to_vec())
+    );
+    assert_eq!(
+        fake.read_value(&revision.path, offset(2)).await,
+        Ok(b"0.2".to_vec())
+    );
+    assert_eq!(
+        fake.read_value(&revision.path, offset(6)).await,
+        Err("org.bluez.Error.InvalidOffset".to_string())
+    );
+    assert!(fake
+        .write_value(&revision.path, b"2.0", offset(0))
+        .await
+        .is_err());
+
+    let format = application.descriptor(&format_uuid).unwrap();
+    assert_eq!(
+        fake.read_value(&format.path, offset(4)).await,
+        Ok(vec![1, 0, 0])
+    );
+}
+
+#[tokio::test]
+async fn it_notifies_managed_values() {
+    let fake = FakeBluez::start();
+    let level_uuid = Uuid::from_sdp_short_uuid(0x2A19u16);
+    let level = CharacteristicHandle::new(vec![100]);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(0x180Fu16))
+        .primary()
+        .characteristic(level_uuid, |c| c.managed(&level).read().write().notify())
+        .build()
+        .unwrap();
+
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let path = fake
+        .application()
+        .unwrap()
+        .characteristic(&level_uuid)
+        .unwrap()
+        .path
+        .clone();
+    assert_eq!(fake.read_value(&path, offset(0)).await, Ok(vec![100]));
+    fake.write_value(&path, &[90], offset(0)).await.unwrap();
+    assert_eq!(level.get_value(), vec![90]);
+
+    let mut notifications = fake.notifications();
+    fake.start_notify(&path).await.unwrap();
+    fake.read_value(&path, offset(0)).await.unwrap();
+    level.set_value(vec![80]).await;
+    assert_eq!(
+        notifications.next().await,
+        Some(Notification {
+            path: path.clone(),
+            value: vec![80],
+        })
+    );
+    assert_eq!(fake.read_value(&path, offset(0)).await, Ok(vec![80]));
+}
+
+/// Answers every read with the error at the read's offset.
+struct Refuse(Vec<AttError>);
+
+#[async_trait]
+impl CharacteristicHandler for Refuse {
+    async fn on_read(&self, _ctx: &RequestContext, offset: u16) -> Result<Vec<u8>, AttError> {
+        Err(self.0[usize::from(offset)].clone())
+    }
+}
+
+#[tokio::test]
+async fn it_maps_att_errors_to_bluez_errors() {
+    let fake = FakeBluez::start();
+    let errors = [
+        (AttError::ReadNotPermitted, "NotPermitted"),
+        (AttError::InsufficientEncryption, "NotPermitted"),
+        (AttError::InsufficientAuthorization, "NotAuthorized"),
+        (AttError::InvalidOffset, "InvalidOffset"),
+        (AttError::InvalidAttributeLength, "InvalidValueLength"),
+        (AttError::ValueNotAllowed, "ValueNotAllowed"),
+        (AttError::Application(0x80), "Failed"),
+        (AttError::Application(0x9F), "Failed"),
+        (AttError::Application(0xA0), "UnlikelyError"),
+        (AttError::Unlikely, "UnlikelyError"),
+    ];
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let refuse = Refuse(errors.iter().map(|(error, _)| error.clone()).collect());
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| c.handler(refuse).read())
+        .build()
+        .unwrap();
+
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let path = fake
+        .application()
+        .unwrap()
+        .characteristic(&uuid)
+        .unwrap()
+        .path
+        .clone();
+    for (index, (_, name)) in errors.iter().enumerate() {
+        assert_eq!(
+            fake.read_value(&path, offset(index as u16)).await,
+            Err(format!("org.bluez.Error.{}", name))
+        );
+    }
+}
+
+#[tokio::test]
+async fn it_passes_the_request_context() {
+    let fake = FakeBluez::start();
+    let (service, mut receiver_characteristic, _receiver_descriptor) = service();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let expected = RequestContext {
+        uuid,
+        device: Some(DEVICE_PATH.to_string()),
+        mtu: Some(185),
+        link: Some(Link::Le),
+    };
+    let path = fake
+        .application()
+        .unwrap()
+        .characteristic(&uuid)
+        .unwrap()
+        .path
+        .clone();
+    let requests = async {
+        match receiver_characteristic.next().await {
+            Some(Event::ReadRequest(read_request)) => {
+                assert_eq!(read_request.context, expected);
+                read_request
+                    .response
+                    .send(Response::Success(vec![]))
+                    .unwrap();
+            }
+            event => panic!("expected a read request, got {:?}", event),
+        }
+        match receiver_characteristic.next().await {
+            Some(Event::WriteRequest(write_request)) => {
+                assert_eq!(
+                    write_request.context,
+                    RequestContext {
+                        mtu: Some(517),
+                        ..expected.clone()
+                    }
+                );
+                write_request
+                    .response
+                    .send(Response::Success(vec![]))
+                    .unwrap();
+            }
+            event => panic!("expected a write request, got {:?}", event),
+        }
+    };
+    let central = async {
+        fake.read_value(&path, offset(0)).await.unwrap();
+        let options = RequestOptions {
+            mtu: 517,
+            ..Default::default()
+        };
+        fake.write_value(&path, b"hi", options).await.unwrap();
+    };
+    futures::join!(requests, central);
+}
+
+#[tokio::test]
+async fn it_reports_the_write_kind() {
+    let fake = FakeBluez::start();
+    let (service, mut receiver_characteristic, _receiver_descriptor) = service();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let path = fake
+        .application()
+        .unwrap()
+        .characteristic(&Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID))
+        .unwrap()
+        .path
+        .clone();
+    let requests = async {
+        let mut kinds = vec![];
+        // The command is never answered.
+        let mut unanswered = vec![];
+        while let Some(Event::WriteRequest(write_request)) = receiver_characteristic.next().await {
+            kinds.push(write_request.kind);
+            match write_request.kind {
+                WriteKind::Command => unanswered.push(write_request.response),
+                WriteKind::Request => write_request
+                    .response
+                    .send(Response::Success(vec![]))
+                    .unwrap(),
+                WriteKind::Reliable => {
+                    write_request
+                        .response
+                        .send(Response::ValueNotAllowed)
+                        .unwrap();
+                    break;
+                }
+            }
+        }
+        kinds
+    };
+    let central = async {
+        let write_type = |write_type: &str| RequestOptions {
+            write_type: write_type.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            fake.write_value(&path, b"a", write_type("command")).await,
+            Ok(())
+        );
+        assert_eq!(
+            fake.write_value(&path, b"b", write_type("request")).await,
+            Ok(())
+        );
+        assert_eq!(
+            fake.write_value(&path, b"c", write_type("reliable")).await,
+            Err("org.bluez.Error.ValueNotAllowed".to_string())
+        );
+    };
+    let (kinds, ()) = futures::join!(requests, central);
+    assert_eq!(
+        kinds,
+        vec![WriteKind::Command, WriteKind::Request, WriteKind::Reliable]
+    );
+}
+
+#[tokio::test]
+async fn it_authorizes_requests() {
+    let fake = FakeBluez::start();
+    let (sender, mut receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| c.sender(sender).read().write().authorize())
+        .build()
+        .unwrap();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let characteristic = fake
+        .application()
+        .unwrap()
+        .characteristic(&uuid)
+        .unwrap()
+        .clone();
+    assert_eq!(characteristic.flags, vec!["read", "write", "authorize"]);
+    let path = characteristic.path;
+
+    let requests = async {
+        let mut accesses = vec![];
+        while let Some(event) = receiver.next().await {
+            match event {
+                Event::AuthorizeRequest(authorize_request) => {
+                    assert_eq!(authorize_request.context.mtu, Some(185));
+                    accesses.push(authorize_request.access);
+                    let authorization = match authorize_request.access {
+                        Access::Read => Authorization::Deny,
+                        Access::Write(_) => Authorization::Allow,
+                    };
+                    authorize_request.response.send(authorization).unwrap();
+                }
+                Event::WriteRequest(write_request) => {
+                    assert_eq!(write_request.data, b"allowed".to_vec());
+                    write_request
+                        .response
+                        .send(Response::Success(vec![]))
+                        .unwrap();
+                    break;
+                }
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+        accesses
+    };
+    let central = async {
+        assert_eq!(
+            fake.read_value(&path, offset(0)).await,
+            Err("org.bluez.Error.NotAuthorized".to_string())
+        );
+        let prepare = RequestOptions {
+            prepare_authorize: true,
+            ..Default::default()
+        };
+        assert_eq!(fake.write_value(&path, b"queued", prepare).await, Ok(()));
+        assert_eq!(fake.write_value(&path, b"allowed", offset(0)).await, Ok(()));
+    };
+    let (accesses, ()) = futures::join!(requests, central);
+    assert_eq!(
+        accesses,
+        vec![
+            Access::Read,
+            Access::Write(WriteKind::Reliable),
+            Access::Write(WriteKind::Request)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn it_exports_security_levels_as_flags() {
+    let fake = FakeBluez::start();
+    let (sender, _receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let descriptor_uuid = Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| {
+            c.sender(sender)
+                .read_with(SecurityLevel::Encrypted)
+                .write_with(SecurityLevel::Authenticated)
+                .notify_with(SecurityLevel::SecureConnections)
+                .indicate()
+                .descriptor(descriptor_uuid, |d| d.secure_read().write())
+        })
+        .build()
+        .unwrap();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let application = fake.application().unwrap();
+    assert_eq!(
+        application.characteristic(&uuid).unwrap().flags,
+        vec![
+            "encrypt-read",
+            "encrypt-authenticated-write",
+            "secure-notify",
+            "encrypt-authenticated-notify",
+            "indicate"
+        ]
+    );
+    assert_eq!(
+        application.descriptor(&descriptor_uuid).unwrap().flags,
+        vec!["secure-read", "encrypt-authenticated-read", "write"]
+    );
+}
+
+#[tokio::test]
+async fn it_exports_secure_properties_as_it_always_has() {
+    let fake = FakeBluez::start();
+    let (sender, _receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let descriptor_uuid = Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID);
+    let service = Service::new(
+        Uuid::from_sdp_short_uuid(SERVICE_UUID),
+        true,
+        vec![Characteristic::new(
+            uuid,
+            characteristic::Properties::new(
+                Some(characteristic::Read(characteristic::Secure::Secure(
+                    sender.clone(),
+                ))),
+                Some(characteristic::Write::WithResponse(
+                    characteristic::Secure::Secure(sender.clone()),
+                )),
+                None,
+                None,
+            ),
+            None,
+            vec![Descriptor::new(
+                descriptor_uuid,
+                descriptor::Properties::new(
+                    Some(descriptor::Read(descriptor::Secure::Secure(sender.clone()))),
+                    Some(descriptor::Write(descriptor::Secure::Secure(sender))),
+                ),
+                None,
+            )],
+        )],
+    );
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+
+    let secure = vec![
+        "secure-read",
+        "encrypt-authenticated-read",
+        "secure-write",
+        "encrypt-authenticated-write",
+    ];
+    let application = fake.application().unwrap();
+    assert_eq!(application.characteristic(&uuid).unwrap().flags, secure);
+    assert_eq!(
+        application.descriptor(&descriptor_uuid).unwrap().flags,
+        secure
+    );
+}
+
+#[tokio::test]
+async fn it_hands_acquired_sockets_over() {
+    let fake = FakeBluez::start();
+    let (sender, mut receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| {
+            c.sender(sender)
+                .write_without_response()
+                .notify()
+                .acquire_write()
+                .acquire_notify()
+        })
+        .build()
+        .unwrap();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let application = fake.application().unwrap();
+    let path = &application.characteristic(&uuid).unwrap().path;
+
+    let options = RequestOptions {
+        mtu: 23,
+        ..RequestOptions::default()
+    };
+    let (mut socket, mtu) = fake.acquire_write(path, options.clone()).await.unwrap();
+    assert_eq!(mtu, 23);
+    let mut acquire_write = match receiver.next().await {
+        Some(Event::AcquireWrite(acquire_write)) => acquire_write,
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(acquire_write.mtu, 23);
+    assert_eq!(acquire_write.context.device, Some(DEVICE_PATH.to_string()));
+    socket.write_all(b"one").unwrap();
+    socket.write_all(b"two").unwrap();
+    assert_eq!(acquire_write.stream.next().await, Some(b"one".to_vec()));
+    assert_eq!(acquire_write.stream.next().await, Some(b"two".to_vec()));
+    drop(socket);
+    assert_eq!(acquire_write.stream.next().await, None);
+
+    let (mut socket, _) = fake.acquire_notify(path, options).await.unwrap();
+    let mut acquire_notify = match receiver.next().await {
+        Some(Event::AcquireNotify(acquire_notify)) => acquire_notify,
+        event => panic!("unexpected event {:?}", event),
+    };
+    acquire_notify.sink.send(b"value".to_vec()).await.unwrap();
+    let mut packet = [0; 23];
+    let read = socket.read(&mut packet).unwrap();
+    assert_eq!(&packet[..read], b"value");
+    let error = acquire_notify.sink.send(vec![0; 21]).await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+/// Only ever reads and writes its whole value.
+struct WholeValue(Mutex<Vec<u8>>);
+
+#[async_trait]
+impl CharacteristicHandler for WholeValue {
+    async fn on_read(&self, _ctx: &RequestContext, offset: u16) -> Result<Vec<u8>, AttError> {
+        assert_eq!(offset, 0);
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    async fn on_write(
+        &self,
+        _ctx: &RequestContext,
+        offset: u16,
+        data: Vec<u8>,
+        _kind: WriteKind,
+    ) -> Result<(), AttError> {
+        assert_eq!(offset, 0);
+        *self.0.lock().unwrap() = data;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn it_handles_offsets_of_long_values() {
+    let fake = FakeBluez::start();
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| {
+            c.handler(WholeValue(Mutex::new(b"hello".to_vec())))
+                .read()
+                .write()
+                .long_values()
+        })
+        .build()
+        .unwrap();
+    let peripheral = peripheral(&fake).await;
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let application = fake.application().unwrap();
+    let path = &application.characteristic(&uuid).unwrap().path;
+
+    assert_eq!(fake.read_value(path, offset(3)).await, Ok(b"lo".to_vec()));
+    assert_eq!(
+        fake.read_value(path, offset(6)).await,
+        Err("org.bluez.Error.InvalidOffset".to_string())
+    );
+
+    fake.write_value(path, b" world", offset(5)).await.unwrap();
+    assert_eq!(
+        fake.read_value(path, offset(0)).await,
+        Ok(b"hello world".to_vec())
+    );
+    assert_eq!(
+        fake.write_value(path, b"!", offset(12)).await,
+        Err("org.bluez.Error.InvalidOffset".to_string())
+    );
+    assert_eq!(
+        fake.write_value(path, &[0; 513], offset(0)).await,
+        Err("org.bluez.Error.InvalidValueLength".to_string())
+    );
+}
@@ -0,0 +1,114 @@
+//! The service and event handler shared by the sim and BlueZ tests.
+
+use futures::{
+    channel::mpsc::{channel, Receiver, Sender},
+    prelude::*,
+};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic,
+        characteristic::Characteristic,
+        descriptor,
+        descriptor::Descriptor,
+        event::{Authorization, Event, Response},
+        service::Service,
+    },
+    SdpShortUuid,
+};
+
+pub const SERVICE_UUID: u16 = 0x1234;
+pub const CHARACTERISTIC_UUID: u16 = 0x2A3D;
+pub const DESCRIPTOR_UUID: u16 = 0x2901;
+
+/// A readable, writable and notifying characteristic with a readable descriptor, along with
+/// the receivers of their events.
+pub fn service() -> (Service, Receiver<Event>, Receiver<Event>) {
+    let (sender_characteristic, receiver_characteristic) = channel(1);
+    let (sender_descriptor, receiver_descriptor) = channel(1);
+
+    let descriptors = vec![Descriptor::new(
+        Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID),
+        descriptor::Properties::new(
+            Some(descriptor::Read(descriptor::Secure::Insecure(
+                sender_descriptor,
+            ))),
+            None,
+        ),
+        None,
+    )];
+
+    let characteristics = vec![Characteristic::new(
+        Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID),
+        characteristic::Properties::new(
+            Some(characteristic::Read(characteristic::Secure::Insecure(
+                sender_characteristic.clone(),
+            ))),
+            Some(characteristic::Write::WithResponse(
+                characteristic::Secure::Insecure(sender_characteristic.clone()),
+            )),
+            Some(sender_characteristic),
+            None,
+        ),
+        None,
+        descriptors,
+    )];
+
+    (
+        Service::new(
+            Uuid::from_sdp_short_uuid(SERVICE_UUID),
+            true,
+            characteristics,
+        ),
+        receiver_characteristic,
+        receiver_descriptor,
+    )
+}
+
+/// Serves the events from `receiver` out of a value starting at `value`, which is returned.
+pub fn handle_events(mut receiver: Receiver<Event>, value: &str) -> Arc<Mutex<Vec<u8>>> {
+    let value = Arc::new(Mutex::new(value.as_bytes().to_vec()));
+    let handler_value = Arc::clone(&value);
+    tokio::spawn(async move {
+        let mut subscribers: Vec<Sender<Vec<u8>>> = vec![];
+        while let Some(event) = receiver.next().await {
+            match event {
+                Event::ReadRequest(read_request) => {
+                    let value = handler_value.lock().unwrap().clone();
+                    let response = match value.get(read_request.offset as usize..) {
+                        Some(value) => Response::Success(value.to_vec()),
+                        None => Response::InvalidOffset,
+                    };
+                    read_request.response.send(response).unwrap();
+                }
+                Event::WriteRequest(write_request) => {
+                    *handler_value.lock().unwrap() = write_request.data.clone();
+                    for subscriber in subscribers.iter_mut() {
+                        let _ = subscriber.send(write_request.data.clone()).await;
+                    }
+                    write_request
+                        .response
+                        .send(Response::Success(vec![]))
+                        .unwrap();
+                }
+                Event::NotifySubscribe(notify_subscribe) => {
+                    subscribers.push(notify_subscribe.notification);
+                }
+                Event::NotifyUnsubscribe(_) => {
+                    subscribers.clear();
+                }
+                Event::AuthorizeRequest(authorize_request) => {
+                    authorize_request
+                        .response
+                        .send(Authorization::Allow)
+                        .unwrap();
+                }
+                // Nothing here is built with `acquire_write` or `acquire_notify`.
+                Event::AcquireWrite(_) | Event::AcquireNotify(_) => {}
+            }
+        }
+    });
+    value
+}
@@ -0,0 +1,95 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT_BUS: AtomicUsize = AtomicUsize::new(0);
+
+const CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path={socket}</listen>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+/// A private `dbus-daemon` that lives as long as the value does.
+#[derive(Debug)]
+pub struct Bus {
+    daemon: Child,
+    directory: PathBuf,
+    pub address: String,
+}
+
+impl Bus {
+    /// Starts a new bus, failing when `dbus-daemon` cannot be run on this machine.
+    pub fn start() -> io::Result<Self> {
+        let directory = env::temp_dir().join(format!(
+            "bluster-fake-bluez-{}-{}",
+            process::id(),
+            NEXT_BUS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&directory)?;
+        let socket = directory.join("system_bus_socket");
+        let config = directory.join("bus.conf");
+        fs::write(
+            &config,
+            CONFIG.replace("{socket}", &socket.to_string_lossy()),
+        )?;
+
+        let daemon = spawn_daemon(&config)?;
+        Ok(Bus {
+            daemon,
+            directory,
+            address: format!("unix:path={}", socket.to_string_lossy()),
+        })
+    }
+
+    /// Stops the daemon and starts a new one listening on the same address.
+    pub fn restart(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+        fs::remove_file(self.directory.join("system_bus_socket")).ok();
+        self.daemon =
+            spawn_daemon(&self.directory.join("bus.conf")).expect("Could not restart dbus-daemon");
+    }
+}
+
+fn spawn_daemon(config: &Path) -> io::Result<Child> {
+    let mut daemon = Command::new("dbus-daemon")
+        .arg(format!("--config-file={}", config.to_string_lossy()))
+        .arg("--nofork")
+        .arg("--print-address")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // The daemon prints its address once it is listening.
+    let mut line = String::new();
+    let stdout = daemon.stdout.take().expect("stdout is piped");
+    if BufReader::new(stdout).read_line(&mut line)? == 0 {
+        daemon.kill().ok();
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "dbus-daemon exited without listening",
+        ));
+    }
+    Ok(daemon)
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+        fs::remove_dir_all(&self.directory).ok();
+    }
+}
@@ -0,0 +1,454 @@
+//! A stand-in for bluetoothd, serving `org.bluez` on a private bus.
+//!
+//! The fake exports an adapter at `/org/bluez/hci0` with the `Adapter1`, `LEAdvertisingManager1`
+//! and `GattManager1` interfaces, reads registered applications and advertisements back the way
+//! bluetoothd does, and can drive the exported GATT objects like a connected central. Adapters
+//! can be plugged, unplugged and switched off behind the application's back.
+
+mod bus;
+mod server;
+
+use dbus::{
+    arg::{OwnedFd, Variant},
+    Message,
+};
+use futures::channel::{mpsc, oneshot};
+use std::collections::HashMap;
+use std::{
+    os::unix::{io::FromRawFd, net::UnixStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+use uuid::Uuid;
+
+use self::{bus::Bus, server::Server};
+
+pub const ADAPTER_PATH: &str = "/org/bluez/hci0";
+pub const DEVICE_PATH: &str = "/org/bluez/hci0/dev_00_11_22_33_44_55";
+
+pub const GATT_SERVICE_IFACE: &str = "org.bluez.GattService1";
+pub const GATT_CHARACTERISTIC_IFACE: &str = "org.bluez.GattCharacteristic1";
+pub const GATT_DESCRIPTOR_IFACE: &str = "org.bluez.GattDescriptor1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdapterState {
+    pub path: String,
+    pub address: String,
+    pub name: String,
+    pub alias: String,
+    pub powered: bool,
+    pub discoverable: bool,
+    /// Whether the adapter exports `LEAdvertisingManager1` and `GattManager1`.
+    pub le: bool,
+}
+
+impl Default for AdapterState {
+    fn default() -> Self {
+        AdapterState {
+            path: ADAPTER_PATH.to_string(),
+            address: "00:00:5E:00:53:00".to_string(),
+            name: "fake-bluez".to_string(),
+            alias: "fake-bluez".to_string(),
+            powered: false,
+            discoverable: false,
+            le: true,
+        }
+    }
+}
+
+/// A `GattService1`, `GattCharacteristic1` or `GattDescriptor1` object of a registered
+/// application.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GattObject {
+    pub path: String,
+    pub interface: String,
+    pub uuid: String,
+    pub primary: Option<bool>,
+    /// The `Service` of a characteristic or the `Characteristic` of a descriptor.
+    pub parent: Option<String>,
+    pub flags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Application {
+    pub adapter: String,
+    pub owner: String,
+    pub path: String,
+    pub objects: Vec<GattObject>,
+}
+
+impl Application {
+    pub fn service(&self, uuid: &Uuid) -> Option<&GattObject> {
+        self.find(GATT_SERVICE_IFACE, uuid)
+    }
+
+    pub fn characteristic(&self, uuid: &Uuid) -> Option<&GattObject> {
+        self.find(GATT_CHARACTERISTIC_IFACE, uuid)
+    }
+
+    pub fn descriptor(&self, uuid: &Uuid) -> Option<&GattObject> {
+        self.find(GATT_DESCRIPTOR_IFACE, uuid)
+    }
+
+    fn find(&self, interface: &str, uuid: &Uuid) -> Option<&GattObject> {
+        let uuid = uuid.to_string();
+        self.objects
+            .iter()
+            .find(|object| object.interface == interface && object.uuid == uuid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advertisement {
+    pub adapter: String,
+    pub owner: String,
+    pub path: String,
+    pub kind: String,
+    pub local_name: Option<String>,
+    pub service_uuids: Vec<String>,
+}
+
+/// A `Value` sent by the application through `PropertiesChanged`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub path: String,
+    pub value: Vec<u8>,
+}
+
+/// The options bluetoothd attaches to `ReadValue` and `WriteValue`.
+#[derive(Debug, Clone)]
+pub struct RequestOptions {
+    pub offset: u16,
+    pub mtu: u16,
+    pub device: String,
+    pub link: String,
+    /// Sent with `WriteValue` only: `request`, `command` or `reliable`.
+    pub write_type: String,
+    /// Asks to authorize a prepared write instead of writing.
+    pub prepare_authorize: bool,
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        RequestOptions {
+            offset: 0,
+            mtu: 185,
+            device: DEVICE_PATH.to_string(),
+            link: "LE".to_string(),
+            write_type: "request".to_string(),
+            prepare_authorize: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    adapters: Vec<AdapterState>,
+    application: Option<Application>,
+    advertisement: Option<Advertisement>,
+    notifications: Vec<mpsc::UnboundedSender<Notification>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            adapters: vec![AdapterState::default()],
+            application: None,
+            advertisement: None,
+            notifications: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Method {
+    ReadValue(RequestOptions),
+    WriteValue(Vec<u8>, RequestOptions),
+    StartNotify,
+    StopNotify,
+    Confirm,
+    AcquireWrite(RequestOptions),
+    AcquireNotify(RequestOptions),
+}
+
+/// The D-Bus error name is all the tests need to look at.
+type CallResult = Result<Vec<u8>, String>;
+/// The whole reply, for the calls that return more than a value.
+type Reply = Result<Message, String>;
+
+#[derive(Debug)]
+struct Call {
+    path: String,
+    method: Method,
+    reply: oneshot::Sender<Reply>,
+}
+
+/// The server thread and the channels into it.
+#[derive(Debug)]
+struct ServerHandle {
+    calls: mpsc::UnboundedSender<Call>,
+    outgoing: mpsc::UnboundedSender<Message>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    fn start(address: &str, state: &Arc<Mutex<State>>) -> Self {
+        let (calls, call_receiver) = mpsc::unbounded();
+        let (outgoing, outgoing_receiver) = mpsc::unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = Server::connect(
+            address,
+            Arc::clone(state),
+            call_receiver,
+            outgoing_receiver,
+            Arc::clone(&stop),
+        )
+        .expect("Could not connect the fake bluez to its bus");
+
+        ServerHandle {
+            calls,
+            outgoing,
+            stop,
+            thread: Some(thread::spawn(move || server.run())),
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[derive(Debug)]
+pub struct FakeBluez {
+    state: Arc<Mutex<State>>,
+    server: ServerHandle,
+    bus: Bus,
+}
+
+impl FakeBluez {
+    /// Starts a fake bluetoothd on a bus of its own.
+    ///
+    /// Panics when no private bus can be started: these tests need `dbus-daemon` installed.
+    pub fn start() -> Self {
+        let bus = Bus::start().unwrap_or_else(|err| {
+            panic!(
+                "Could not start dbus-daemon, which the fake bluez tests need: {}",
+                err
+            )
+        });
+
+        let state = Arc::new(Mutex::new(State::default()));
+        FakeBluez {
+            server: ServerHandle::start(&bus.address, &state),
+            state,
+            bus,
+        }
+    }
+
+    /// Restarts bluetoothd: it forgets every registration and `org.bluez` changes owner.
+    pub fn restart(&self) {
+        self.forget_registrations();
+        self.send(server::release_name());
+        self.send(server::request_name());
+    }
+
+    /// Restarts the bus itself, dropping every connection to it.
+    pub fn restart_bus(&mut self) {
+        self.server.stop();
+        self.bus.restart();
+        self.forget_registrations();
+        self.server = ServerHandle::start(&self.bus.address, &self.state);
+    }
+
+    fn forget_registrations(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.application = None;
+        state.advertisement = None;
+    }
+
+    /// The address of the private bus the fake is serving on.
+    pub fn address(&self) -> &str {
+        &self.bus.address
+    }
+
+    /// The state of the default adapter, `hci0`.
+    pub fn adapter(&self) -> AdapterState {
+        self.adapter_at(ADAPTER_PATH).unwrap()
+    }
+
+    pub fn adapter_at(&self, path: &str) -> Option<AdapterState> {
+        self.state
+            .lock()
+            .unwrap()
+            .adapters
+            .iter()
+            .find(|adapter| adapter.path == path)
+            .cloned()
+    }
+
+    /// Plugs in another controller.
+    pub fn add_adapter(&self, adapter: AdapterState) {
+        self.send(server::interfaces_added(&adapter));
+        self.state.lock().unwrap().adapters.push(adapter);
+    }
+
+    /// Unplugs a controller.
+    pub fn remove_adapter(&self, path: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state
+            .adapters
+            .iter()
+            .position(|adapter| adapter.path == path)
+        {
+            let adapter = state.adapters.remove(index);
+            self.send(server::interfaces_removed(&adapter));
+        }
+    }
+
+    /// Switches a controller on or off without going through D-Bus, like rfkill does.
+    pub fn set_powered(&self, path: &str, powered: bool) {
+        self.update_adapter(path, "Powered", powered, |adapter| {
+            adapter.powered = powered
+        });
+    }
+
+    pub fn set_discoverable(&self, path: &str, discoverable: bool) {
+        self.update_adapter(path, "Discoverable", discoverable, |adapter| {
+            adapter.discoverable = discoverable
+        });
+    }
+
+    fn update_adapter<F: FnOnce(&mut AdapterState)>(
+        &self,
+        path: &str,
+        name: &str,
+        value: bool,
+        update: F,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let adapter = state
+            .adapters
+            .iter_mut()
+            .find(|adapter| adapter.path == path)
+            .expect("No such adapter");
+        update(adapter);
+        let mut changed = HashMap::new();
+        changed.insert(
+            name.to_string(),
+            Variant(Box::new(value) as Box<dyn dbus::arg::RefArg>),
+        );
+        self.send(server::properties_changed(path, changed));
+    }
+
+    fn send(&self, message: Message) {
+        self.server
+            .outgoing
+            .unbounded_send(message)
+            .expect("The fake bluez has stopped");
+    }
+
+    pub fn application(&self) -> Option<Application> {
+        self.state.lock().unwrap().application.clone()
+    }
+
+    pub fn advertisement(&self) -> Option<Advertisement> {
+        self.state.lock().unwrap().advertisement.clone()
+    }
+
+    /// Streams every characteristic value the application notifies from now on.
+    pub fn notifications(&self) -> mpsc::UnboundedReceiver<Notification> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.state.lock().unwrap().notifications.push(sender);
+        receiver
+    }
+
+    pub async fn read_value(&self, path: &str, options: RequestOptions) -> CallResult {
+        self.call(path, Method::ReadValue(options))
+            .await
+            .map(|reply| reply.get1::<Vec<u8>>().unwrap_or_default())
+    }
+
+    pub async fn write_value(
+        &self,
+        path: &str,
+        value: &[u8],
+        options: RequestOptions,
+    ) -> Result<(), String> {
+        self.call(path, Method::WriteValue(value.to_vec(), options))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn start_notify(&self, path: &str) -> Result<(), String> {
+        self.call(path, Method::StartNotify).await.map(|_| ())
+    }
+
+    pub async fn stop_notify(&self, path: &str) -> Result<(), String> {
+        self.call(path, Method::StopNotify).await.map(|_| ())
+    }
+
+    /// Passes on a central's confirmation of the last indication.
+    pub async fn confirm(&self, path: &str) -> Result<(), String> {
+        self.call(path, Method::Confirm).await.map(|_| ())
+    }
+
+    /// Takes the socket write commands go through, along with the MTU the application replied
+    /// with.
+    pub async fn acquire_write(
+        &self,
+        path: &str,
+        options: RequestOptions,
+    ) -> Result<(UnixStream, u16), String> {
+        self.call(path, Method::AcquireWrite(options))
+            .await
+            .and_then(acquired)
+    }
+
+    /// Takes the socket notifications come from, along with the MTU the application replied
+    /// with.
+    pub async fn acquire_notify(
+        &self,
+        path: &str,
+        options: RequestOptions,
+    ) -> Result<(UnixStream, u16), String> {
+        self.call(path, Method::AcquireNotify(options))
+            .await
+            .and_then(acquired)
+    }
+
+    async fn call(&self, path: &str, method: Method) -> Reply {
+        let (reply, receiver) = oneshot::channel();
+        self.server
+            .calls
+            .unbounded_send(Call {
+                path: path.to_string(),
+                method,
+                reply,
+            })
+            .expect("The fake bluez has stopped");
+        receiver.await.expect("The fake bluez has stopped")
+    }
+}
+
+/// The socket of an `AcquireWrite` or `AcquireNotify` reply, made blocking for the tests.
+fn acquired(reply: Message) -> Result<(UnixStream, u16), String> {
+    let (fd, mtu): (OwnedFd, u16) = reply.read2().map_err(|err| err.to_string())?;
+    let socket = unsafe { UnixStream::from_raw_fd(fd.into_fd()) };
+    socket
+        .set_nonblocking(false)
+        .map_err(|err| err.to_string())?;
+    Ok((socket, mtu))
+}
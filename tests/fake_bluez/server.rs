@@ -0,0 +1,669 @@
+use dbus::{
+    arg::{RefArg, Variant},
+    channel::Channel,
+    message::MessageType,
+    Message, Path,
+};
+use futures::channel::mpsc;
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::{
+    AdapterState, Advertisement, Application, Call, GattObject, Method, Notification, Reply,
+    RequestOptions, State, GATT_CHARACTERISTIC_IFACE, GATT_DESCRIPTOR_IFACE, GATT_SERVICE_IFACE,
+};
+
+const BLUEZ_SERVICE_NAME: &str = "org.bluez";
+const DBUS_PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+const DBUS_OBJECTMANAGER_IFACE: &str = "org.freedesktop.DBus.ObjectManager";
+const ADAPTER_IFACE: &str = "org.bluez.Adapter1";
+const LE_ADVERTISING_MANAGER_IFACE: &str = "org.bluez.LEAdvertisingManager1";
+const LE_ADVERTISEMENT_IFACE: &str = "org.bluez.LEAdvertisement1";
+const GATT_MANAGER_IFACE: &str = "org.bluez.GattManager1";
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+type PropMap = HashMap<String, Variant<Box<dyn RefArg>>>;
+type ManagedObjects = HashMap<Path<'static>, HashMap<String, PropMap>>;
+
+/// The bluetoothd side of the fake, run on its own thread so it outlives no test runtime.
+pub struct Server {
+    channel: Channel,
+    state: Arc<Mutex<State>>,
+    calls: mpsc::UnboundedReceiver<Call>,
+    outgoing: mpsc::UnboundedReceiver<Message>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Server {
+    pub fn connect(
+        address: &str,
+        state: Arc<Mutex<State>>,
+        calls: mpsc::UnboundedReceiver<Call>,
+        outgoing: mpsc::UnboundedReceiver<Message>,
+        stop: Arc<AtomicBool>,
+    ) -> Result<Self, dbus::Error> {
+        let mut channel = Channel::open_private(address)?;
+        channel.register()?;
+        let server = Server {
+            channel,
+            state,
+            calls,
+            outgoing,
+            stop,
+        };
+        server.bus_call(
+            "RequestName",
+            (
+                BLUEZ_SERVICE_NAME,
+                4u32, /* DBUS_NAME_FLAG_DO_NOT_QUEUE */
+            ),
+        )?;
+        // bluetoothd watches every exported characteristic for `Value` changes, and registered
+        // applications for objects coming and going.
+        server.bus_call(
+            "AddMatch",
+            ("type='signal',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'",),
+        )?;
+        server.bus_call(
+            "AddMatch",
+            ("type='signal',interface='org.freedesktop.DBus.ObjectManager'",),
+        )?;
+        Ok(server)
+    }
+
+    pub fn run(mut self) {
+        while !self.stop.load(Ordering::Relaxed) {
+            if self.channel.read_write(Some(POLL_INTERVAL)).is_err() {
+                break;
+            }
+            while let Some(message) = self.channel.pop_message() {
+                self.handle_message(&message);
+            }
+            while let Ok(call) = self.calls.try_recv() {
+                let result = self.call_application(&call.path, call.method);
+                call.reply.send(result).ok();
+            }
+            while let Ok(message) = self.outgoing.try_recv() {
+                self.channel.send(message).ok();
+            }
+        }
+    }
+
+    fn bus_call<A: dbus::arg::AppendAll>(&self, method: &str, args: A) -> Result<(), dbus::Error> {
+        let message = Message::call_with_args(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            method,
+            args,
+        );
+        self.channel
+            .send_with_reply_and_block(message, CALL_TIMEOUT)
+            .map(|_| ())
+    }
+
+    fn handle_message(&self, message: &Message) {
+        match message.msg_type() {
+            MessageType::MethodCall => {
+                let reply =
+                    self.handle_method_call(message)
+                        .unwrap_or_else(|(name, description)| {
+                            message.error(&name.into(), &CString::new(description).unwrap())
+                        });
+                self.channel.send(reply).ok();
+            }
+            MessageType::Signal => self.handle_signal(message),
+            _ => {}
+        }
+    }
+
+    fn handle_method_call(&self, message: &Message) -> Result<Message, (&'static str, String)> {
+        let path = message
+            .path()
+            .map(|path| path.to_string())
+            .unwrap_or_default();
+        let interface = message
+            .interface()
+            .map(|interface| interface.to_string())
+            .unwrap_or_default();
+        let member = message
+            .member()
+            .map(|member| member.to_string())
+            .unwrap_or_default();
+
+        if (path.as_str(), interface.as_str(), member.as_str())
+            == ("/", DBUS_OBJECTMANAGER_IFACE, "GetManagedObjects")
+        {
+            let mut objects = ManagedObjects::new();
+            for adapter in self.state.lock().unwrap().adapters.iter() {
+                objects.insert(adapter.path.clone().into(), adapter_interfaces(adapter));
+            }
+            return Ok(message.return_with_args((objects,)));
+        }
+
+        let unknown_method = || {
+            (
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                format!("{} {}.{} is not implemented", path, interface, member),
+            )
+        };
+        let adapter = self
+            .state
+            .lock()
+            .unwrap()
+            .adapters
+            .iter()
+            .find(|adapter| adapter.path == path)
+            .cloned()
+            .ok_or_else(unknown_method)?;
+
+        match (interface.as_str(), member.as_str()) {
+            (LE_ADVERTISING_MANAGER_IFACE, _) | (GATT_MANAGER_IFACE, _) if !adapter.le => {
+                Err(unknown_method())
+            }
+            (DBUS_PROPERTIES_IFACE, "Get") => {
+                let (interface, name): (String, String) = message.read2().map_err(invalid_args)?;
+                adapter_properties(&adapter)
+                    .remove(&name)
+                    .filter(|_| interface == ADAPTER_IFACE)
+                    .map(|value| message.return_with_args((value,)))
+                    .ok_or_else(|| unknown_property(&interface, &name))
+            }
+            (DBUS_PROPERTIES_IFACE, "GetAll") => {
+                let interface: String = message.read1().map_err(invalid_args)?;
+                let properties = if interface == ADAPTER_IFACE {
+                    adapter_properties(&adapter)
+                } else {
+                    PropMap::new()
+                };
+                Ok(message.return_with_args((properties,)))
+            }
+            (DBUS_PROPERTIES_IFACE, "Set") => {
+                let (interface, name, value): (String, String, Variant<Box<dyn RefArg>>) =
+                    message.read3().map_err(invalid_args)?;
+                if interface != ADAPTER_IFACE {
+                    return Err(unknown_property(&interface, &name));
+                }
+                self.set_adapter_property(&adapter.path, &name, &*value.0)?;
+                let mut changed = PropMap::new();
+                changed.insert(name, value);
+                self.channel
+                    .send(properties_changed(&adapter.path, changed))
+                    .ok();
+                Ok(message.method_return())
+            }
+            (LE_ADVERTISING_MANAGER_IFACE, "RegisterAdvertisement") => {
+                let (path, _options): (Path, PropMap) = message.read2().map_err(invalid_args)?;
+                let owner = sender(message);
+                if self.state.lock().unwrap().advertisement.is_some() {
+                    return Err((
+                        "org.bluez.Error.AlreadyExists",
+                        "Already Exists".to_string(),
+                    ));
+                }
+                let advertisement = self
+                    .read_advertisement(&adapter.path, &owner, &path)
+                    .map_err(|err| ("org.bluez.Error.Failed", err.to_string()))?;
+                self.state.lock().unwrap().advertisement = Some(advertisement);
+                Ok(message.method_return())
+            }
+            (LE_ADVERTISING_MANAGER_IFACE, "UnregisterAdvertisement") => {
+                let path: Path = message.read1().map_err(invalid_args)?;
+                let mut state = self.state.lock().unwrap();
+                match state.advertisement {
+                    Some(ref advertisement) if advertisement.path == *path => {
+                        state.advertisement = None;
+                        Ok(message.method_return())
+                    }
+                    _ => Err(does_not_exist()),
+                }
+            }
+            (GATT_MANAGER_IFACE, "RegisterApplication") => {
+                let (path, _options): (Path, PropMap) = message.read2().map_err(invalid_args)?;
+                let owner = sender(message);
+                if self.state.lock().unwrap().application.is_some() {
+                    return Err((
+                        "org.bluez.Error.AlreadyExists",
+                        "Already Exists".to_string(),
+                    ));
+                }
+                let application = self
+                    .read_application(&adapter.path, &owner, &path)
+                    .map_err(|err| ("org.bluez.Error.Failed", err.to_string()))?;
+                self.state.lock().unwrap().application = Some(application);
+                Ok(message.method_return())
+            }
+            (GATT_MANAGER_IFACE, "UnregisterApplication") => {
+                let path: Path = message.read1().map_err(invalid_args)?;
+                let mut state = self.state.lock().unwrap();
+                match state.application {
+                    Some(ref application) if application.path == *path => {
+                        state.application = None;
+                        Ok(message.method_return())
+                    }
+                    _ => Err(does_not_exist()),
+                }
+            }
+            _ => Err(unknown_method()),
+        }
+    }
+
+    fn handle_signal(&self, message: &Message) {
+        match message.member().as_deref() {
+            Some("PropertiesChanged") => self.handle_properties_changed(message),
+            Some("InterfacesAdded") => self.handle_interfaces_added(message),
+            Some("InterfacesRemoved") => self.handle_interfaces_removed(message),
+            _ => {}
+        }
+    }
+
+    fn handle_interfaces_added(&self, message: &Message) {
+        let (object_path, interfaces): (Path, HashMap<String, PropMap>) = match message.read2() {
+            Ok(args) => args,
+            Err(_) => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        if let Some(application) = registered_application(&mut state, message, &object_path) {
+            application
+                .objects
+                .retain(|object| object.path != *object_path);
+            application
+                .objects
+                .extend(gatt_objects(&object_path, &interfaces));
+            application.objects.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+    }
+
+    fn handle_interfaces_removed(&self, message: &Message) {
+        let (object_path, interfaces): (Path, Vec<String>) = match message.read2() {
+            Ok(args) => args,
+            Err(_) => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        if let Some(application) = registered_application(&mut state, message, &object_path) {
+            application.objects.retain(|object| {
+                object.path != *object_path || !interfaces.contains(&object.interface)
+            });
+        }
+    }
+
+    fn handle_properties_changed(&self, message: &Message) {
+        let (interface, changed, _invalidated): (String, PropMap, Vec<String>) =
+            match message.read3() {
+                Ok(args) => args,
+                Err(_) => return,
+            };
+        if interface != GATT_CHARACTERISTIC_IFACE {
+            return;
+        }
+        if let Some(value) = changed.get("Value").and_then(|value| bytes(&*value.0)) {
+            let notification = Notification {
+                path: message
+                    .path()
+                    .map(|path| path.to_string())
+                    .unwrap_or_default(),
+                value,
+            };
+            self.state
+                .lock()
+                .unwrap()
+                .notifications
+                .retain(|sender| sender.unbounded_send(notification.clone()).is_ok());
+        }
+    }
+
+    fn set_adapter_property(
+        &self,
+        path: &str,
+        name: &str,
+        value: &dyn RefArg,
+    ) -> Result<(), (&'static str, String)> {
+        let mut state = self.state.lock().unwrap();
+        let adapter = state
+            .adapters
+            .iter_mut()
+            .find(|adapter| adapter.path == path)
+            .unwrap();
+        match name {
+            "Powered" => adapter.powered = value.as_u64().ok_or_else(invalid_value)? != 0,
+            "Discoverable" => adapter.discoverable = value.as_u64().ok_or_else(invalid_value)? != 0,
+            "Alias" => adapter.alias = value.as_str().ok_or_else(invalid_value)?.to_string(),
+            "Address" | "Name" => {
+                return Err((
+                    "org.freedesktop.DBus.Error.PropertyReadOnly",
+                    format!("Property '{}' is not writable", name),
+                ))
+            }
+            _ => return Err(unknown_property(ADAPTER_IFACE, name)),
+        }
+        Ok(())
+    }
+
+    /// Fetches the advertisement properties, like bluetoothd does on `RegisterAdvertisement`.
+    fn read_advertisement(
+        &self,
+        adapter: &str,
+        owner: &str,
+        path: &Path,
+    ) -> Result<Advertisement, dbus::Error> {
+        let message = Message::call_with_args(
+            owner,
+            path.clone(),
+            DBUS_PROPERTIES_IFACE,
+            "GetAll",
+            (LE_ADVERTISEMENT_IFACE,),
+        );
+        let reply = self
+            .channel
+            .send_with_reply_and_block(message, CALL_TIMEOUT)?;
+        let properties: PropMap = reply.read1()?;
+        Ok(Advertisement {
+            adapter: adapter.to_string(),
+            owner: owner.to_string(),
+            path: path.to_string(),
+            kind: string(&properties, "Type").unwrap_or_default(),
+            local_name: string(&properties, "LocalName"),
+            service_uuids: strings(&properties, "ServiceUUIDs"),
+        })
+    }
+
+    /// Walks the application's object manager, like bluetoothd does on `RegisterApplication`.
+    fn read_application(
+        &self,
+        adapter: &str,
+        owner: &str,
+        path: &Path,
+    ) -> Result<Application, dbus::Error> {
+        let message = Message::call_with_args(
+            owner,
+            path.clone(),
+            DBUS_OBJECTMANAGER_IFACE,
+            "GetManagedObjects",
+            (),
+        );
+        let reply = self
+            .channel
+            .send_with_reply_and_block(message, CALL_TIMEOUT)?;
+        let managed_objects: ManagedObjects = reply.read1()?;
+
+        let mut objects: Vec<GattObject> = managed_objects
+            .iter()
+            .flat_map(|(object_path, interfaces)| gatt_objects(object_path, interfaces))
+            .collect();
+        objects.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Application {
+            adapter: adapter.to_string(),
+            owner: owner.to_string(),
+            path: path.to_string(),
+            objects,
+        })
+    }
+
+    /// Calls into an exported characteristic or descriptor the way bluetoothd does when a
+    /// connected central accesses it.
+    fn call_application(&self, path: &str, method: Method) -> Reply {
+        let (owner, interface) = {
+            let state = self.state.lock().unwrap();
+            let application = state
+                .application
+                .as_ref()
+                .ok_or_else(|| "org.bluez.Error.NotRegistered".to_string())?;
+            let object = application
+                .objects
+                .iter()
+                .find(|object| object.path == path && object.interface != GATT_SERVICE_IFACE)
+                .ok_or_else(|| "org.freedesktop.DBus.Error.UnknownObject".to_string())?;
+            (application.owner.clone(), object.interface.clone())
+        };
+
+        let message = match method {
+            Method::ReadValue(options) => Message::call_with_args(
+                owner,
+                path,
+                interface,
+                "ReadValue",
+                (request_options(&options, None),),
+            ),
+            Method::WriteValue(value, options) => Message::call_with_args(
+                owner,
+                path,
+                interface,
+                "WriteValue",
+                (value, request_options(&options, Some(&options.write_type))),
+            ),
+            Method::StartNotify => {
+                Message::call_with_args(owner, path, interface, "StartNotify", ())
+            }
+            Method::StopNotify => Message::call_with_args(owner, path, interface, "StopNotify", ()),
+            Method::Confirm => Message::call_with_args(owner, path, interface, "Confirm", ()),
+            Method::AcquireWrite(options) => Message::call_with_args(
+                owner,
+                path,
+                interface,
+                "AcquireWrite",
+                (request_options(&options, None),),
+            ),
+            Method::AcquireNotify(options) => Message::call_with_args(
+                owner,
+                path,
+                interface,
+                "AcquireNotify",
+                (request_options(&options, None),),
+            ),
+        };
+
+        self.channel
+            .send_with_reply_and_block(message, CALL_TIMEOUT)
+            .map_err(|err| err.name().unwrap_or_default().to_string())
+    }
+}
+
+pub fn release_name() -> Message {
+    bus_method_call("ReleaseName").append1(BLUEZ_SERVICE_NAME)
+}
+
+pub fn request_name() -> Message {
+    bus_method_call("RequestName").append2(
+        BLUEZ_SERVICE_NAME,
+        4u32, /* DBUS_NAME_FLAG_DO_NOT_QUEUE */
+    )
+}
+
+fn bus_method_call(method: &str) -> Message {
+    Message::new_method_call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        method,
+    )
+    .unwrap()
+}
+
+/// The `PropertiesChanged` signal bluetoothd emits when adapter properties change.
+pub fn properties_changed(path: &str, changed: PropMap) -> Message {
+    Message::signal(
+        &path.to_string().into(),
+        &DBUS_PROPERTIES_IFACE.into(),
+        &"PropertiesChanged".into(),
+    )
+    .append3(ADAPTER_IFACE, changed, Vec::<String>::new())
+}
+
+/// The `InterfacesAdded` signal bluetoothd emits when a controller is plugged in.
+pub fn interfaces_added(adapter: &AdapterState) -> Message {
+    Message::signal(
+        &"/".into(),
+        &DBUS_OBJECTMANAGER_IFACE.into(),
+        &"InterfacesAdded".into(),
+    )
+    .append2(
+        Path::from(adapter.path.clone()),
+        adapter_interfaces(adapter),
+    )
+}
+
+/// The `InterfacesRemoved` signal bluetoothd emits when a controller is unplugged.
+pub fn interfaces_removed(adapter: &AdapterState) -> Message {
+    let interfaces: Vec<String> = adapter_interfaces(adapter).into_keys().collect();
+    Message::signal(
+        &"/".into(),
+        &DBUS_OBJECTMANAGER_IFACE.into(),
+        &"InterfacesRemoved".into(),
+    )
+    .append2(Path::from(adapter.path.clone()), interfaces)
+}
+
+fn adapter_interfaces(adapter: &AdapterState) -> HashMap<String, PropMap> {
+    let mut interfaces = HashMap::new();
+    interfaces.insert(ADAPTER_IFACE.to_string(), adapter_properties(adapter));
+    if adapter.le {
+        interfaces.insert(LE_ADVERTISING_MANAGER_IFACE.to_string(), PropMap::new());
+        interfaces.insert(GATT_MANAGER_IFACE.to_string(), PropMap::new());
+    }
+    interfaces
+}
+
+/// The application a signal is about, if its sender registered one that contains the path.
+fn registered_application<'a>(
+    state: &'a mut State,
+    message: &Message,
+    object_path: &str,
+) -> Option<&'a mut Application> {
+    state.application.as_mut().filter(|application| {
+        application.owner == sender(message)
+            && object_path.starts_with(&format!("{}/", application.path))
+    })
+}
+
+fn gatt_objects(object_path: &Path, interfaces: &HashMap<String, PropMap>) -> Vec<GattObject> {
+    interfaces
+        .iter()
+        .filter_map(|(interface, properties)| {
+            let parent = match interface.as_str() {
+                GATT_SERVICE_IFACE => None,
+                GATT_CHARACTERISTIC_IFACE => string(properties, "Service"),
+                GATT_DESCRIPTOR_IFACE => string(properties, "Characteristic"),
+                _ => return None,
+            };
+            Some(GattObject {
+                path: object_path.to_string(),
+                interface: interface.clone(),
+                uuid: string(properties, "UUID").unwrap_or_default(),
+                primary: properties
+                    .get("Primary")
+                    .and_then(|primary| primary.0.as_u64())
+                    .map(|primary| primary != 0),
+                parent,
+                flags: strings(properties, "Flags"),
+            })
+        })
+        .collect()
+}
+
+fn adapter_properties(adapter: &AdapterState) -> PropMap {
+    let mut properties = PropMap::new();
+    properties.insert(
+        "Address".to_string(),
+        Variant(Box::new(adapter.address.clone())),
+    );
+    properties.insert("Name".to_string(), Variant(Box::new(adapter.name.clone())));
+    properties.insert(
+        "Alias".to_string(),
+        Variant(Box::new(adapter.alias.clone())),
+    );
+    properties.insert("Powered".to_string(), Variant(Box::new(adapter.powered)));
+    properties.insert(
+        "Discoverable".to_string(),
+        Variant(Box::new(adapter.discoverable)),
+    );
+    properties
+}
+
+fn request_options(options: &RequestOptions, write_type: Option<&str>) -> PropMap {
+    let mut map = PropMap::new();
+    // bluetoothd leaves the offset out of the dictionary when it is zero.
+    if options.offset != 0 {
+        map.insert("offset".to_string(), Variant(Box::new(options.offset)));
+    }
+    map.insert("mtu".to_string(), Variant(Box::new(options.mtu)));
+    map.insert(
+        "device".to_string(),
+        Variant(Box::new(Path::from(options.device.clone()))),
+    );
+    map.insert("link".to_string(), Variant(Box::new(options.link.clone())));
+    if let Some(write_type) = write_type {
+        map.insert(
+            "type".to_string(),
+            Variant(Box::new(write_type.to_string())),
+        );
+        if options.prepare_authorize {
+            map.insert("prepare-authorize".to_string(), Variant(Box::new(true)));
+        }
+    }
+    map
+}
+
+fn sender(message: &Message) -> String {
+    message
+        .sender()
+        .map(|sender| sender.to_string())
+        .unwrap_or_default()
+}
+
+fn string(properties: &PropMap, name: &str) -> Option<String> {
+    properties
+        .get(name)
+        .and_then(|value| value.0.as_str())
+        .map(ToString::to_string)
+}
+
+fn strings(properties: &PropMap, name: &str) -> Vec<String> {
+    properties
+        .get(name)
+        .and_then(|value| value.0.as_iter())
+        .map(|values| {
+            values
+                .filter_map(|value| value.as_str().map(ToString::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn bytes(value: &dyn RefArg) -> Option<Vec<u8>> {
+    value
+        .as_iter()?
+        .map(|byte| byte.as_u64().map(|byte| byte as u8))
+        .collect()
+}
+
+fn invalid_args(err: dbus::arg::TypeMismatchError) -> (&'static str, String) {
+    ("org.freedesktop.DBus.Error.InvalidArgs", err.to_string())
+}
+
+fn invalid_value() -> (&'static str, String) {
+    (
+        "org.freedesktop.DBus.Error.InvalidArgs",
+        "Invalid property value".to_string(),
+    )
+}
+
+fn unknown_property(interface: &str, name: &str) -> (&'static str, String) {
+    (
+        "org.freedesktop.DBus.Error.InvalidArgs",
+        format!("No such property '{}' on {}", name, interface),
+    )
+}
+
+fn does_not_exist() -> (&'static str, String) {
+    ("org.bluez.Error.DoesNotExist", "Does Not Exist".to_string())
+}
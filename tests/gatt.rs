@@ -0,0 +1,271 @@
+use async_trait::async_trait;
+use futures::channel::mpsc::channel;
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        builder::BuildError,
+        characteristic::{self, Characteristic},
+        descriptor::{self, Descriptor},
+        event::{RequestContext, Response},
+        handle::CharacteristicHandle,
+        handler::{AttError, DescriptorHandler},
+        service::Service,
+    },
+    peripheral::sim,
+    SdpShortUuid,
+};
+
+const SERVICE_UUID: u16 = 0x1234;
+const CHARACTERISTIC_UUID: u16 = 0x2A3D;
+const DESCRIPTOR_UUID: u16 = 0x2901;
+
+struct Description;
+
+#[async_trait]
+impl DescriptorHandler for Description {
+    async fn on_read(&self, _ctx: &RequestContext, _offset: u16) -> Result<Vec<u8>, AttError> {
+        Ok(b"level".to_vec())
+    }
+}
+
+#[tokio::test]
+async fn it_builds_services() {
+    let (sender, _receiver) = channel(1);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.sender(sender)
+                .read()
+                .write_without_response()
+                .notify()
+                .descriptor(Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID), |d| d.read())
+        })
+        .characteristic(Uuid::from_sdp_short_uuid(0x2A24u16), |c| c.value("model"))
+        .build()
+        .unwrap();
+
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    peripheral.add_service(&service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    let services = peripheral.central().discover_services().await.unwrap();
+    assert!(services[0].primary);
+
+    let characteristics = &services[0].characteristics;
+    assert_eq!(characteristics.len(), 2);
+    let properties = &characteristics[0].properties;
+    assert!(properties.read && properties.write_without_response && properties.notify);
+    assert!(!properties.write && !properties.indicate);
+    assert!(characteristics[0].descriptors[0].properties.read);
+    assert!(characteristics[1].properties.read);
+}
+
+#[tokio::test]
+async fn it_builds_what_the_constructors_build() {
+    let (sender, _receiver) = channel(1);
+    let (descriptor_sender, _descriptor_receiver) = channel(1);
+    let built = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.sender(sender.clone())
+                .read()
+                .write()
+                .notify()
+                .descriptor(Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID), |d| {
+                    d.sender(descriptor_sender.clone()).read().write()
+                })
+        })
+        .build()
+        .unwrap();
+    let constructed = Service::new(
+        Uuid::from_sdp_short_uuid(SERVICE_UUID),
+        true,
+        vec![Characteristic::new(
+            Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID),
+            characteristic::Properties::new(
+                Some(characteristic::Read(characteristic::Secure::Insecure(
+                    sender.clone(),
+                ))),
+                Some(characteristic::Write::WithResponse(
+                    characteristic::Secure::Insecure(sender.clone()),
+                )),
+                Some(sender),
+                None,
+            ),
+            None,
+            vec![Descriptor::new(
+                Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID),
+                descriptor::Properties::new(
+                    Some(descriptor::Read(descriptor::Secure::Insecure(
+                        descriptor_sender.clone(),
+                    ))),
+                    Some(descriptor::Write(descriptor::Secure::Insecure(
+                        descriptor_sender,
+                    ))),
+                ),
+                None,
+            )],
+        )],
+    );
+
+    let mut discovered = vec![];
+    for service in &[built, constructed] {
+        let peripheral = sim::Peripheral::new().await.unwrap();
+        peripheral.add_service(service).unwrap();
+        peripheral.register_gatt().await.unwrap();
+        discovered.push(peripheral.central().discover_services().await.unwrap());
+    }
+    assert_eq!(discovered[0], discovered[1]);
+}
+
+#[test]
+fn it_builds_served_services_outside_of_a_runtime() {
+    let level = CharacteristicHandle::new(vec![100]);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.managed(&level)
+                .read()
+                .descriptor(Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID), |d| {
+                    d.handler(Description).read()
+                })
+        })
+        .build()
+        .unwrap();
+
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let peripheral = sim::Peripheral::new().await.unwrap();
+        peripheral.add_service(&service).unwrap();
+        peripheral.register_gatt().await.unwrap();
+        let central = peripheral.central();
+        let services = central.discover_services().await.unwrap();
+        let characteristic = &services[0].characteristics[0];
+        assert_eq!(
+            central.read(characteristic.handle, 0).await.unwrap(),
+            Response::Success(vec![100])
+        );
+        assert_eq!(
+            central
+                .read(characteristic.descriptors[0].handle, 0)
+                .await
+                .unwrap(),
+            Response::Success(b"level".to_vec())
+        );
+    });
+}
+
+#[test]
+fn it_rejects_invalid_combinations() {
+    let (sender, _receiver) = channel(1);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let build = |configure: fn(_) -> _| {
+        Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+            .characteristic(uuid, configure)
+            .build()
+            .err()
+    };
+
+    assert_eq!(
+        build(|c| c.read()),
+        Some(BuildError::MissingEventSender(uuid))
+    );
+    assert_eq!(build(|c| c), Some(BuildError::Inaccessible(uuid)));
+    assert_eq!(
+        build(|c| c.write().acquire_write()),
+        Some(BuildError::NothingToAcquire(uuid))
+    );
+    assert_eq!(
+        build(|c| c.indicate().acquire_notify()),
+        Some(BuildError::NothingToAcquire(uuid))
+    );
+
+    let characteristic = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .characteristic(uuid, |c| {
+            c.sender(sender.clone()).write().write_without_response()
+        })
+        .build();
+    assert_eq!(
+        characteristic.err(),
+        Some(BuildError::ConflictingWrite(uuid))
+    );
+
+    let characteristic = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .characteristic(uuid, |c| c.sender(sender.clone()).write().value("fixed"))
+        .build();
+    assert_eq!(
+        characteristic.err(),
+        Some(BuildError::WritableFixedValue(uuid))
+    );
+
+    let descriptor_uuid = Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID);
+    assert_eq!(
+        Descriptor::builder(descriptor_uuid).write().build().err(),
+        Some(BuildError::MissingEventSender(descriptor_uuid))
+    );
+    assert_eq!(
+        Descriptor::builder(descriptor_uuid)
+            .value("fixed")
+            .authorize()
+            .build()
+            .err(),
+        Some(BuildError::AuthorizedFixedValue(descriptor_uuid))
+    );
+    assert!(Descriptor::builder(descriptor_uuid)
+        .sender(sender.clone())
+        .write()
+        .build()
+        .is_ok());
+
+    let level = CharacteristicHandle::new(vec![100]);
+    let characteristic = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .characteristic(uuid, |c| c.managed(&level).read().authorize())
+        .build();
+    assert_eq!(
+        characteristic.err(),
+        Some(BuildError::AuthorizedManagedValue(uuid))
+    );
+    // The handle only keeps the characteristic's value, so descriptors need their own sender.
+    let characteristic = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .characteristic(uuid, |c| {
+            c.managed(&level)
+                .read()
+                .descriptor(descriptor_uuid, |d| d.read())
+        })
+        .build();
+    assert_eq!(
+        characteristic.err(),
+        Some(BuildError::MissingEventSender(descriptor_uuid))
+    );
+    let characteristic = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .characteristic(uuid, |c| {
+            c.managed(&level)
+                .read()
+                .descriptor(descriptor_uuid, |d| d.sender(sender).read())
+        })
+        .build();
+    assert!(characteristic.is_ok());
+}
+
+#[test]
+fn it_knows_att_error_codes() {
+    assert_eq!(Response::Success(vec![]).att_error_code(), None);
+    assert_eq!(Response::ReadNotPermitted.att_error_code(), Some(0x02));
+    assert_eq!(
+        Response::InsufficientEncryption.att_error_code(),
+        Some(0x0F)
+    );
+    assert_eq!(Response::ValueNotAllowed.att_error_code(), Some(0x13));
+    assert_eq!(
+        Response::from(AttError::Application(0x9F)).att_error_code(),
+        Some(0x9F)
+    );
+    // Codes outside of the application range are not the application's to use.
+    assert_eq!(
+        Response::ApplicationError(0x20).att_error_code(),
+        Some(0x0E)
+    );
+}
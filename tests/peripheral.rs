@@ -1,8 +1,6 @@
 use futures::{channel::mpsc::channel, prelude::*};
 use std::{
-    collections::HashSet,
     sync::{atomic, Arc, Mutex},
-    thread,
     time::Duration,
 };
 use uuid::Uuid;
@@ -13,7 +11,8 @@ use bluster::{
         characteristic::Characteristic,
         descriptor,
         descriptor::Descriptor,
-        event::{Event, Response},
+        event::{Authorization, Event, Response},
+        notifier::{Notifier, NotifyPolicy},
         service::Service,
     },
     Peripheral, SdpShortUuid,
@@ -30,8 +29,7 @@ async fn it_advertises_gatt() {
     let (sender_characteristic, receiver_characteristic) = channel(1);
     let (sender_descriptor, receiver_descriptor) = channel(1);
 
-    let mut characteristics: HashSet<Characteristic> = HashSet::new();
-    characteristics.insert(Characteristic::new(
+    let characteristics = vec![Characteristic::new(
         Uuid::from_sdp_short_uuid(0x2A3D as u16),
         characteristic::Properties::new(
             Some(characteristic::Read(characteristic::Secure::Insecure(
@@ -44,23 +42,19 @@ async fn it_advertises_gatt() {
             None,
         ),
         None,
-        {
-            let mut descriptors = HashSet::<Descriptor>::new();
-            descriptors.insert(Descriptor::new(
-                Uuid::from_sdp_short_uuid(0x2A3D as u16),
-                descriptor::Properties::new(
-                    Some(descriptor::Read(descriptor::Secure::Insecure(
-                        sender_descriptor.clone(),
-                    ))),
-                    Some(descriptor::Write(descriptor::Secure::Insecure(
-                        sender_descriptor,
-                    ))),
-                ),
-                None,
-            ));
-            descriptors
-        },
-    ));
+        vec![Descriptor::new(
+            Uuid::from_sdp_short_uuid(0x2A3D as u16),
+            descriptor::Properties::new(
+                Some(descriptor::Read(descriptor::Secure::Insecure(
+                    sender_descriptor.clone(),
+                ))),
+                Some(descriptor::Write(descriptor::Secure::Insecure(
+                    sender_descriptor,
+                ))),
+            ),
+            None,
+        )],
+    )];
 
     let characteristic_handler = async {
         let characteristic_value = Arc::new(Mutex::new(String::from("hi")));
@@ -96,7 +90,8 @@ async fn it_advertises_gatt() {
                     println!("GATT server got a notify subscription!");
                     let notifying = Arc::clone(&notifying);
                     notifying.store(true, atomic::Ordering::Relaxed);
-                    thread::spawn(move || {
+                    let mut notifier = Notifier::new(notify_subscribe, NotifyPolicy::LatestWins);
+                    tokio::spawn(async move {
                         let mut count = 0;
                         loop {
                             if !(&notifying).load(atomic::Ordering::Relaxed) {
@@ -104,19 +99,30 @@ async fn it_advertises_gatt() {
                             };
                             count += 1;
                             println!("GATT server notifying \"hi {}\"!", count);
-                            notify_subscribe
-                                .clone()
-                                .notification
-                                .try_send(format!("hi {}", count).into())
-                                .unwrap();
-                            thread::sleep(Duration::from_secs(2));
+                            if notifier
+                                .notify(format!("hi {}", count).into())
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
+                            tokio::time::delay_for(Duration::from_secs(2)).await;
                         }
                     });
                 }
-                Event::NotifyUnsubscribe => {
+                Event::NotifyUnsubscribe(_) => {
                     println!("GATT server got a notify unsubscribe!");
                     notifying.store(false, atomic::Ordering::Relaxed);
                 }
+                Event::AuthorizeRequest(authorize_request) => {
+                    println!("GATT server got an authorization request!");
+                    authorize_request
+                        .response
+                        .send(Authorization::Allow)
+                        .unwrap();
+                }
+                // Nothing here is built with `acquire_write` or `acquire_notify`.
+                Event::AcquireWrite(_) | Event::AcquireNotify(_) => {}
             };
         }
     };
@@ -0,0 +1,1062 @@
+mod common;
+
+use async_trait::async_trait;
+use futures::{
+    channel::mpsc::{channel, Sender},
+    prelude::*,
+};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use bluster::{
+    gatt::{
+        characteristic,
+        characteristic::Characteristic,
+        descriptor,
+        descriptor::Descriptor,
+        event::{
+            Access, Authorization, Event, IndicationError, Link, NotifyMode, NotifySubscribe,
+            RequestContext, Response, SubscriptionId, WriteKind,
+        },
+        handle::CharacteristicHandle,
+        handler::{AttError, CharacteristicHandler, DescriptorHandler},
+        notifier::{Delivery, Notifier, NotifyError, NotifyPolicy},
+        security::SecurityLevel,
+        service::Service,
+    },
+    peripheral::{
+        event::{Event as PeripheralEvent, State},
+        sim, Peripheral,
+    },
+    SdpShortUuid,
+};
+
+use self::common::{handle_events, service, CHARACTERISTIC_UUID, DESCRIPTOR_UUID, SERVICE_UUID};
+
+async fn start<P: Peripheral>(peripheral: &P, service: &Service) {
+    peripheral.add_service(service).unwrap();
+    peripheral.register_gatt().await.unwrap();
+    peripheral
+        .start_advertising("sim", &[Uuid::from_sdp_short_uuid(SERVICE_UUID)])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn it_discovers_services() {
+    let (service, _, _) = service();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let advertisement = central.scan().unwrap();
+    assert_eq!(advertisement.name, "sim");
+    assert_eq!(
+        advertisement.uuids,
+        vec![Uuid::from_sdp_short_uuid(SERVICE_UUID)]
+    );
+
+    let services = central.discover_services().await.unwrap();
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0].uuid, Uuid::from_sdp_short_uuid(SERVICE_UUID));
+    let characteristic = &services[0].characteristics[0];
+    assert_eq!(
+        characteristic.uuid,
+        Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID)
+    );
+    assert!(characteristic.properties.read);
+    assert!(characteristic.properties.write);
+    assert!(characteristic.properties.notify);
+    assert!(!characteristic.properties.write_without_response);
+    assert_eq!(
+        characteristic.descriptors[0].uuid,
+        Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID)
+    );
+}
+
+#[tokio::test]
+async fn it_reads_and_writes_through_event_handlers() {
+    let (service, receiver_characteristic, receiver_descriptor) = service();
+    let value = handle_events(receiver_characteristic, "hello");
+    handle_events(receiver_descriptor, "description");
+
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let services = central.discover_services().await.unwrap();
+    let characteristic = &services[0].characteristics[0];
+
+    assert_eq!(
+        central.read(characteristic.handle, 0).await.unwrap(),
+        Response::Success(b"hello".to_vec())
+    );
+    assert_eq!(
+        central.read(characteristic.handle, 3).await.unwrap(),
+        Response::Success(b"lo".to_vec())
+    );
+    assert_eq!(
+        central.read(characteristic.handle, 6).await.unwrap(),
+        Response::InvalidOffset
+    );
+    assert_eq!(
+        central
+            .read(characteristic.descriptors[0].handle, 0)
+            .await
+            .unwrap(),
+        Response::Success(b"description".to_vec())
+    );
+
+    assert_eq!(
+        central
+            .write(characteristic.handle, 0, "world")
+            .await
+            .unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(*value.lock().unwrap(), b"world".to_vec());
+    assert!(central
+        .write(characteristic.descriptors[0].handle, 0, "nope")
+        .await
+        .is_err());
+    assert!(central
+        .write_without_response(characteristic.handle, "nope")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn it_notifies_subscribed_centrals() {
+    let (service, receiver_characteristic, _) = service();
+    handle_events(receiver_characteristic, "hello");
+
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let services = central.discover_services().await.unwrap();
+    let handle = services[0].characteristics[0].handle;
+
+    let mut notifications = central.subscribe(handle).await.unwrap();
+    central.write(handle, 0, "one").await.unwrap();
+    assert_eq!(notifications.next().await, Some(b"one".to_vec()));
+
+    central.disconnect().await;
+    assert!(!central.is_connected());
+    assert_eq!(notifications.next().await, None);
+    assert!(central.read(handle, 0).await.is_err());
+}
+
+#[tokio::test]
+async fn it_confirms_indications() {
+    let (sender, mut receiver) = channel(4);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.sender(sender).notify().indicate()
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let handle = central.discover_services().await.unwrap()[0].characteristics[0].handle;
+
+    let _notifications = central.subscribe(handle).await.unwrap();
+    let subscription = match receiver.next().await {
+        Some(Event::NotifySubscribe(notify_subscribe)) => notify_subscribe,
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(subscription.mode, NotifyMode::Notify);
+    assert_eq!(
+        subscription.indicate(b"one".to_vec()).await,
+        Err(IndicationError::NotIndicating)
+    );
+
+    let mut indications = central
+        .subscribe_with(handle, NotifyMode::Indicate)
+        .await
+        .unwrap();
+    match receiver.next().await {
+        Some(Event::NotifyUnsubscribe(notify_unsubscribe)) => {
+            assert_eq!(notify_unsubscribe.id, subscription.id)
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    let subscription = match receiver.next().await {
+        Some(Event::NotifySubscribe(notify_subscribe)) => notify_subscribe,
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(subscription.mode, NotifyMode::Indicate);
+    let (confirmation, value) =
+        future::join(subscription.indicate(b"two".to_vec()), indications.next()).await;
+    assert_eq!(confirmation, Ok(()));
+    assert_eq!(value, Some(b"two".to_vec()));
+
+    drop(indications);
+    assert_eq!(
+        subscription.indicate(b"three".to_vec()).await,
+        Err(IndicationError::Disconnected)
+    );
+}
+
+#[tokio::test]
+async fn it_coalesces_notifications() {
+    let (sender, mut receiver) = channel(1);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.sender(sender).notify()
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let handle = central.discover_services().await.unwrap()[0].characteristics[0].handle;
+    let mut notifications = central.subscribe(handle).await.unwrap();
+    let subscription = match receiver.next().await {
+        Some(Event::NotifySubscribe(notify_subscribe)) => notify_subscribe,
+        event => panic!("unexpected event {:?}", event),
+    };
+
+    let mut latest = Notifier::new(subscription.clone(), NotifyPolicy::LatestWins);
+    assert_eq!(latest.notify(vec![1]).await, Ok(Delivery::Queued));
+    assert_eq!(latest.notify(vec![2]).await, Ok(Delivery::Coalesced));
+    assert_eq!(latest.notify(vec![3]).await, Ok(Delivery::Coalesced));
+    assert_eq!(notifications.next().await, Some(vec![3]));
+
+    let mut queue = Notifier::new(subscription, NotifyPolicy::Queue);
+    assert_eq!(queue.notify(vec![4]).await, Ok(Delivery::Queued));
+    assert_eq!(queue.notify(vec![5]).await, Ok(Delivery::Queued));
+    assert_eq!(notifications.next().await, Some(vec![4]));
+    assert_eq!(notifications.next().await, Some(vec![5]));
+
+    drop(notifications);
+    assert_eq!(queue.notify(vec![6]).await, Err(NotifyError::Unsubscribed));
+}
+
+#[tokio::test]
+async fn it_tracks_subscribers_per_central() {
+    let (sender, mut receiver) = channel(4);
+    let uuid = Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(uuid, |c| c.sender(sender).notify())
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let first = peripheral.central();
+    let second = peripheral.central();
+    let handle = first.discover_services().await.unwrap()[0].characteristics[0].handle;
+
+    let mut subscriptions = vec![];
+    for central in [&first, &second].iter() {
+        let _notifications = central.subscribe(handle).await.unwrap();
+        match receiver.next().await {
+            Some(Event::NotifySubscribe(notify_subscribe)) => {
+                assert_eq!(
+                    notify_subscribe.context.device.as_deref(),
+                    Some(central.address())
+                );
+                subscriptions.push(notify_subscribe.id);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+    assert_ne!(subscriptions[0], subscriptions[1]);
+    let subscribers = peripheral.subscribers(&uuid);
+    assert_eq!(
+        subscribers
+            .iter()
+            .map(|subscriber| subscriber.id)
+            .collect::<Vec<_>>(),
+        subscriptions
+    );
+    assert!(peripheral
+        .subscribers(&Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID))
+        .is_empty());
+
+    second.disconnect().await;
+    match receiver.next().await {
+        Some(Event::NotifyUnsubscribe(notify_unsubscribe)) => {
+            assert_eq!(notify_unsubscribe.id, subscriptions[1]);
+            assert_eq!(
+                notify_unsubscribe.context.device.as_deref(),
+                Some(second.address())
+            );
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    let subscribers = peripheral.subscribers(&uuid);
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(
+        subscribers[0].context.device.as_deref(),
+        Some(first.address())
+    );
+}
+
+#[tokio::test]
+async fn it_rejects_requests_until_gatt_is_registered() {
+    let (service, _, _) = service();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    peripheral.add_service(&service).unwrap();
+
+    let central = peripheral.central();
+    assert!(central.discover_services().await.is_err());
+
+    peripheral.register_gatt().await.unwrap();
+    assert!(central.discover_services().await.is_ok());
+
+    peripheral.unregister_gatt().await.unwrap();
+    assert!(central.discover_services().await.is_err());
+}
+
+#[tokio::test]
+async fn it_reports_power_changes() {
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    let mut events = peripheral.events();
+    assert!(peripheral.is_powered().await.unwrap());
+
+    peripheral.set_powered(false);
+    assert_eq!(
+        events.next().await,
+        Some(PeripheralEvent::StateChange(State::PoweredOff))
+    );
+    assert!(!peripheral.is_powered().await.unwrap());
+    assert!(peripheral.start_advertising("sim", &[]).await.is_err());
+}
+
+#[tokio::test]
+async fn it_removes_services() {
+    let (service, _, _) = service();
+    let battery = Service::new(Uuid::from_sdp_short_uuid(0x180Fu16), true, vec![]);
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+    peripheral.add_service(&battery).unwrap();
+
+    let central = peripheral.central();
+    let services = central.discover_services().await.unwrap();
+    assert_eq!(services.len(), 2);
+    let handle = services[0].characteristics[0].handle;
+
+    peripheral
+        .remove_service(&Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .unwrap();
+    let services = central.discover_services().await.unwrap();
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0].uuid, Uuid::from_sdp_short_uuid(0x180Fu16));
+    assert!(central.read(handle, 0).await.is_err());
+    assert!(peripheral
+        .remove_service(&Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .is_err());
+}
+
+#[tokio::test]
+async fn it_indicates_service_changes() {
+    let (service, _, _) = service();
+    let battery_uuid = Uuid::from_sdp_short_uuid(0x180Fu16);
+    let battery = Service::new(battery_uuid, true, vec![]);
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    let central = peripheral.central();
+    let mut changes = central.service_changes();
+
+    // Nothing is indicated before the database is visible to centrals.
+    start(&peripheral, &service).await;
+    peripheral.add_service(&battery).unwrap();
+    assert_eq!(changes.next().await, Some(5..=5));
+
+    peripheral
+        .remove_service(&Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .unwrap();
+    assert_eq!(changes.next().await, Some(1..=4));
+}
+
+#[tokio::test]
+async fn it_hashes_the_database() {
+    let (same_service, _, _) = service();
+    let (service, _, _) = service();
+    let battery_uuid = Uuid::from_sdp_short_uuid(0x180Fu16);
+    let battery = Service::new(battery_uuid, true, vec![]);
+
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+    let hash = peripheral.database_hash();
+
+    let restarted = sim::Peripheral::new().await.unwrap();
+    start(&restarted, &same_service).await;
+    assert_eq!(restarted.database_hash(), hash);
+
+    peripheral.add_service(&battery).unwrap();
+    assert_ne!(peripheral.database_hash(), hash);
+    peripheral.remove_service(&battery_uuid).unwrap();
+    assert_eq!(peripheral.database_hash(), hash);
+    assert_eq!(hash.to_string().len(), 32);
+}
+
+#[tokio::test]
+async fn it_keeps_attributes_with_the_same_uuid_in_order() {
+    let (sender, _receiver) = channel(1);
+    let report = |properties, reference: [u8; 2]| {
+        Characteristic::new(
+            Uuid::from_sdp_short_uuid(0x2A4Du16),
+            properties,
+            None,
+            vec![Descriptor::new(
+                Uuid::from_sdp_short_uuid(0x2908u16),
+                descriptor::Properties::new(None, None),
+                Some(reference.to_vec()),
+            )],
+        )
+    };
+    let input = characteristic::Properties::new(
+        Some(characteristic::Read(characteristic::Secure::Insecure(
+            sender.clone(),
+        ))),
+        None,
+        Some(sender.clone()),
+        None,
+    );
+    let output = characteristic::Properties::new(
+        Some(characteristic::Read(characteristic::Secure::Insecure(
+            sender.clone(),
+        ))),
+        Some(characteristic::Write::WithResponse(
+            characteristic::Secure::Insecure(sender),
+        )),
+        None,
+        None,
+    );
+    let hid = Service::new(
+        Uuid::from_sdp_short_uuid(0x1812u16),
+        true,
+        vec![report(input, [1, 1]), report(output, [1, 2])],
+    );
+
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &hid).await;
+    let services = peripheral.central().discover_services().await.unwrap();
+    let characteristics = &services[0].characteristics;
+    assert_eq!(characteristics.len(), 2);
+    assert!(characteristics[0].properties.notify);
+    assert!(characteristics[1].properties.write);
+    assert!(characteristics[0].handle < characteristics[1].handle);
+    assert_eq!(characteristics[0].descriptors.len(), 1);
+    assert_eq!(characteristics[1].descriptors.len(), 1);
+}
+
+#[tokio::test]
+async fn it_serves_fixed_values() {
+    let service = Service::builder(Uuid::from_sdp_short_uuid(0x180Au16))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(0x2A26u16), |c| {
+            c.value("1.0.2")
+                .descriptor(Uuid::from_sdp_short_uuid(0x2901u16), |d| {
+                    d.value("Firmware")
+                })
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let services = central.discover_services().await.unwrap();
+    let characteristic = &services[0].characteristics[0];
+    assert!(characteristic.properties.read);
+    assert_eq!(
+        central.read(characteristic.handle, 0).await.unwrap(),
+        Response::Success(b"1.0.2".to_vec())
+    );
+    assert_eq!(
+        central.read(characteristic.handle, 5).await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(
+        central.read(characteristic.handle, 6).await.unwrap(),
+        Response::InvalidOffset
+    );
+    let descriptor = &characteristic.descriptors[0];
+    assert!(descriptor.properties.read);
+    assert_eq!(
+        central.read(descriptor.handle, 4).await.unwrap(),
+        Response::Success(b"ware".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn it_manages_characteristic_values() {
+    let level = CharacteristicHandle::with_validator(vec![100], |value| match value {
+        [level] if *level <= 100 => Ok(()),
+        [_] => Err(Response::UnlikelyError),
+        _ => Err(Response::InvalidAttributeLength),
+    });
+    let service = Service::builder(Uuid::from_sdp_short_uuid(0x180Fu16))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(0x2A19u16), |c| {
+            c.managed(&level).read().write().notify()
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let services = central.discover_services().await.unwrap();
+    let handle = services[0].characteristics[0].handle;
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(vec![100])
+    );
+
+    assert_eq!(
+        central.write(handle, 0, vec![42]).await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(level.get_value(), vec![42]);
+    assert_eq!(
+        central.write(handle, 0, vec![101]).await.unwrap(),
+        Response::UnlikelyError
+    );
+    assert_eq!(
+        central.write(handle, 0, vec![1, 2]).await.unwrap(),
+        Response::InvalidAttributeLength
+    );
+    assert_eq!(
+        central.write(handle, 2, vec![1]).await.unwrap(),
+        Response::InvalidOffset
+    );
+    assert_eq!(level.get_value(), vec![42]);
+
+    let mut notifications = central.subscribe(handle).await.unwrap();
+    // Requests are handled in order, so the subscription is in place once the read is answered.
+    central.read(handle, 0).await.unwrap();
+    level.set_value(vec![41]).await;
+    assert_eq!(notifications.next().await, Some(vec![41]));
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(vec![41])
+    );
+
+    central.unsubscribe(handle).await.unwrap();
+    central.read(handle, 0).await.unwrap();
+    level.set_value(vec![40]).await;
+    assert_eq!(notifications.next().await, None);
+}
+
+#[tokio::test]
+async fn it_does_not_wait_for_stalled_subscribers() {
+    let level = CharacteristicHandle::new(vec![100]);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(0x180Fu16))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(0x2A19u16), |c| {
+            c.managed(&level).read().notify()
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let stalled = peripheral.central();
+    let handle = stalled.discover_services().await.unwrap()[0].characteristics[0].handle;
+    let _stalled_notifications = stalled.subscribe(handle).await.unwrap();
+    let central = peripheral.central();
+    let mut notifications = central.subscribe(handle).await.unwrap();
+    central.read(handle, 0).await.unwrap();
+
+    // Far more values than the stalled central's queue holds.
+    for level_value in 0..100u8 {
+        level.set_value(vec![level_value]).await;
+        assert_eq!(notifications.next().await, Some(vec![level_value]));
+    }
+}
+
+struct Counter {
+    count: Mutex<u8>,
+    subscribers: Mutex<Vec<(SubscriptionId, Sender<Vec<u8>>)>>,
+}
+
+#[async_trait]
+impl CharacteristicHandler for Counter {
+    async fn on_read(&self, ctx: &RequestContext, offset: u16) -> Result<Vec<u8>, AttError> {
+        assert_eq!(ctx.uuid, Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID));
+        if offset > 0 {
+            return Err(AttError::InvalidOffset);
+        }
+        Ok(vec![*self.count.lock().unwrap()])
+    }
+
+    async fn on_write(
+        &self,
+        _ctx: &RequestContext,
+        _offset: u16,
+        data: Vec<u8>,
+        kind: WriteKind,
+    ) -> Result<(), AttError> {
+        assert_eq!(kind, WriteKind::Command);
+        match data[..] {
+            [count] => *self.count.lock().unwrap() = count,
+            _ => return Err(AttError::InvalidAttributeLength),
+        }
+        let subscribers = self.subscribers.lock().unwrap().clone();
+        for (_, mut subscriber) in subscribers {
+            subscriber.send(data.clone()).await.ok();
+        }
+        Ok(())
+    }
+
+    async fn on_subscribe(&self, _ctx: &RequestContext, subscription: NotifySubscribe) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push((subscription.id, subscription.notification));
+    }
+
+    async fn on_unsubscribe(&self, _ctx: &RequestContext, id: SubscriptionId) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|(subscriber, _)| *subscriber != id);
+    }
+}
+
+struct Description;
+
+#[async_trait]
+impl DescriptorHandler for Description {
+    async fn on_read(&self, _ctx: &RequestContext, _offset: u16) -> Result<Vec<u8>, AttError> {
+        Ok(b"Counter".to_vec())
+    }
+}
+
+#[tokio::test]
+async fn it_calls_handlers() {
+    let counter = Counter {
+        count: Mutex::new(0),
+        subscribers: Mutex::new(vec![]),
+    };
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.handler(counter)
+                .read()
+                .write_without_response()
+                .notify()
+                .descriptor(Uuid::from_sdp_short_uuid(DESCRIPTOR_UUID), |d| {
+                    d.handler(Description).read()
+                })
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let services = central.discover_services().await.unwrap();
+    let characteristic = &services[0].characteristics[0];
+    let handle = characteristic.handle;
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(vec![0])
+    );
+    assert_eq!(
+        central.read(handle, 1).await.unwrap(),
+        Response::InvalidOffset
+    );
+    assert_eq!(
+        central
+            .read(characteristic.descriptors[0].handle, 0)
+            .await
+            .unwrap(),
+        Response::Success(b"Counter".to_vec())
+    );
+
+    let mut notifications = central.subscribe(handle).await.unwrap();
+    central
+        .write_without_response(handle, vec![7])
+        .await
+        .unwrap();
+    assert_eq!(notifications.next().await, Some(vec![7]));
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(vec![7])
+    );
+}
+
+/// Answers reads with the central's address and MTU.
+struct Greeter;
+
+#[async_trait]
+impl CharacteristicHandler for Greeter {
+    async fn on_read(&self, ctx: &RequestContext, _offset: u16) -> Result<Vec<u8>, AttError> {
+        assert_eq!(ctx.link, Some(Link::Le));
+        let device = ctx.device.as_ref().ok_or(AttError::Unlikely)?;
+        let mtu = ctx.mtu.ok_or(AttError::Unlikely)?;
+        Ok(format!("{} {}", device, mtu).into_bytes())
+    }
+}
+
+#[tokio::test]
+async fn it_tells_handlers_about_the_central() {
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.handler(Greeter).read()
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let first = peripheral.central();
+    let second = peripheral.central();
+    assert_ne!(first.address(), second.address());
+    second.set_mtu(247);
+
+    let handle = first.discover_services().await.unwrap()[0].characteristics[0].handle;
+    assert_eq!(
+        first.read(handle, 0).await.unwrap(),
+        Response::Success(format!("{} 23", first.address()).into_bytes())
+    );
+    assert_eq!(
+        second.read(handle, 0).await.unwrap(),
+        Response::Success(format!("{} 247", second.address()).into_bytes())
+    );
+}
+
+#[tokio::test]
+async fn it_executes_reliable_writes() {
+    let name = CharacteristicHandle::with_validator(b"name".to_vec(), |value| {
+        if value.len() <= 8 {
+            Ok(())
+        } else {
+            Err(Response::InvalidAttributeLength)
+        }
+    });
+    let secret = CharacteristicHandle::new(vec![]);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.managed(&name).read().write()
+        })
+        .characteristic(Uuid::from_sdp_short_uuid(0x2A3Eu16), |c| {
+            c.managed(&secret).write_with(SecurityLevel::Encrypted)
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let services = central.discover_services().await.unwrap();
+    let handle = services[0].characteristics[0].handle;
+    let secret_handle = services[0].characteristics[1].handle;
+
+    central.prepare_write(handle, 0, "hel").unwrap();
+    central.prepare_write(handle, 3, "lo").unwrap();
+    assert_eq!(
+        central.execute_write(true).await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(name.get_value(), b"hello".to_vec());
+
+    central.prepare_write(handle, 0, "hello").unwrap();
+    central.prepare_write(handle, 5, " world").unwrap();
+    assert_eq!(
+        central.execute_write(true).await.unwrap(),
+        Response::InvalidAttributeLength
+    );
+    assert_eq!(name.get_value(), b"hello".to_vec());
+
+    central.prepare_write(handle, 0, "bye").unwrap();
+    assert_eq!(
+        central.execute_write(false).await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(name.get_value(), b"hello".to_vec());
+
+    // The second part needs an encrypted link, so neither is written.
+    central.prepare_write(handle, 0, "bye").unwrap();
+    central.prepare_write(secret_handle, 0, "key").unwrap();
+    assert_eq!(
+        central.execute_write(true).await.unwrap(),
+        Response::InsufficientEncryption
+    );
+    assert_eq!(name.get_value(), b"hello".to_vec());
+    assert_eq!(secret.get_value(), vec![]);
+
+    // The application refuses the second part after the first is written, which stays written.
+    central.set_security(SecurityLevel::Encrypted);
+    central.prepare_write(secret_handle, 0, "key").unwrap();
+    central.prepare_write(handle, 0, "far too long").unwrap();
+    assert_eq!(
+        central.execute_write(true).await.unwrap(),
+        Response::InvalidAttributeLength
+    );
+    assert_eq!(secret.get_value(), b"key".to_vec());
+    assert_eq!(name.get_value(), b"hello".to_vec());
+}
+
+/// Only ever reads and writes its whole value.
+struct WholeValue(Mutex<Vec<u8>>);
+
+#[async_trait]
+impl CharacteristicHandler for WholeValue {
+    async fn on_read(&self, _ctx: &RequestContext, offset: u16) -> Result<Vec<u8>, AttError> {
+        assert_eq!(offset, 0);
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    async fn on_write(
+        &self,
+        _ctx: &RequestContext,
+        offset: u16,
+        data: Vec<u8>,
+        _kind: WriteKind,
+    ) -> Result<(), AttError> {
+        assert_eq!(offset, 0);
+        *self.0.lock().unwrap() = data;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn it_handles_offsets_of_long_values() {
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.handler(WholeValue(Mutex::new(vec![])))
+                .read()
+                .write()
+                .long_values()
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let handle = central.discover_services().await.unwrap()[0].characteristics[0].handle;
+
+    central.write(handle, 0, "hello").await.unwrap();
+    assert_eq!(
+        central.read(handle, 2).await.unwrap(),
+        Response::Success(b"llo".to_vec())
+    );
+    assert_eq!(
+        central.read(handle, 5).await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(
+        central.read(handle, 6).await.unwrap(),
+        Response::InvalidOffset
+    );
+
+    assert_eq!(
+        central.write(handle, 5, " world").await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(b"hello world".to_vec())
+    );
+    assert_eq!(
+        central.write(handle, 12, "!").await.unwrap(),
+        Response::InvalidOffset
+    );
+
+    central.prepare_write(handle, 0, "good").unwrap();
+    central.prepare_write(handle, 4, "bye").unwrap();
+    assert_eq!(
+        central.execute_write(true).await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(b"goodbye".to_vec())
+    );
+
+    central.prepare_write(handle, 0, vec![0; 500]).unwrap();
+    central.prepare_write(handle, 500, vec![0; 13]).unwrap();
+    assert_eq!(
+        central.execute_write(true).await.unwrap(),
+        Response::InvalidAttributeLength
+    );
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(b"goodbye".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn it_splices_long_writes_one_at_a_time() {
+    let (sender, receiver) = channel(1);
+    let (write_only_sender, write_only_receiver) = channel(1);
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.sender(sender).read().write().long_values()
+        })
+        .characteristic(Uuid::from_sdp_short_uuid(0x2A3Eu16), |c| {
+            c.sender(write_only_sender).write().long_values()
+        })
+        .build()
+        .unwrap();
+    let value = handle_events(receiver, "0123456789");
+    let write_only_value = handle_events(write_only_receiver, "");
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let first = peripheral.central();
+    let second = peripheral.central();
+    let services = first.discover_services().await.unwrap();
+    let handle = services[0].characteristics[0].handle;
+    let write_only_handle = services[0].characteristics[1].handle;
+
+    // Spliced into the value read before either was written, the second part would start past
+    // its end.
+    let (first_response, second_response) =
+        futures::join!(first.write(handle, 10, "ab"), second.write(handle, 12, "c"));
+    assert_eq!(first_response.unwrap(), Response::Success(vec![]));
+    assert_eq!(second_response.unwrap(), Response::Success(vec![]));
+    assert_eq!(*value.lock().unwrap(), b"0123456789abc".to_vec());
+
+    // Whatever followed the written part is dropped.
+    first.write(handle, 2, "xy").await.unwrap();
+    assert_eq!(*value.lock().unwrap(), b"01xy".to_vec());
+
+    first.write(write_only_handle, 0, "hello").await.unwrap();
+    assert_eq!(
+        first.write(write_only_handle, 5, " world").await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(*write_only_value.lock().unwrap(), b"hello world".to_vec());
+    assert_eq!(
+        second.write(write_only_handle, 12, "!").await.unwrap(),
+        Response::InvalidOffset
+    );
+}
+
+/// Only lets one central read and write its value.
+struct Gatekeeper {
+    device: String,
+    value: Mutex<Vec<u8>>,
+}
+
+#[async_trait]
+impl CharacteristicHandler for Gatekeeper {
+    async fn on_read(&self, _ctx: &RequestContext, _offset: u16) -> Result<Vec<u8>, AttError> {
+        Ok(self.value.lock().unwrap().clone())
+    }
+
+    async fn on_write(
+        &self,
+        _ctx: &RequestContext,
+        _offset: u16,
+        data: Vec<u8>,
+        _kind: WriteKind,
+    ) -> Result<(), AttError> {
+        *self.value.lock().unwrap() = data;
+        Ok(())
+    }
+
+    async fn on_authorize(&self, ctx: &RequestContext, _access: Access) -> Authorization {
+        if ctx.device.as_ref() == Some(&self.device) {
+            Authorization::Allow
+        } else {
+            Authorization::Deny
+        }
+    }
+}
+
+#[tokio::test]
+async fn it_asks_for_authorization() {
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    let trusted = peripheral.central();
+    let stranger = peripheral.central();
+    let gatekeeper = Gatekeeper {
+        device: trusted.address().to_string(),
+        value: Mutex::new(b"secret".to_vec()),
+    };
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.handler(gatekeeper).read().write().authorize()
+        })
+        .build()
+        .unwrap();
+    start(&peripheral, &service).await;
+
+    let handle = trusted.discover_services().await.unwrap()[0].characteristics[0].handle;
+    assert_eq!(
+        stranger.read(handle, 0).await.unwrap(),
+        Response::InsufficientAuthorization
+    );
+    assert_eq!(
+        stranger.write(handle, 0, "public").await.unwrap(),
+        Response::InsufficientAuthorization
+    );
+    assert_eq!(
+        trusted.read(handle, 0).await.unwrap(),
+        Response::Success(b"secret".to_vec())
+    );
+    assert_eq!(
+        trusted.write(handle, 0, "still secret").await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert_eq!(
+        trusted.read(handle, 0).await.unwrap(),
+        Response::Success(b"still secret".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn it_requires_security_levels() {
+    let (sender, receiver) = channel(1);
+    handle_events(receiver, "value");
+    let service = Service::builder(Uuid::from_sdp_short_uuid(SERVICE_UUID))
+        .primary()
+        .characteristic(Uuid::from_sdp_short_uuid(CHARACTERISTIC_UUID), |c| {
+            c.sender(sender)
+                .read_with(SecurityLevel::Encrypted)
+                .write_with(SecurityLevel::Authenticated)
+                .notify_with(SecurityLevel::SecureConnections)
+        })
+        .build()
+        .unwrap();
+    let peripheral = sim::Peripheral::new().await.unwrap();
+    start(&peripheral, &service).await;
+
+    let central = peripheral.central();
+    let handle = central.discover_services().await.unwrap()[0].characteristics[0].handle;
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::InsufficientEncryption
+    );
+    assert_eq!(
+        central.write(handle, 0, "new").await.unwrap(),
+        Response::InsufficientAuthentication
+    );
+
+    central.set_security(SecurityLevel::Encrypted);
+    assert_eq!(
+        central.read(handle, 0).await.unwrap(),
+        Response::Success(b"value".to_vec())
+    );
+    assert_eq!(
+        central.write(handle, 0, "new").await.unwrap(),
+        Response::InsufficientAuthentication
+    );
+    assert!(central.subscribe(handle).await.is_err());
+
+    central.set_security(SecurityLevel::SecureConnections);
+    assert_eq!(
+        central.write(handle, 0, "new").await.unwrap(),
+        Response::Success(vec![])
+    );
+    assert!(central.subscribe(handle).await.is_ok());
+}